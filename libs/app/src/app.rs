@@ -8,6 +8,11 @@ pub struct State {
     pub commands: Commands,
     pub input: Input,
     pub speaker: Speaker,
+    pub replay_mode: replay::Mode,
+    pub save_path: Option<std::path::PathBuf>,
+    /// Where maps saved in the editor get written to.
+    #[cfg(feature = "editor")]
+    pub edit_path: Option<std::path::PathBuf>,
 }
 
 impl State {
@@ -28,14 +33,110 @@ impl State {
             commands: Commands::default(),
             input: Input::default(),
             speaker: Speaker::default(),
+            replay_mode: replay::Mode::default(),
+            save_path: None,
+            #[cfg(feature = "editor")]
+            edit_path: None,
         }
     }
+
+    /// Resumes from the save at `path`, if there is one, and saves the game
+    /// there when quitting.
+    pub fn with_save_file(
+        mut self,
+        path: std::path::PathBuf,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        match std::fs::read(&path) {
+            Ok(bytes) => {
+                self.game_state = game::State::load_in_world(&bytes, self.game_state.world)?;
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {},
+            Err(err) => return Err(err.into()),
+        }
+
+        self.save_path = Some(path);
+
+        Ok(self)
+    }
+
+    /// Plays on the map in the file at `path`, if there is one, or else the
+    /// built-in one, with the editor open. Maps saved in the editor get
+    /// written to `path`.
+    #[cfg(feature = "editor")]
+    pub fn with_editor_file(
+        mut self,
+        path: std::path::PathBuf,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                let seed = xs::new_seed(&mut self.game_state.rng);
+                self.game_state = game::State::new_editing(seed, &text)?;
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                self.game_state.open_editor();
+            },
+            Err(err) => return Err(err.into()),
+        }
+
+        self.edit_path = Some(path);
+
+        Ok(self)
+    }
+
+    #[cfg(feature = "editor")]
+    fn write_editor_save(&mut self) {
+        let Some(text) = self.game_state.take_editor_save() else { return };
+
+        match &self.edit_path {
+            Some(path) => {
+                if let Err(err) = std::fs::write(path, text) {
+                    features::log(&format!("Could not save the map to {}: {err}", path.display()));
+                }
+            },
+            None => features::log("Nowhere to save the map to. Pass --edit <file> to pick a file."),
+        }
+    }
+
+    /// Like `new`, but also writes the seed and every frame's input to `writer`.
+    pub fn recording(
+        params: StateParams,
+        writer: Box<dyn std::io::Write>,
+    ) -> std::io::Result<Self> {
+        let recorder = replay::Recorder::new(writer, params.0)?;
+
+        let mut state = Self::new(params);
+        state.replay_mode = replay::Mode::Recording(recorder);
+
+        Ok(state)
+    }
+
+    /// Like `new`, but uses the seed and inputs from `replay` instead of the
+    /// passed seed and the player's input, until the replay runs out.
+    pub fn replaying(
+        (_, logger, error_logger): StateParams,
+        replay: replay::Replay,
+    ) -> Self {
+        let mut state = Self::new((replay.seed, logger, error_logger));
+        state.replay_mode = replay::Mode::Replaying(replay);
+
+        state
+    }
 }
 
 impl platform_types::State for State {
     fn frame(&mut self) -> (&[platform_types::Command], &[SFX]) {
         self.commands.clear();
         self.speaker.clear();
+
+        if let replay::Mode::Replaying(ref mut replay) = self.replay_mode {
+            if let Some(input) = replay.next_input() {
+                self.input = input;
+            } else {
+                features::log("Replay finished. Handing control back to the player.");
+                self.replay_mode = replay::Mode::Live;
+            }
+        }
+
         update_and_render(
             &mut self.commands,
             &mut self.game_state,
@@ -43,12 +144,38 @@ impl platform_types::State for State {
             &mut self.speaker,
         );
 
+        #[cfg(feature = "editor")]
+        self.write_editor_save();
+
+        match self.replay_mode {
+            replay::Mode::Live => {},
+            replay::Mode::Recording(ref mut recorder) => {
+                let game_state = &self.game_state;
+                if let Err(err) = recorder.record(self.input, || game_state.hash()) {
+                    features::log(&format!("Stopping recording due to: {err}"));
+                    self.replay_mode = replay::Mode::Live;
+                }
+            },
+            replay::Mode::Replaying(ref mut replay) => {
+                let game_state = &self.game_state;
+                if let Err(divergence) = replay.check(|| game_state.hash()) {
+                    // If we kept going, then anything we saw after this point
+                    // would be misleading.
+                    panic!("{divergence}");
+                }
+            },
+        }
+
         self.input.previous_gamepad = self.input.gamepad;
 
         (self.commands.slice(), self.speaker.slice())
     }
 
     fn press(&mut self, button: Button) {
+        if let replay::Mode::Replaying(_) = self.replay_mode {
+            return
+        }
+
         if self.input.previous_gamepad.contains(button) {
             //This is meant to pass along the key repeat, if any.
             //Not sure if rewriting history is the best way to do this.
@@ -59,8 +186,20 @@ impl platform_types::State for State {
     }
 
     fn release(&mut self, button: Button) {
+        if let replay::Mode::Replaying(_) = self.replay_mode {
+            return
+        }
+
         self.input.gamepad.remove(button);
     }
+
+    fn quit(&mut self) {
+        if let Some(path) = &self.save_path {
+            if let Err(err) = std::fs::write(path, self.game_state.save()) {
+                features::log(&format!("Could not save to {}: {err}", path.display()));
+            }
+        }
+    }
 }
 
 fn update(state: &mut game::State, input: Input, speaker: &mut Speaker) {
@@ -84,6 +223,7 @@ fn render(commands: &mut Commands, state: &game::State) {
         tiles,
         text_boxes,
         message_segments,
+        overview,
         hud,
     } = state.render_info();
 
@@ -113,6 +253,10 @@ fn render(commands: &mut Commands, state: &game::State) {
         );
     }
 
+    for cell in overview {
+        commands.fill_rect(cell.rect, cell.colour);
+    }
+
     for print in hud.prints {
         commands.print(
             &print.text,
@@ -133,3 +277,375 @@ fn update_and_render(
     update(state, input, speaker);
     render(commands, state);
 }
+
+pub mod replay {
+    //! Recording and playback of the input given to an `app::State`.
+    //!
+    //! The game is deterministic given a seed and the input for each frame,
+    //! so a recording is just those, along with a hash of the game state every
+    //! `HASH_INTERVAL` frames, so that playback can tell if it ever stops
+    //! matching what happened when the recording was made.
+    //!
+    //! The format is as follows, with multi-byte numbers in little endian:
+    //! * The 4 bytes of `MAGIC`
+    //! * A version byte, currently always `VERSION`
+    //! * The 16 byte seed
+    //! * Any number of entries, each starting with a tag byte:
+    //!     * `INPUT_TAG`, then a `gamepad` byte, a `previous_gamepad` byte,
+    //!       and a count byte: That input was used for that many frames in a row.
+    //!     * `HASH_TAG`, then 8 bytes: The hash of the game state after all
+    //!       of the frames so far.
+    use platform_types::{Button, Input};
+    use xs::Seed;
+    use std::io::{self, Write};
+
+    pub const MAGIC: [u8; 4] = *b"TLCK";
+    pub const VERSION: u8 = 1;
+
+    const INPUT_TAG: u8 = 0;
+    const HASH_TAG: u8 = 1;
+
+    pub const HASH_INTERVAL: u32 = 60;
+
+    type RunLength = u8;
+
+    #[derive(Default)]
+    pub enum Mode {
+        #[default]
+        Live,
+        Recording(Recorder<Box<dyn Write>>),
+        Replaying(Replay),
+    }
+
+    pub struct Recorder<W: Write> {
+        writer: W,
+        run: Option<(Input, RunLength)>,
+        frames_since_hash: u32,
+    }
+
+    impl <W: Write> Recorder<W> {
+        pub fn new(mut writer: W, seed: Seed) -> io::Result<Self> {
+            writer.write_all(&MAGIC)?;
+            writer.write_all(&[VERSION])?;
+            writer.write_all(&seed)?;
+
+            Ok(Self {
+                writer,
+                run: None,
+                frames_since_hash: 0,
+            })
+        }
+
+        /// Records that `input` was used for a frame. `hash` is only called
+        /// on the frames where a hash gets written out.
+        pub fn record(&mut self, input: Input, hash: impl FnOnce() -> u64) -> io::Result<()> {
+            match self.run {
+                Some((run_input, ref mut count))
+                if run_input == input
+                && *count < RunLength::MAX => {
+                    *count += 1;
+                },
+                _ => {
+                    self.write_run()?;
+                    self.run = Some((input, 1));
+                }
+            }
+
+            self.frames_since_hash += 1;
+            if self.frames_since_hash >= HASH_INTERVAL {
+                self.frames_since_hash = 0;
+
+                self.write_run()?;
+                self.writer.write_all(&[HASH_TAG])?;
+                self.writer.write_all(&hash().to_le_bytes())?;
+                // Flush regularly so a crash loses at most a second or so.
+                self.writer.flush()?;
+            }
+
+            Ok(())
+        }
+
+        fn write_run(&mut self) -> io::Result<()> {
+            if let Some((input, count)) = self.run.take() {
+                self.writer.write_all(&[
+                    INPUT_TAG,
+                    input.gamepad.bits(),
+                    input.previous_gamepad.bits(),
+                    count,
+                ])?;
+            }
+
+            Ok(())
+        }
+    }
+
+    impl <W: Write> Drop for Recorder<W> {
+        fn drop(&mut self) {
+            // There's nowhere to report errors to at this point.
+            let _ = self.write_run();
+            let _ = self.writer.flush();
+        }
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    enum Entry {
+        Input(Input, RunLength),
+        Hash(u64),
+    }
+
+    #[derive(Debug)]
+    pub enum Error {
+        BadMagic,
+        UnsupportedVersion(u8),
+        Truncated,
+        UnknownTag(u8),
+    }
+
+    impl core::fmt::Display for Error {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match self {
+                Error::BadMagic => write!(f, "Not a recording: the magic bytes did not match"),
+                Error::UnsupportedVersion(v) => write!(f, "Unsupported recording version {v}. Expected {VERSION}"),
+                Error::Truncated => write!(f, "The recording ended partway through an entry"),
+                Error::UnknownTag(tag) => write!(f, "Unknown entry tag {tag}"),
+            }
+        }
+    }
+
+    impl std::error::Error for Error {}
+
+    #[derive(Debug)]
+    pub struct Divergence {
+        pub frame: u64,
+        pub expected: u64,
+        pub actual: u64,
+    }
+
+    impl core::fmt::Display for Divergence {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(
+                f,
+                "Replay diverged from the recording on frame {}! Expected state hash {:016x}, got {:016x}",
+                self.frame,
+                self.expected,
+                self.actual,
+            )
+        }
+    }
+
+    pub struct Replay {
+        pub seed: Seed,
+        entries: Vec<Entry>,
+        index: usize,
+        current: Input,
+        remaining: RunLength,
+        frame: u64,
+    }
+
+    impl Replay {
+        pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+            let Some(rest) = bytes.strip_prefix(&MAGIC) else {
+                return Err(Error::BadMagic)
+            };
+
+            let (&version, mut rest) = rest.split_first().ok_or(Error::Truncated)?;
+            if version != VERSION {
+                return Err(Error::UnsupportedVersion(version))
+            }
+
+            if rest.len() < 16 {
+                return Err(Error::Truncated)
+            }
+            let (seed_bytes, entry_bytes) = rest.split_at(16);
+            let mut seed = Seed::default();
+            seed.copy_from_slice(seed_bytes);
+            rest = entry_bytes;
+
+            let mut entries = Vec::with_capacity(rest.len() / 4);
+
+            while let Some((&tag, after_tag)) = rest.split_first() {
+                match tag {
+                    INPUT_TAG => {
+                        let &[gamepad, previous_gamepad, count, ..] = after_tag else {
+                            return Err(Error::Truncated)
+                        };
+                        entries.push(Entry::Input(
+                            Input {
+                                gamepad: Button::from_bits(gamepad),
+                                previous_gamepad: Button::from_bits(previous_gamepad),
+                            },
+                            count,
+                        ));
+                        rest = &after_tag[3..];
+                    },
+                    HASH_TAG => {
+                        if after_tag.len() < 8 {
+                            return Err(Error::Truncated)
+                        }
+                        let (hash_bytes, after_hash) = after_tag.split_at(8);
+                        let mut hash = [0; 8];
+                        hash.copy_from_slice(hash_bytes);
+                        entries.push(Entry::Hash(u64::from_le_bytes(hash)));
+                        rest = after_hash;
+                    },
+                    _ => return Err(Error::UnknownTag(tag)),
+                }
+            }
+
+            Ok(Self {
+                seed,
+                entries,
+                index: 0,
+                current: Input::default(),
+                remaining: 0,
+                frame: 0,
+            })
+        }
+
+        /// Returns the input to use for the next frame, or `None` if the
+        /// recording has run out.
+        pub fn next_input(&mut self) -> Option<Input> {
+            while self.remaining == 0 {
+                match self.entries.get(self.index) {
+                    Some(&Entry::Input(input, count)) => {
+                        self.current = input;
+                        self.remaining = count;
+                    },
+                    // A hash we skipped over could not have been checked
+                    // anyway, so ignore it.
+                    Some(Entry::Hash(_)) => {},
+                    None => return None,
+                }
+                self.index += 1;
+            }
+
+            self.remaining -= 1;
+            self.frame += 1;
+
+            Some(self.current)
+        }
+
+        /// To be called after each frame, to confirm that the state matches
+        /// the recording. `hash` is only called on the frames that have a
+        /// recorded hash.
+        pub fn check(&mut self, hash: impl FnOnce() -> u64) -> Result<(), Divergence> {
+            if self.remaining != 0 {
+                return Ok(())
+            }
+
+            if let Some(&Entry::Hash(expected)) = self.entries.get(self.index) {
+                self.index += 1;
+
+                let actual = hash();
+                if actual != expected {
+                    return Err(Divergence {
+                        frame: self.frame,
+                        expected,
+                        actual,
+                    })
+                }
+            }
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod replay_works {
+    use super::*;
+    use platform_types::State as _;
+    use std::{cell::RefCell, rc::Rc};
+
+    const SEED: xs::Seed = [42; 16];
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Wanders around, so more than just the mobs' movement gets recorded.
+    fn record_example() -> (Vec<u8>, u64) {
+        let buffer = SharedBuffer::default();
+
+        let mut state = State::recording(
+            (SEED, None, None),
+            Box::new(buffer.clone()),
+        ).unwrap();
+
+        for i in 0..1000 {
+            match i % 40 {
+                0 => state.press(Button::LEFT),
+                10 => state.release(Button::LEFT),
+                20 => state.press(Button::DOWN),
+                30 => state.release(Button::DOWN),
+                _ => {},
+            }
+
+            state.frame();
+        }
+
+        let hash = state.game_state.hash();
+
+        // Make sure everything gets written.
+        drop(state);
+
+        let bytes = buffer.0.borrow().clone();
+
+        (bytes, hash)
+    }
+
+    /// Returns the hash after the last replayed frame.
+    fn replay_all(bytes: &[u8]) -> u64 {
+        let replay = replay::Replay::parse(bytes).unwrap();
+
+        let mut state = State::replaying(([0; 16], None, None), replay);
+
+        let mut hash = state.game_state.hash();
+        while let replay::Mode::Replaying(_) = state.replay_mode {
+            state.frame();
+
+            // The frame that notices the replay has run out runs with live
+            // input, so skip that one.
+            if let replay::Mode::Replaying(_) = state.replay_mode {
+                hash = state.game_state.hash();
+            }
+        }
+
+        hash
+    }
+
+    #[test]
+    fn on_this_example_recording() {
+        let (bytes, expected) = record_example();
+
+        assert_eq!(replay_all(&bytes), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "diverged")]
+    fn when_the_seed_is_tampered_with() {
+        let (mut bytes, _) = record_example();
+
+        let seed_index = replay::MAGIC.len() + 1;
+        bytes[seed_index] ^= 0xFF;
+
+        replay_all(&bytes);
+    }
+
+    #[test]
+    fn parse_rejects_the_wrong_magic() {
+        assert!(matches!(
+            replay::Replay::parse(b"nope"),
+            Err(replay::Error::BadMagic)
+        ));
+    }
+}
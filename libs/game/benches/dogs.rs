@@ -0,0 +1,42 @@
+//! Times frames on the built-in map, once the pack of dogs there has started
+//! running between landmarks. Run with `cargo bench -p game`.
+
+use game::{SeekState, State};
+use platform_types::{Input, Speaker};
+use std::time::{Duration, Instant};
+
+const WARM_UP_FRAMES: u32 = 120;
+const FRAMES: u32 = 1000;
+
+fn main() {
+    let mut state = State::new([42; 16]);
+    let mut speaker = Speaker::default();
+
+    // The dogs start out sniffing around, so give them a while to get going.
+    for _ in 0..WARM_UP_FRAMES {
+        state.frame(Input::default(), &mut speaker);
+    }
+
+    let mut times = Vec::with_capacity(FRAMES as usize);
+    let mut active = 0;
+
+    for _ in 0..FRAMES {
+        let start = Instant::now();
+        state.frame(Input::default(), &mut speaker);
+        times.push(start.elapsed());
+
+        active += state.mob_states.iter()
+            .filter(|mob_state| matches!(mob_state.seek, SeekState::MovingTowards(..)))
+            .count();
+    }
+
+    times.sort_unstable();
+
+    let total: Duration = times.iter().sum();
+
+    println!("{FRAMES} frames, with {:.1} dogs running on average", active as f64 / f64::from(FRAMES));
+    println!("mean:   {:?}", total / FRAMES);
+    println!("median: {:?}", times[times.len() / 2]);
+    println!("99%:    {:?}", times[times.len() * 99 / 100]);
+    println!("max:    {:?}", times[times.len() - 1]);
+}
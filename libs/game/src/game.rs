@@ -1,13 +1,16 @@
-use maps::{DogIndex};
-use models::{X, Y, W, H, Rect, tile, TileKind};
+use maps::MapIndex;
+use maps::locks::{self, ButtonIndex, LockId};
+use maps::timeline::{self, TimelineIndex};
+use maps::dialogue::{self, ChoiceIndex, Flag, LoopCount, NodeIndex, SpeakerIndex};
+use models::{X, Y, W, H, Rect, tile, properties::{self, Depth}, TileKind, FrameCount};
 pub use models::xy;
-use platform_types::{Button, Input, Speaker, SFX, unscaled};
+use platform_types::{Button, Input, Speaker, SFX, PaletteIndex, command, unscaled};
 use xs::{Xs, Seed};
 
 
 use std::collections::HashMap;
 
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Hash)]
 pub struct Entity {
     pub kind: TileKind,
     pub x: X,
@@ -23,8 +26,16 @@ pub struct Tile {
 
 type Map = &'static maps::Map;
 
-fn xy_to_i(map: Map, x: X, y: Y) -> usize {
-    y.usize() * map.width.usize() + x.usize()
+const PLAYER_KIND: TileKind = 9;
+
+fn in_bounds(map: Map, x: X, y: Y) -> bool {
+    x.usize() < map.width.usize()
+    && y.usize() < map.height.usize()
+}
+
+/// The index of `x`, `y` in the map's tiles, if it is on the map.
+fn xy_to_i(map: Map, x: X, y: Y) -> Option<usize> {
+    in_bounds(map, x, y).then(|| y.usize() * map.width.usize() + x.usize())
 }
 
 fn i_to_xy(map: Map, index: usize) -> (X, Y) {
@@ -34,175 +45,224 @@ fn i_to_xy(map: Map, index: usize) -> (X, Y) {
     )
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, Hash)]
 pub enum Screen {
     #[default]
     Gameplay,
     Congraturation,
+    Journal { scroll: JournalIndex },
+    /// A small picture of the whole map, scrolled so that `x`, `y` is at the
+    /// top left.
+    Overview { x: X, y: Y, show_visited: bool },
+    #[cfg(feature = "editor")]
+    Editor,
 }
 
-macro_rules! entities_def {
-    ($($mob: ident)+ [ $($dog_mob: ident)+ ]) => {
-        #[derive(Default)]
-        pub struct Entities {
-            pub dynamic: HashMap<usize, Entity>,
-            $(pub $mob: Entity,)+
-            $(pub $dog_mob: Entity,)+
-        }
+/// The dynamic entities on one tile, with at most one at each depth.
+#[derive(Clone, Default, Hash)]
+pub struct Stack([Option<Entity>; properties::DEPTH_COUNT]);
 
-        const MOB_COUNT: usize = {
-            let mut count = 0;
+impl Stack {
+    fn get(&self, depth: Depth) -> Option<&Entity> {
+        self.0[depth.index()].as_ref()
+    }
 
-            $({
-                // Use the repetition for something, so the += runs
-                let $mob = 1;
-                count += $mob;
-            })+
+    /// The one that gets drawn last, and bumped into first.
+    fn top(&self) -> Option<(Depth, &Entity)> {
+        Depth::ALL.into_iter()
+            .rev()
+            .find_map(|depth| Some((depth, self.get(depth)?)))
+    }
 
-            $({
-                // Use the repetition for something, so the += runs
-                let $dog_mob = 1;
-                count += $dog_mob;
-            })+
+    /// From the bottom up.
+    pub fn iter(&self) -> impl Iterator<Item = &Entity> {
+        self.0.iter().flatten()
+    }
 
-            count
-        };
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(Option::is_none)
+    }
+}
 
-        impl Entities {
-            fn mobs(&self) -> [&Entity; MOB_COUNT] {
-                [$(& self.$mob,)+ $(& self.$dog_mob,)+ ]
-            }
+#[derive(Clone, Default)]
+pub struct Entities {
+    pub dynamic: HashMap<usize, Stack>,
+    pub player: Entity,
+    /// In the same order as the map's mobs.
+    pub mobs: Vec<Entity>,
+    /// The player from the previous loop, if there was one.
+    pub echo: Option<Entity>,
+}
 
-            #[allow(dead_code)]
-            fn mobs_mut(&mut self) -> [&mut Entity; MOB_COUNT] {
-                [$(&mut self.$mob,)+ $(&mut self.$dog_mob,)+ ]
-            }
+fn hash_dynamic<H: core::hash::Hasher>(dynamic: &HashMap<usize, Stack>, state: &mut H) {
+    use core::hash::Hash;
 
-            fn get_mut(&mut self, map: Map, x: X, y: Y) -> Option<&mut Entity> {
-                for mob in [$(&mut self.$mob,)+ $(&mut self.$dog_mob,)+ ] {
-                    if x == mob.x
-                    && y == mob.y {
-                        return Some(mob);
-                    }
-                }
+    // HashMap iteration order is not stable, so sort the keys first.
+    let mut keys: Vec<_> = dynamic.keys().copied().collect();
+    keys.sort_unstable();
 
-                let index = xy_to_i(map, x, y);
+    for key in keys {
+        key.hash(state);
+        dynamic[&key].hash(state);
+    }
+}
 
-                self.dynamic.get_mut(&index)
-            }
+impl core::hash::Hash for Entities {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        hash_dynamic(&self.dynamic, state);
 
-            fn get_dog_mut(&mut self, index: DogIndex) -> Option<&mut Entity> {
-                let mut current = 0;
+        for mob in self.all_mobs() {
+            mob.hash(state);
+        }
+    }
+}
 
-                $({
-                    if current == index {
-                        return Some(&mut self.$dog_mob)
-                    }
+impl Entities {
+    /// The player, then the rest of the mobs, then the echo.
+    fn all_mobs(&self) -> impl Iterator<Item = &Entity> {
+        core::iter::once(&self.player).chain(&self.mobs).chain(&self.echo)
+    }
 
-                    {
-                        #![allow(unused_assignments)] // We don't care if this does an extra assignment in the None case.
-                        current += 1;
-                    }
-                })+
+    /// The player or mob at `x`, `y`, to be moved. The echo is left out, since
+    /// it only ever goes where the recording says, and nothing can push or
+    /// pull it, as it is neither walkable nor pushable.
+    fn mob_mut(&mut self, x: X, y: Y) -> Option<&mut Entity> {
+        core::iter::once(&mut self.player)
+            .chain(&mut self.mobs)
+            .find(|mob| x == mob.x && y == mob.y)
+    }
 
-                None
-            }
+    fn stack(&self, map: Map, x: X, y: Y) -> Option<&Stack> {
+        self.dynamic.get(&xy_to_i(map, x, y)?)
+    }
 
-            fn get_dog(&self, index: DogIndex) -> Option<&Entity> {
-                let mut current = 0;
+    /// Replaces whatever was at that depth on the entity's tile. Does nothing
+    /// if the entity is off the map.
+    fn insert(&mut self, map: Map, depth: Depth, entity: Entity) {
+        let Some(index) = xy_to_i(map, entity.x, entity.y) else { return };
 
-                $({
-                    if current == index {
-                        return Some(&self.$dog_mob)
-                    }
+        self.dynamic.entry(index).or_default().0[depth.index()] = Some(entity);
+    }
 
-                    {
-                        #![allow(unused_assignments)] // We don't care if this does an extra assignment in the None case.
-                        current += 1;
-                    }
-                })+
+    fn remove(&mut self, map: Map, x: X, y: Y, depth: Depth) -> Option<Entity> {
+        let index = xy_to_i(map, x, y)?;
+        let stack = self.dynamic.get_mut(&index)?;
+        let output = stack.0[depth.index()].take();
 
-                None
-            }
+        if stack.is_empty() {
+            self.dynamic.remove(&index);
         }
+
+        output
     }
-}
 
-entities_def! {
-    player
-    turtle
-    crab
-    ghost
-    large_pot
-    panoptikhan
-    zombie
-    [
-        dog0
-        dog1
-        dog2
-        dog3
-        dog4
-        dog5
-        dog6
-        dog7
-        dog8
-        dog9
-        dog10
-        dog11
-        dog12
-        dog13
-        dog14
-        dog15
-        dog16
-        dog17
-        dog18
-        dog19
-        dog20
-        dog21
-    ]
-}
-
-type ButtonIndex = usize;
-type ButtonCount = usize;
+    fn remove_top(&mut self, map: Map, x: X, y: Y) -> Option<(Depth, Entity)> {
+        let (depth, _) = self.stack(map, x, y)?.top()?;
 
-#[derive(Clone, Debug)]
-pub struct PasswordLock<const N: ButtonCount = 4> {
-    // TODO? not really a good reason to do SoA here huh? Switch to regular AoS?
-    xs: [X; N],
-    ys: [Y; N],
-    names: [&'static str; N],
-    open: [bool; N],
-    press_count: ButtonCount,
+        Some((depth, self.remove(map, x, y, depth)?))
+    }
 }
 
-impl <const N: ButtonCount> PasswordLock<N> {
-    fn new(mut buttons: [(X, Y, &'static str); N], rng: &mut Xs) -> Self {
-        xs::shuffle(rng, &mut buttons);
+/// How far along one of a map's locks is. The buttons themselves are in the
+/// map's `locks::Lock`.
+#[derive(Clone, Debug, Default, Hash)]
+pub struct PasswordLock {
+    /// Which of the lock's buttons to press, in order.
+    sequence: Vec<ButtonIndex>,
+    /// Whether each press in the sequence was done at the right time.
+    open: Vec<bool>,
+    press_count: ButtonIndex,
+}
 
-        let mut output = Self {
-            xs: [<_>::default(); N],
-            ys: [<_>::default(); N],
-            names: ["unlabeled button"; N],
-            open: [false; N],
+impl PasswordLock {
+    fn new(lock: &locks::Lock, rng: &mut Xs) -> Self {
+        // Maps are checked to have no more buttons than a `ButtonIndex` can
+        // tell apart, when they are loaded or built.
+        let mut sequence: Vec<ButtonIndex> = (0..=ButtonIndex::MAX).take(lock.buttons.len()).collect();
+        xs::shuffle(rng, &mut sequence);
+        sequence.truncate(lock.length as usize);
+
+        Self {
+            open: vec![false; sequence.len()],
+            sequence,
             press_count: 0,
-        };
-        for i in 0..N {
-            let (x, y, name) = buttons[i];
-            output.xs[i] = x;
-            output.ys[i] = y;
-            output.names[i] = name;
         }
-        output
+    }
+
+    /// Whether every press in the sequence was done at the right time.
+    fn is_open(&self) -> bool {
+        self.open.iter().all(|&b| b)
     }
 
     fn reset(&mut self) {
-        for i in 0..N {
-            self.open[i] = false;
+        for open in &mut self.open {
+            *open = false;
         }
         self.press_count = 0;
     }
 }
 
+/// A fresh set of passwords for each lock on each map in the world.
+fn new_password_locks(world: maps::World, rng: &mut Xs) -> Vec<Vec<PasswordLock>> {
+    world.maps.iter()
+        .map(|map| map.locks.iter().map(|lock| PasswordLock::new(lock, rng)).collect())
+        .collect()
+}
+
+/// How many rows of graves are below `x`, `y`, and how many graves are to the
+/// left of it. These are the directions the rambling story gives, from the
+/// bottom left of the graveyard.
+fn grave_counts(map: Map, x: X, y: Y) -> (usize, usize) {
+    let is_grave = |x: usize, y: usize| matches!(
+        map.tiles.get(y * map.width.usize() + x),
+        Some(&(tile::GRAVE_1 | tile::GRAVE_2))
+    );
+
+    let rows = (y.usize() + 1..map.height.usize())
+        .filter(|&below| is_grave(x.usize(), below))
+        .count();
+    let graves = (0..x.usize())
+        .filter(|&left| is_grave(left, y.usize()))
+        .count();
+
+    (rows, graves)
+}
+
+/// Picks one of the special grave tiles on the map to be the special grave
+/// for this game, the way `PasswordLock::new` picks a password. Only graves
+/// that no other grave shares directions with are picked, so the story always
+/// points to the right one. Falls back to the map's own special grave.
+fn pick_special_grave(map: Map, rng: &mut Xs) -> (X, Y) {
+    // So the story has something to count.
+    const MIN_ROWS: usize = 2;
+    const MIN_GRAVES: usize = 3;
+
+    let graves: Vec<_> = map.tiles.iter()
+        .enumerate()
+        .filter(|&(_, &kind)| matches!(kind, tile::GRAVE_1 | tile::GRAVE_2))
+        .map(|(i, &kind)| {
+            let (x, y) = i_to_xy(map, i);
+            ((x, y), kind, grave_counts(map, x, y))
+        })
+        .collect();
+
+    let spots: Vec<(X, Y)> = graves.iter()
+        .filter(|&&(_, kind, (rows, left))| {
+            kind == tile::SPECIAL_GRAVE
+            && rows >= MIN_ROWS
+            && left >= MIN_GRAVES
+            && graves.iter().filter(|&&(_, _, counts)| counts == (rows, left)).count() == 1
+        })
+        .map(|&(xy, _, _)| xy)
+        .collect();
+
+    if spots.is_empty() {
+        return (map.special_grave_x, map.special_grave_y)
+    }
+
+    spots[xs::range(rng, 0..spots.len() as u32) as usize]
+}
+
 // Plan:
 // Have a short 4 direction password you can type out on switches on the ground to win the game.
 //
@@ -225,12 +285,13 @@ impl <const N: ButtonCount> PasswordLock<N> {
 //    * Walk to edge of the map, but make the edges that don't have anything say a message instead?
 // * Allow moving that gravestone and put something under it (done)
 //    * Either a new map or thing you can read to get more knowledge
-//        * A tiny map with a zombie you can talk to happens to be easy, once we have any multi-map issues worked out
+//        * A tiny map with a zombie you can talk to happens to be easy, once we have any multi-map issues worked out (done)
 //        * Move one of the password answers there, and remove one so you have to derive the last one
 //            * Make the third one say "I forgot my part of the combination!"
 //            * Place the rambly NPC where the last one is
 // * Make a bunch of mobs with different movement patterns
 //     * A ghost that literally just brownian motions around, and passes through anything to do so (done)
+//         * Now it drifts between landmarks instead, pathing through walls to get there (done)
 //         * Make it say something, so we can make it useful later
 //     * A Panoptikhan the floats left and right, moving up and down as it does so (done)
 //         * Panoptikhan name is from https://www.prismaticwasteland.com/blog/no-one-owns-these-monsters
@@ -287,25 +348,49 @@ fn get_effective_tile(map: Map, entities: &Entities, x: X, y: Y) -> Option<TileK
 }
 
 fn get_effective_tile_custom(map: Map, entities: &Entities, x: X, y: Y, flags: TileFlags) -> Option<TileKind> {
-    for mob in entities.mobs() {
-        if x == mob.x
-        && y == mob.y {
-            if flags & NO_MOBS == 0 {
+    if flags & NO_MOBS == 0 {
+        for mob in entities.all_mobs() {
+            if x == mob.x
+            && y == mob.y {
                 return Some(mob.kind);
             }
         }
     }
 
-    let index = xy_to_i(map, x, y);
+    let index = xy_to_i(map, x, y)?;
 
     entities.dynamic.get(&index)
-        .map(|e| e.kind)
+        .and_then(Stack::top)
+        .map(|(_, e)| e.kind)
         .or_else(|| {
             map.tiles.get(index).copied()
         })
 }
 
-#[derive(Clone, Copy)]
+/// Everything drawn at `x`, `y`, from the bottom up: the map's tile, or the
+/// ground entity covering it, then the rest of the dynamic entities, then any
+/// mob. `None` if `x`, `y` is off the map.
+fn tiles_at(map: Map, entities: &Entities, x: X, y: Y) -> Option<impl Iterator<Item = TileKind> + '_> {
+    let index = xy_to_i(map, x, y)?;
+    let stack = entities.dynamic.get(&index);
+
+    let ground = match stack.and_then(|stack| stack.get(Depth::Ground)) {
+        Some(entity) => entity.kind,
+        None => *map.tiles.get(index)?,
+    };
+
+    let above = stack.into_iter()
+        .flat_map(|stack| stack.0[Depth::Item.index()..].iter().flatten())
+        .map(|entity| entity.kind);
+
+    let mob = entities.all_mobs()
+        .find(|mob| x == mob.x && y == mob.y)
+        .map(|mob| mob.kind);
+
+    Some(core::iter::once(ground).chain(above).chain(mob))
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum Dir {
     Up,
     Down,
@@ -327,16 +412,43 @@ impl core::ops::Not for Dir {
     }
 }
 
-fn xy_in_dir(dir: Dir, mut x: X, mut y: Y) -> (X, Y) {
+/// The position one step in `dir` from `x`, `y`, or `None` if that would be
+/// outside the range of coordinates.
+fn xy_in_dir(dir: Dir, x: X, y: Y) -> Option<(X, Y)> {
     use Dir::*;
-    match dir {
-        Up => { y -= H::ONE; },
-        Down => { y += H::ONE; },
-        Left => { x -= W::ONE; },
-        Right => { x += W::ONE; },
+    Some(match dir {
+        Up => (x, y.checked_sub(H::ONE)?),
+        Down => (x, y.checked_add(H::ONE)?),
+        Left => (x.checked_sub(W::ONE)?, y),
+        Right => (x.checked_add(W::ONE)?, y),
+    })
+}
+
+/// The positions one step in each direction from `x`, `y`, that are within the
+/// range of coordinates.
+fn four_neighbours(x: X, y: Y) -> impl Iterator<Item = (X, Y)> {
+    [Dir::Left, Dir::Right, Dir::Up, Dir::Down]
+        .into_iter()
+        .filter_map(move |dir| xy_in_dir(dir, x, y))
+}
+
+/// The direction the pattern says to go in on this frame, if any.
+fn pattern_dir(pattern: maps::mobs::Pattern, frame_count: FrameCount) -> Option<Dir> {
+    let dirs = pattern.dirs.as_bytes();
+
+    if dirs.is_empty() {
+        return None
     }
 
-    (x, y)
+    let index = (frame_count / pattern.step_length.max(1)) as usize % dirs.len();
+
+    match dirs[index] {
+        b'u' => Some(Dir::Up),
+        b'd' => Some(Dir::Down),
+        b'l' => Some(Dir::Left),
+        b'r' => Some(Dir::Right),
+        _ => None,
+    }
 }
 
 fn gen_dir(rng: &mut Xs) -> Dir {
@@ -349,91 +461,44 @@ fn gen_dir(rng: &mut Xs) -> Dir {
     }
 }
 
-fn is_landmark(kind: TileKind) -> bool {
-    match kind {
-        tile::FLOOR
-        | tile::GROUND
-        | tile::GRASS_GROUND
-        | tile::EXCLAMATION_BUBBLE
-        | tile::DOOR_0
-        | tile::DOOR_1
-        | tile::DOOR_2
-        | tile::DOOR_3
-        | tile::DOOR_4
-        | tile::CLOSED_DOOR
-        | tile::WALL_0
-        | tile::WALL_1
-        | tile::WALL_2
-        | tile::WALL_3
-        | tile::WALL_4
-        | tile::WALL_5
-        | tile::WALL_6
-        | tile::WALL_7
-        | tile::WALL_8
-        | tile::WALL_9
-        | tile::WALL_10
-        | tile::WALL_11
-        | tile::WALL_12
-        | tile::WALL_13
-        | tile::WALL_14
-        | tile::WALL_15
-        | tile::WALL_16
-        | tile::WALL_17
-        | tile::WALL_18
-        | tile::WALL_19
-        | tile::WALL_20
-        | tile::WALL_21
-        | tile::WALL_22
-        | tile::WALL_23
-        | tile::WALL_24
-        | tile::WALL_25
-        | tile::WALL_26
-        => false,
-        _ => true,
-    }
+fn is_landmark(map: Map, kind: TileKind) -> bool {
+    map.properties(kind).has(properties::LANDMARK)
 }
 
 fn is_xy_landmark(map: Map, x: X, y: Y) -> bool {
-    let index = xy_to_i(map, x, y);
-
     // We don't do get_effective_tile because entities aren't landmarks
-    map.tiles
-        .get(index)
-        .map(|&kind| is_landmark(kind))
-        // TODO? Track down why this panicked once when this was map.tiles[index]
-        .unwrap_or(false)
+    xy_to_i(map, x, y)
+        .and_then(|index| map.tiles.get(index))
+        .is_some_and(|&kind| is_landmark(map, kind))
+}
+
+/// The randomness for one mob for one tick. Mobs are told apart by where they
+/// start on the map, rather than by their index, so that listing the mobs in
+/// a different order does not change what any of them do.
+fn mob_rng(seed: xs::Seed, spawn_x: X, spawn_y: Y) -> Xs {
+    let spawn = (spawn_x.usize() as u128) << 64 | spawn_y.usize() as u128;
+    // Spread the bits of the spawn point across the whole seed.
+    let mixed = u128::from_le_bytes(seed)
+        ^ spawn.wrapping_mul(0x9E37_79B9_7F4A_7C15_F39C_C605_CEDC_834B);
+
+    xs::from_seed(mixed.to_le_bytes())
 }
 
+/// The first suitable landmark from a random place on the map onwards, if
+/// there are any.
 fn random_landmark(
     rng: &mut Xs,
     map: Map,
     is_suitable: impl Fn((X, Y)) -> bool,
-) -> (X, Y) {
+) -> Option<(X, Y)> {
     // TODO? Do this starting from X,Y to avoid the divide?
-    let mut index = xs::range(rng, 0..map.tiles.len() as _) as usize;
-
-    // We don't do get_effective_tile because entities aren't landmarks
-    if is_landmark(map.tiles[index]) {
-        let xy = i_to_xy(map, index);
-        if is_suitable(xy) {
-            return xy
-        }
-    }
-
-    let loop_index = index;
-
-    while {
-        index += 1;
-        if index >= map.tiles.len() {
-            index = 0;
-        }
-
-        !is_landmark(map.tiles[index])
-        && !is_suitable(i_to_xy(map, index))
-        && index != loop_index
-    } {}
+    let start = xs::range(rng, 0..map.tiles.len() as _) as usize;
 
-    return i_to_xy(map, index);
+    (start..map.tiles.len()).chain(0..start)
+        // We don't do get_effective_tile because entities aren't landmarks
+        .filter(|&index| is_landmark(map, map.tiles[index]))
+        .map(|index| i_to_xy(map, index))
+        .find(|&xy| is_suitable(xy))
 }
 
 mod movement {
@@ -447,68 +512,121 @@ mod movement {
         new_y: Y,
     }
 
-    type PlannedLength = u8;
-
+    /// Moves to make together, in order. Empty when there is nothing to do,
+    /// like when already at the target.
     #[derive(Default)]
     pub struct Planned {
-        plans: [Plan; 15],
-        length: PlannedLength,
+        plans: Vec<Plan>,
     }
 
     impl Planned {
         fn push(&mut self, plan: Plan) {
-            // TODO? report back if we ran out of room?
-            if let Some(p) = self.plans.get_mut(self.length as usize) {
-                *p = plan;
-                self.length += 1;
-            }
+            self.plans.push(plan);
         }
 
-        pub fn len(&self) -> PlannedLength {
-            self.length
+        pub fn len(&self) -> usize {
+            self.plans.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.plans.is_empty()
         }
     }
 
-    pub type Flags = u8;
+    /// Why a move could not be made.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Blocked {
+        /// By one of the map's own tiles.
+        Wall,
+        /// By a mob, or a dynamic entity, that cannot be pushed.
+        Immovable,
+        /// By the edge of the map.
+        OutOfBounds,
+        /// By there being more than `MAX_CHAIN_LENGTH` things to push.
+        ChainTooLong,
+        /// By another move made at the same time, that needed the same tiles
+        /// and went first. See `resolve`.
+        Contested,
+    }
 
-    pub const PASS_THROUGH: Flags = 0x1;
+    /// The most things one move can push at once. This is longer than any
+    /// line of things that fits on the built-in maps, and is only here so that
+    /// a single move on a huge map does a bounded amount of work.
+    pub const MAX_CHAIN_LENGTH: usize = 1024;
 
-    pub fn plan(entity_x: X, entity_y: Y, map: Map, entities: &Entities, dir: Dir) -> Planned {
-        plan_custom(entity_x, entity_y, map, entities, dir, 0)
-    }
+    pub use maps::mobs::{Flags, Pathing, PASS_THROUGH};
 
-    pub fn plan_custom(entity_x: X, entity_y: Y, map: Map, entities: &Entities, dir: Dir, flags: Flags) -> Planned {
+    /// Plans for the entity to move in `dir`, pushing along however many
+    /// things are lined up in front of it. The things furthest along move
+    /// first, so the entity's own move is planned last.
+    pub fn plan_custom(entity_x: X, entity_y: Y, map: Map, entities: &Entities, dir: Dir, flags: Flags) -> Result<Planned, Blocked> {
         let mut planned = Planned::default();
 
-        plan_helper(&mut planned, entity_x, entity_y, map, entities, dir, flags);
-
-        planned
-    }
+        let (mut x, mut y) = (entity_x, entity_y);
+        loop {
+            // Each step is to a different position, so this can't go on
+            // forever, even without the chain length limit.
+            let (new_x, new_y) = xy_in_dir(dir, x, y).ok_or(Blocked::OutOfBounds)?;
+
+            planned.push(Plan {
+                old_x: x,
+                old_y: y,
+                new_x,
+                new_y,
+            });
 
-    fn plan_helper(planned: &mut Planned, entity_x: X, entity_y: Y, map: Map, entities: &Entities, dir: Dir, flags: Flags) {
-        let (new_x, new_y) = xy_in_dir(dir, entity_x, entity_y);
+            match allowed_to(map, entities, new_x, new_y, flags) {
+                Allowed::Not(blocked) => return Err(blocked),
+                Allowed::Move => break,
+                Allowed::Push => {
+                    if planned.len() > MAX_CHAIN_LENGTH {
+                        return Err(Blocked::ChainTooLong)
+                    }
 
-        if new_x == entity_x && new_y == entity_y {
-            // Don't ever recurse forever.
-            *planned = Planned::default();
-            return
+                    (x, y) = (new_x, new_y);
+                },
+            }
         }
 
-        let initial_length = planned.length;
+        planned.plans.reverse();
+
+        Ok(planned)
+    }
+
+    /// Plans for the entity to move in `dir`, pulling along whatever is
+    /// right behind it, if that could be pushed. Nothing gets pushed out of
+    /// the way, since pulling takes both hands. Like with pushing, the
+    /// entity's own move is planned last.
+    pub fn plan_pull(entity_x: X, entity_y: Y, map: Map, entities: &Entities, dir: Dir, flags: Flags) -> Result<Planned, Blocked> {
+        let mut planned = Planned::default();
+
+        let (new_x, new_y) = xy_in_dir(dir, entity_x, entity_y).ok_or(Blocked::OutOfBounds)?;
 
         match allowed_to(map, entities, new_x, new_y, flags) {
-            Allowed::Not => return,
+            Allowed::Not(blocked) => return Err(blocked),
             Allowed::Move => {},
-            Allowed::Push => {
-                // Attempt to push it
-                plan_helper(planned, new_x, new_y, map, entities, dir, flags);
+            Allowed::Push => return Err(Blocked::Immovable),
+        }
 
-                if initial_length == planned.length {
-                    // If something can't move, cancel all the other moves
-                    *planned = Planned::default();
-                    return
-                }
-            },
+        let pulled = xy_in_dir(!dir, entity_x, entity_y)
+            .filter(|&(behind_x, behind_y)| {
+                get_effective_tile(map, entities, behind_x, behind_y)
+                    .is_some_and(|kind| map.properties(kind).has(properties::PUSHABLE))
+            })
+            // The pulled thing ends up where the entity was, so that has to
+            // be somewhere things can go, once the entity is out of the way.
+            .filter(|_| {
+                get_effective_tile_custom(map, entities, entity_x, entity_y, NO_MOBS)
+                    .is_some_and(|kind| map.properties(kind).has(properties::WALKABLE))
+            });
+
+        if let Some((behind_x, behind_y)) = pulled {
+            planned.push(Plan {
+                old_x: behind_x,
+                old_y: behind_y,
+                new_x: entity_x,
+                new_y: entity_y,
+            });
         }
 
         planned.push(Plan {
@@ -516,106 +634,194 @@ mod movement {
             old_y: entity_y,
             new_x,
             new_y,
-        })
+        });
+
+        Ok(planned)
     }
 
-    pub fn perform(entities: &mut Entities, map: Map, Planned { plans, .. }: Planned) {
-        let max_map_x = X::ZERO + map.width - W::ONE;
-        let max_map_y = Y::ZERO + map.height - H::ONE;
+    /// Where a plan started from, when nothing was there to move. That can
+    /// only happen if the plans were made from different entities than they
+    /// are performed on.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct NothingThere {
+        pub x: X,
+        pub y: Y,
+    }
+
+    /// Performs all the plans it can, and reports the first one with nothing
+    /// to move.
+    pub fn perform(entities: &mut Entities, map: Map, Planned { plans }: Planned) -> Result<(), NothingThere> {
+        let mut output = Ok(());
 
         for Plan { old_x, old_y, new_x, new_y, } in plans {
-            if let Some(entity) = entities.get_mut(map, old_x, old_y) {
-                entity.x = new_x.clamp(X::ZERO, max_map_x);
-                entity.y = new_y.clamp(Y::ZERO, max_map_y);
+            // `allowed_to` keeps the plans on the map.
+            if let Some(mob) = entities.mob_mut(old_x, old_y) {
+                mob.x = new_x;
+                mob.y = new_y;
+            } else if let Some((depth, mut entity)) = entities.remove_top(map, old_x, old_y) {
+                // Dynamic entities are stored by where they are, so they
+                // have to be moved between tiles, keeping their depth.
+                entity.x = new_x;
+                entity.y = new_y;
+                entities.insert(map, depth, entity);
+            } else if output.is_ok() {
+                output = Err(NothingThere { x: old_x, y: old_y });
+            }
+        }
+
+        output
+    }
+
+    /// Performs the moves of everything that gets pushed or pulled, and
+    /// returns where the entity that did the pushing or pulling should end
+    /// up, if it moves at all. This is for entities that may share a tile with
+    /// another one, which `perform` would not be able to tell apart.
+    pub fn perform_pushes(entities: &mut Entities, map: Map, mut planned: Planned) -> Option<(X, Y)> {
+        // The moving entity's own move is always planned last.
+        let mover = planned.plans.pop()?;
+
+        let performed = perform(entities, map, planned);
+        // Whatever was pushed was found while planning, so it is there.
+        debug_assert!(performed.is_ok(), "{performed:?}");
+
+        Some((mover.new_x, mover.new_y))
+    }
+
+    impl Planned {
+        /// Where the moving entity starts from, since its move is last.
+        fn mover(&self) -> Option<(X, Y)> {
+            self.plans.last().map(|plan| (plan.old_x, plan.old_y))
+        }
+
+        /// Every tile that something moves out of or into.
+        fn tiles(&self) -> impl Iterator<Item = (X, Y)> + '_ {
+            self.plans.iter()
+                .flat_map(|plan| [(plan.old_x, plan.old_y), (plan.new_x, plan.new_y)])
+        }
+    }
+
+    /// Decides which of a set of moves, all planned against the same world,
+    /// get to happen together. Two moves conflict if they touch any of the
+    /// same tiles, which covers two things moving onto the same tile, two
+    /// things swapping places, and two chains pushing the same thing. Of
+    /// conflicting moves, the one that pushes the fewest things goes, and
+    /// then the one whose mover is highest up, and then furthest left. Since
+    /// no two movers start on the same tile, that decides every conflict
+    /// without depending on the order the moves are in. The rest are blocked
+    /// as `Blocked::Contested`.
+    ///
+    /// Once resolved, the moves that are left can be performed in any order.
+    pub fn resolve(intents: &mut [Result<Planned, Blocked>]) {
+        let mut order: Vec<usize> = (0..intents.len()).collect();
+        order.sort_by_key(|&i| match &intents[i] {
+            Ok(planned) => planned.mover().map(|(x, y)| (planned.len(), y, x)),
+            Err(_) => None,
+        });
+
+        let mut claimed = std::collections::HashSet::new();
+        for i in order {
+            let Ok(planned) = &intents[i] else { continue };
+
+            if planned.tiles().any(|xy| claimed.contains(&xy)) {
+                intents[i] = Err(Blocked::Contested);
             } else {
-                // TODO? Send a signal back here that something went wrong?
+                claimed.extend(planned.tiles());
             }
         }
     }
 
     #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub enum Allowed {
-        Not,
+        Not(Blocked),
         Move,
         Push,
     }
 
     pub fn allowed_to(map: Map, entities: &Entities, new_x: X, new_y: Y, flags: Flags) -> Allowed {
-        if flags & PASS_THROUGH != 0 {
-            // Always allow moving
-            Allowed::Move
-        } else if let Some(tile_kind) = get_effective_tile(map, entities, new_x, new_y) {
-            match tile_kind {
-                tile::FLOOR
-                | tile::GROUND
-                | tile::GRASS_GROUND
-                | tile::DOOR_0
-                | tile::DOOR_1
-                | tile::DOOR_2
-                | tile::DOOR_3
-                | tile::DOOR_4
-                | tile::STAIRS_DOWN
-                | tile::KEY
-                | tile::BUTTON_LIT
-                | tile::BUTTON_DARK
-                | tile::BUTTON_PRESSED
-                | tile::PORTAL => {
-                     // Allow move to happen
+        allowed_to_custom(map, entities, new_x, new_y, flags, 0)
+    }
+
+    /// `tile_flags` are passed along to `get_effective_tile_custom`.
+    fn allowed_to_custom(map: Map, entities: &Entities, new_x: X, new_y: Y, flags: Flags, tile_flags: TileFlags) -> Allowed {
+        if !in_bounds(map, new_x, new_y) {
+            // Nothing leaves the map by moving. Exits are handled separately.
+            Allowed::Not(Blocked::OutOfBounds)
+        } else if let Some(tile_kind) = get_effective_tile_custom(map, entities, new_x, new_y, tile_flags) {
+            let tile_properties = map.properties(tile_kind);
+
+            // Whether it is the map's own tile in the way, or something on it.
+            let blocked = if Some(tile_kind) == xy_to_i(map, new_x, new_y).and_then(|i| map.tiles.get(i).copied()) {
+                Blocked::Wall
+            } else {
+                Blocked::Immovable
+            };
+
+            if flags & PASS_THROUGH != 0 {
+                if tile_properties.has(properties::BLOCKS_GHOSTS) {
+                    Allowed::Not(blocked)
+                } else {
                     Allowed::Move
                 }
-                tile::LARGE_POT => {
-                    Allowed::Push
-                }
-                _ => Allowed::Not, // Don't allow move to happen
+            } else if tile_properties.has(properties::WALKABLE) {
+                Allowed::Move
+            } else if tile_properties.has(properties::PUSHABLE) {
+                Allowed::Push
+            } else {
+                Allowed::Not(blocked)
             }
         } else {
-            // If we're glitched out of bounds, let movement through so they can maybe get unstuck.
-            Allowed::Move
+            // Ought to be impossible, since it is in bounds.
+            Allowed::Not(Blocked::OutOfBounds)
         }
     }
 
+    /// The tiles next to `x`, `y` that an entity with these flags could move
+    /// onto, possibly by pushing something.
+    pub fn neighbours(map: Map, entities: &Entities, x: X, y: Y, flags: Flags) -> impl Iterator<Item = (X, Y)> + '_ {
+        four_neighbours(x, y)
+        .filter(move |&(x, y)|
+            !matches!(
+                allowed_to(
+                    map,
+                    entities,
+                    x,
+                    y,
+                    flags,
+                ),
+                Allowed::Not(_)
+            )
+        )
+    }
+
     pub fn plan_entity_on_path_towards(
         entity_x: X,
         entity_y: Y,
         entities: &Entities,
         map: Map,
-        target_x: X,
-        target_y: Y,
-    ) -> Planned {
+        (target_x, target_y): (X, Y),
+        flags: Flags,
+        pathing: &Pathing,
+    ) -> Result<Planned, Blocked> {
         use pathfinding::prelude::astar;
         use Dir::*;
 
-        let output = Planned::default();
+        // Not finding a way there is not being blocked by any one thing, so
+        // it just means nothing to do.
+        let output = Ok(Planned::default());
 
-        // TODO? movement::Flags param?
-        let flags = 0;
-        // TODO? cache the path? Or at least like reuse the memory allocation each time?
+        let min_cost = pathing.min_cost();
 
         let Some((path, _)) = astar(
             &(entity_x, entity_y),
             |&(x, y)| {
-                [
-                    (x - W::ONE, y),
-                    (x + W::ONE, y),
-                    (x, y - H::ONE),
-                    (x, y + H::ONE),
-                ]
-                .into_iter()
-                .filter(|&(x, y)|
-                    movement::allowed_to(
-                        map,
-                        entities,
-                        x,
-                        y,
-                        flags,
-                    ) != movement::Allowed::Not
-                )
-                .map(|p| (p, 1))
-
+                neighbours(map, entities, x, y, flags)
+                    .filter_map(|(x, y)| Some(((x, y), step_cost(map, entities, x, y, pathing)?)))
             },
             |&(x, y)| {
-                (x.usize().abs_diff(target_x.usize()))
-                + (y.usize().abs_diff(target_y.usize()))
+                let moves = (x.usize().abs_diff(target_x.usize()))
+                + (y.usize().abs_diff(target_y.usize()));
+
+                moves as u32 * min_cost
             },
             |&(x, y)| {
                 x == target_x && y == target_y
@@ -644,664 +850,5741 @@ mod movement {
             }
         };
 
-        plan(
-            entity_x, entity_y, map, entities, dir
+        plan_custom(
+            entity_x, entity_y, map, entities, dir, flags
         )
     }
-}
 
-// TODO add a way to pull things, like pots. Does having them try to move you just work?
-fn move_entity(entity_x: X, entity_y: Y, entities: &mut Entities, map: Map, dir: Dir) {
-    move_entity_custom(entity_x, entity_y, entities, map, dir, 0)
-}
+    const UNREACHABLE: u32 = u32::MAX;
 
-fn move_entity_custom(entity_x: X, entity_y: Y, entities: &mut Entities, map: Map, dir: Dir, flags: movement::Flags) {
-    movement::perform(entities, map, movement::plan_custom(entity_x, entity_y, map, entities, dir, flags));
-}
+    /// What it costs to path onto `x`, `y`, going by what is there besides
+    /// mobs, or `None` if it is to be avoided.
+    fn step_cost(map: Map, entities: &Entities, x: X, y: Y, pathing: &Pathing) -> Option<u32> {
+        let kind = get_effective_tile_custom(map, entities, x, y, NO_MOBS)?;
 
-#[allow(unused)]
-fn move_entity_on_path_towards(
-    entity_x: X,
-    entity_y: Y,
-    entities: &mut Entities,
-    map: Map,
-    target_x: X,
-    target_y: Y,
-) {
-    movement::perform(entities, map, movement::plan_entity_on_path_towards(entity_x, entity_y, entities, map, target_x, target_y));
-}
+        (!pathing.avoids(kind, x, y)).then(|| pathing.cost(kind))
+    }
 
-type RambleIndex = u8;
+    /// How much it costs to get from each tile of a map to a target, as far
+    /// as the map's tiles and its dynamic entities go. Mobs are left out,
+    /// since they move around too often for that to be worth keeping track
+    /// of.
+    struct DistanceField {
+        /// Indexed like the map's tiles.
+        distances: Vec<u32>,
+        /// Whether this has been used since the last refresh.
+        used: bool,
+    }
 
-#[derive(Default)]
-pub enum MessageInfo {
-    #[default]
-    NoMessage,
-    PasswordReveal {
-        index: ButtonIndex,
-    },
-    PasswordRevealRefused,
-    ForgotPassword,
-    Ramble(RambleIndex),
-    GhostOoo,
-}
+    impl DistanceField {
+        fn new(map: Map, entities: &Entities, (target_x, target_y): (X, Y), flags: Flags, pathing: &Pathing) -> Self {
+            use core::cmp::Reverse;
 
-/// 65536 distinct frames ought to be enough for anybody!
-type FrameCount = u16;
+            let mut distances = vec![UNREACHABLE; map.width.usize() * map.height.usize()];
 
-#[derive(Debug, Default)]
-pub enum DogState {
-    #[default]
-    PreSniffing, // This state exists to reduce sniffing indicator spam/flickering
-    Sniffing,
-    MovingTowards(X, Y),
-}
+            if let Some(i) = xy_to_i(map, target_x, target_y) {
+                distances[i] = 0;
+            }
 
-pub struct State {
-    pub frame_count: FrameCount,
-    pub rng: Xs,
-    pub map: Map,
-    pub screen: Screen,
-    pub entities: Entities,
-    pub password_lock: PasswordLock,
-    pub message_info: MessageInfo,
-    pub previous_password_reveal_index: Option<usize>,
-    pub hud_prints: [Print; 1],
-    pub invert_panoptikhan_moves: bool,
-    pub invert_zombie_moves: bool,
-    pub dog_states: [DogState; maps::DOG_COUNT as usize],
-    pub dog_tile_kinds: DogTileKinds,
-}
+            // Dijkstra's algorithm, out from the target, which goes backwards
+            // along each move.
+            let mut queue = std::collections::BinaryHeap::from([Reverse((0, target_x, target_y))]);
+            while let Some(Reverse((distance, x, y))) = queue.pop() {
+                let Some(i) = xy_to_i(map, x, y) else { continue };
+                if distance > distances[i] {
+                    // A cheaper way here was already found.
+                    continue
+                }
 
-type DogTileKinds = [TileKind; maps::DOG_COUNT as usize];
+                let Some(cost) = step_cost(map, entities, x, y, pathing)
+                    .filter(|_| enterable(map, entities, x, y, flags, pathing)) else {
+                    // Nothing can get here from the neighbours, so it does
+                    // not lead them anywhere.
+                    continue
+                };
+                let distance = distance + cost;
 
-impl State {
-    pub fn new(seed: Seed) -> State {
-        let mut rng = xs::from_seed(seed);
+                for (x, y) in four_neighbours(x, y) {
+                    let Some(i) = xy_to_i(map, x, y) else { continue };
 
-        let mut dog_tile_kinds = DogTileKinds::default();
+                    if distance < distances[i] {
+                        distances[i] = distance;
+                        queue.push(Reverse((distance, x, y)));
+                    }
+                }
+            }
 
-        for i in 0..maps::DOG_COUNT {
-            let dog_index = xs::range(&mut rng, 0..tile::DOGS.len() as _) as usize;
-            dog_tile_kinds[i as usize] = tile::DOGS[dog_index];
+            Self {
+                distances,
+                used: false,
+            }
         }
 
-        Self::new_inner(rng, dog_tile_kinds)
+        fn distance(&self, map: Map, x: X, y: Y) -> u32 {
+            xy_to_i(map, x, y)
+                .and_then(|i| self.distances.get(i).copied())
+                .unwrap_or(UNREACHABLE)
+        }
     }
 
-    fn new_inner(mut rng: Xs, dog_tile_kinds: DogTileKinds) -> State {
-        let map = &maps::MAP;
-
-        let mut entities = Entities::default();
-
-        entities.player = Entity {
-            kind: 9,
-            x: map.player_x,
-            y: map.player_y,
-        };
+    /// Whether something with these flags could path onto `x`, `y`, if there
+    /// were no mobs around.
+    fn enterable(map: Map, entities: &Entities, x: X, y: Y, flags: Flags, pathing: &Pathing) -> bool {
+        !matches!(allowed_to_custom(map, entities, x, y, flags, NO_MOBS), Allowed::Not(_))
+        && step_cost(map, entities, x, y, pathing).is_some()
+    }
 
-        entities.turtle.x = map.turtle_x;
-        entities.turtle.y = map.turtle_y;
-        entities.turtle.kind = tile::TURTLE;
+    /// Which tiles can be reached from which, going by the same things as
+    /// `DistanceField`. This is much cheaper to check than making a distance
+    /// field, and a distance field to somewhere that can't be reached would
+    /// only go to waste.
+    struct Regions {
+        /// Indexed like the map's tiles. Tiles that can be moved between
+        /// have the same label, and tiles that can't be moved onto at all
+        /// are `UNREACHABLE`.
+        labels: Vec<u32>,
+    }
 
-        entities.crab.x = map.crab_x;
-        entities.crab.y = map.crab_y;
-        entities.crab.kind = tile::CRAB;
+    impl Regions {
+        fn new(map: Map, entities: &Entities, flags: Flags, pathing: &Pathing) -> Self {
+            let (width, height) = (map.width.usize(), map.height.usize());
+            let mut labels = vec![UNREACHABLE; width * height];
+            let mut next_label = 0;
 
-        entities.ghost.x = map.ghost_x;
-        entities.ghost.y = map.ghost_y;
-        entities.ghost.kind = tile::GHOST_1;
+            let mut queue = std::collections::VecDeque::new();
+            for i in 0..labels.len() {
+                if labels[i] != UNREACHABLE {
+                    continue
+                }
 
-        entities.panoptikhan.x = map.panoptikhan_x;
-        entities.panoptikhan.y = map.panoptikhan_y;
-        entities.panoptikhan.kind = tile::PANOPTIKHAN;
+                let (x, y) = (xy::x((i % width) as xy::Inner), xy::y((i / width) as xy::Inner));
+                if !enterable(map, entities, x, y, flags, pathing) {
+                    continue
+                }
 
-        entities.large_pot.x = map.large_pot_x;
-        entities.large_pot.y = map.large_pot_y;
-        entities.large_pot.kind = tile::LARGE_POT;
+                labels[i] = next_label;
+                queue.push_back((x, y));
 
-        entities.zombie.x = map.zombie_x;
-        entities.zombie.y = map.zombie_y;
-        entities.zombie.kind = tile::ZOMBIE;
+                while let Some((x, y)) = queue.pop_front() {
+                    for (x, y) in four_neighbours(x, y) {
+                        let Some(i) = xy_to_i(map, x, y) else { continue };
 
-        for i in 0..maps::DOG_COUNT {
-            let Some(dog) = entities.get_dog_mut(i) else { continue };
+                        if labels[i] == UNREACHABLE && enterable(map, entities, x, y, flags, pathing) {
+                            labels[i] = next_label;
+                            queue.push_back((x, y));
+                        }
+                    }
+                }
 
-            dog.x = map.dogs[i as usize].0;
-            dog.y = map.dogs[i as usize].1;
+                next_label += 1;
+            }
 
-            dog.kind = dog_tile_kinds[i as usize];
+            Self { labels }
         }
 
-        State {
-            frame_count: 0,
-            rng,
-            map,
-            screen: Screen::default(),
-            entities,
-            password_lock: PasswordLock::new(
-                map.buttons,
-                &mut rng
-            ),
-            message_info: MessageInfo::default(),
-            previous_password_reveal_index: <_>::default(),
-            hud_prints: <_>::default(),
-            invert_panoptikhan_moves: false,
-            invert_zombie_moves: false,
-            dog_states: <_>::default(),
-            dog_tile_kinds,
+        fn label(&self, map: Map, x: X, y: Y) -> u32 {
+            xy_to_i(map, x, y)
+                .and_then(|i| self.labels.get(i).copied())
+                .unwrap_or(UNREACHABLE)
         }
-    }
 
-    fn reset_time(&mut self) {
-        let mut password_lock = self.password_lock.clone();
-        // Retain the combination for this game across resets.
-        password_lock.reset();
+        /// Whether something at `x`, `y` could get to the target. What it
+        /// is standing on does not matter, since it is already there.
+        fn connects(&self, map: Map, x: X, y: Y, (target_x, target_y): (X, Y)) -> bool {
+            let target = self.label(map, target_x, target_y);
 
-        // New seed for the rng, so different resets are slightly different.
-        *self = State::new_inner(
-            xs::from_seed(xs::new_seed(&mut self.rng)),
-            self.dog_tile_kinds,
-        );
+            target != UNREACHABLE
+            && (
+                (x == target_x && y == target_y)
+                || four_neighbours(x, y).any(|(x, y)| self.label(map, x, y) == target)
+            )
+        }
+    }
 
-        self.password_lock = password_lock;
+    /// Distance fields shared by everything heading towards the same target,
+    /// so that many things pathfinding at once costs about as much as one
+    /// search per target, instead of one per thing per move. The fields are
+    /// kept until the map, or its dynamic entities, change. Things that path
+    /// differently get separate fields.
+    #[derive(Default)]
+    pub struct DistanceFields {
+        map: Option<Map>,
+        dynamic_hash: u64,
+        regions: HashMap<(Flags, Pathing), Regions>,
+        fields: HashMap<((X, Y), Flags, Pathing), DistanceField>,
     }
 
-    pub fn frame(&mut self, input: Input, speaker: &mut Speaker) {
-        macro_rules! button_check {
-            ($entity: expr) => ({
-                let entity = &$entity;
-                match get_effective_tile_custom(self.map, &self.entities, entity.x, entity.y, NO_MOBS) {
-                    Some(tile::BUTTON_LIT) => {
-                        if let Some(sfx) = self.entity_on_button(entity.x, entity.y) {
-                            speaker.request_sfx(sfx);
-                        }
-                    }
-                    _ => {}
-                }
-            })
+    impl DistanceFields {
+        /// Drops every field if what they were made from has changed, and
+        /// otherwise drops the ones that have not been used since the last
+        /// refresh, so targets that are no longer being headed to do not pile
+        /// up.
+        pub fn refresh(&mut self, map: Map, entities: &Entities) {
+            use core::hash::Hasher;
+
+            let mut hasher = hash::Fnv::default();
+            hash_dynamic(&entities.dynamic, &mut hasher);
+            let dynamic_hash = hasher.finish();
+
+            if self.map.is_some_and(|old| core::ptr::eq(old, map))
+            && self.dynamic_hash == dynamic_hash {
+                self.fields.retain(|_, field| core::mem::take(&mut field.used));
+            } else {
+                self.map = Some(map);
+                self.dynamic_hash = dynamic_hash;
+                self.regions.clear();
+                self.fields.clear();
+            }
         }
 
-        // Turtle movement
-        if self.frame_count & 0b1111 == 0 {
-            let planned = movement::plan(
-                self.entities.turtle.x,
-                self.entities.turtle.y,
-                self.map,
-                &self.entities,
-                match (self.frame_count >> 6) & 0b11 {
-                    0b01 => Dir::Down,
-                    0b10 => Dir::Right,
-                    0b11 => Dir::Up,
-                    _ => Dir::Left,
-                }
-            );
+        /// Like `plan_entity_on_path_towards`, but by following the distance
+        /// field for the target, only searching for a path when mobs are in
+        /// the way. This is on the map from the last `refresh`, which should
+        /// be called whenever the entities might have changed. Until then,
+        /// it plans nothing.
+        pub fn plan_towards(
+            &mut self,
+            entity_x: X,
+            entity_y: Y,
+            entities: &Entities,
+            target: (X, Y),
+            flags: Flags,
+            pathing: &Pathing,
+        ) -> Result<Planned, Blocked> {
+            let Some(map) = self.map else { return Ok(Planned::default()) };
+
+            let regions = self.regions.entry((flags, *pathing))
+                .or_insert_with(|| Regions::new(map, entities, flags, pathing));
+            if !regions.connects(map, entity_x, entity_y, target) {
+                // Mobs moving won't help with that.
+                return Ok(Planned::default())
+            }
 
-            if planned.len() > 0 {
-                movement::perform(&mut self.entities, self.map, planned);
+            let field = self.fields.entry((target, flags, *pathing))
+                .or_insert_with(|| DistanceField::new(map, entities, target, flags, pathing));
+            field.used = true;
 
-                button_check!(self.entities.turtle);
-            };
-        }
+            let here = field.distance(map, entity_x, entity_y);
+            if here == 0 {
+                return Ok(Planned::default())
+            }
 
-        // Crab movement
-        if self.frame_count & 0b111 == 0 {
-            let planned = movement::plan(
-                self.entities.crab.x,
-                self.entities.crab.y,
-                self.map,
-                &self.entities,
-                match (self.frame_count >> 6) & 0b11 {
-                    0b01 => Dir::Right,
-                    _ => Dir::Left,
-                }
-            );
-            if planned.len() > 0 {
-                movement::perform(&mut self.entities, self.map, planned);
+            let dir = [Dir::Left, Dir::Right, Dir::Up, Dir::Down]
+                .into_iter()
+                .find(|&dir| {
+                    xy_in_dir(dir, entity_x, entity_y).is_some_and(|(x, y)|
+                        // On the cheapest way there.
+                        step_cost(map, entities, x, y, pathing)
+                            .and_then(|cost| field.distance(map, x, y).checked_add(cost))
+                            == Some(here)
+                        && !matches!(allowed_to(map, entities, x, y, flags), Allowed::Not(_))
+                    )
+                });
 
-                button_check!(self.entities.crab);
-            };
-        }
+            if let Some(Ok(planned)) = dir.map(|dir| plan_custom(entity_x, entity_y, map, entities, dir, flags)) {
+                return Ok(planned)
+            }
 
-        // ghost movement
-        if self.frame_count & 0b11_1111 == 0 {
-            move_entity_custom(
-                self.entities.ghost.x,
-                self.entities.ghost.y,
-                &mut self.entities,
-                self.map,
-                gen_dir(&mut self.rng),
-                movement::PASS_THROUGH,
-            );
+            // Every step closer is blocked by mobs, so look for a way around
+            // them.
+            plan_entity_on_path_towards(entity_x, entity_y, entities, map, target, flags, pathing)
         }
+    }
+}
 
-        // panoptikhan movement
-        if self.frame_count & 0b1_1111 == 0 {
-            let mut dir = match (self.frame_count >> 5) & 0b111 {
-                0b01 => Dir::Up,
-                0b10 => Dir::Left,
-                0b11 => Dir::Down,
-                0b100 => Dir::Left,
-                0b101 => Dir::Down,
-                0b110 => Dir::Left,
-                0b111 => Dir::Up,
-                _ => Dir::Left,
-            };
-
-            if self.invert_panoptikhan_moves {
-                dir = !dir;
-            }
+fn move_entity(entity_x: X, entity_y: Y, entities: &mut Entities, map: Map, dir: Dir) -> Result<(), movement::Blocked> {
+    move_entity_custom(entity_x, entity_y, entities, map, dir, 0)
+}
 
-            let planned = movement::plan_custom(
-                self.entities.panoptikhan.x,
-                self.entities.panoptikhan.y,
-                self.map,
-                &self.entities,
-                dir,
-                movement::PASS_THROUGH,
-            );
-            if planned.len() > 0 {
-                movement::perform(&mut self.entities, self.map, planned);
-            } else {
-                self.invert_panoptikhan_moves = !self.invert_panoptikhan_moves;
-            };
-        }
+fn move_entity_custom(entity_x: X, entity_y: Y, entities: &mut Entities, map: Map, dir: Dir, flags: movement::Flags) -> Result<(), movement::Blocked> {
+    let planned = movement::plan_custom(entity_x, entity_y, map, entities, dir, flags)?;
 
-        // zombie movement
-        if self.frame_count & 0b111_1111 == 0 {
-            let mut dir = match (self.frame_count >> 7) & 0b1111 {
-                0b01 => Dir::Right,
-                0b10 => Dir::Down,
-                0b11 => Dir::Down,
-                0b100 => Dir::Down,
-                0b101 => Dir::Down,
-                0b110 => Dir::Left,
-                0b111 => Dir::Down,
-                0b1000 => Dir::Down,
-                0b1001 => Dir::Down,
-                0b1010 => Dir::Left,
-                0b1011 => Dir::Down,
-                0b1100 => Dir::Down,
-                0b1101 => Dir::Down,
-                0b1110 => Dir::Down,
-                0b1111 => Dir::Right,
-                _ => Dir::Down,
-            };
+    let performed = movement::perform(entities, map, planned);
+    // The plans were just made from these entities.
+    debug_assert!(performed.is_ok(), "{performed:?}");
 
-            if self.invert_zombie_moves {
-                dir = !dir;
-            }
+    Ok(())
+}
 
-            let planned = movement::plan(
-                self.entities.zombie.x,
-                self.entities.zombie.y,
-                self.map,
-                &self.entities,
-                dir,
-            );
+#[allow(unused)]
+fn move_entity_on_path_towards(
+    entity_x: X,
+    entity_y: Y,
+    entities: &mut Entities,
+    map: Map,
+    target: (X, Y),
+    flags: movement::Flags,
+    pathing: &movement::Pathing,
+) {
+    if let Ok(planned) = movement::plan_entity_on_path_towards(entity_x, entity_y, entities, map, target, flags, pathing) {
+        let performed = movement::perform(entities, map, planned);
+        // The plans were just made from these entities.
+        debug_assert!(performed.is_ok(), "{performed:?}");
+    }
+}
 
-            if planned.len() > 0 {
-                movement::perform(&mut self.entities, self.map, planned);
+/// Checks maps for mistakes that would otherwise only show up in play, like a
+/// button on a wall, or stairs that cannot be reached.
+pub mod lint {
+    use super::*;
+    use maps::exits::Trigger;
 
-                button_check!(self.entities.zombie);
-            } else {
-                self.invert_zombie_moves = !self.invert_zombie_moves;
-            };
-        }
+    /// Something placed on a map.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Object {
+        Player,
+        /// The index into the map's mobs.
+        Mob(usize),
+        /// The door opened by the lock with this index into the map's locks.
+        LockedDoor(usize),
+        /// The key given by the lock with this index into the map's locks.
+        Key(usize),
+        Button { lock: usize, button: usize },
+        SpecialGrave,
+        Portal,
+        Stairs,
+        /// Where the exit with this index into the map's exits is, or where
+        /// it leads to, depending on the problem.
+        Exit(usize),
+    }
 
-        // dog movement
-        for i in 0..maps::DOG_COUNT {
-            let Some(dog) = self.entities.get_dog(i) else { continue };
-
-            match self.dog_states[i as usize] {
-                DogState::MovingTowards(target_x, target_y) => {
-                    let intitial_x = dog.x;
-                    let intitial_y = dog.y;
-    
-                    let planned = movement::plan_entity_on_path_towards(
-                        dog.x,
-                        dog.y,
-                        &mut self.entities,
-                        self.map,
-                        target_x,
-                        target_y,
-                    );
-    
-                    if planned.len() > 0 {
-                        movement::perform(&mut self.entities, self.map, planned);
-    
-                        let Some(dog) = self.entities.get_dog(i) else { continue };
-
-                        button_check!(dog);
-                    };
-    
-                    // TODO? Have dog avoid the portal?
-                    //    Arguably we'll likely eventually have a different way to reset time and not have a portal?
-    
-                    let Some(dog) = self.entities.get_dog(i) else { continue };
-
-                    if
-                    // If we got there
-                    (
-                        dog.x == target_x
-                        && dog.y == target_y
-                    )
-                    || // Or we can't seem to get there
-                    (
-                        dog.x == intitial_x
-                        && dog.y == intitial_y
-                    )
-                    {
-                        self.dog_states[i as usize] = DogState::PreSniffing;
-                    }
-                }
-                DogState::PreSniffing | DogState::Sniffing => {
-                    self.dog_states[i as usize] = DogState::Sniffing;
-
-                    if self.frame_count & 0b11_1111 == 0
-                    || !xy::eight_neighbors(dog.x, dog.y).iter().any(|&(x, y)| is_xy_landmark(self.map, x, y))
-                    {
-                        // Run off to something else
-                        let (target_x, target_y) = random_landmark(
-                            &mut self.rng,
-                            self.map,
-                            |(x, y)| {
-                                if dog.x == x
-                                && dog.y == y {
-                                    return false
-                                }
-    
-                                let xys = xy::eight_neighbors(dog.x, dog.y);
-    
-                                for xy in xys {
-                                    if xy.0 == x
-                                    && xy.1 == y {
-                                        return false
-                                    }
-                                }
-    
-                                true
-                            }
-                        );
-    
-                        self.dog_states[i as usize] = DogState::MovingTowards(target_x, target_y);
-                    }
-                }
+    impl core::fmt::Display for Object {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            use Object::*;
+            match self {
+                Player => write!(f, "The player"),
+                Mob(i) => write!(f, "Mob {i}"),
+                LockedDoor(lock) => write!(f, "The door of lock {lock}"),
+                Key(lock) => write!(f, "The key of lock {lock}"),
+                Button { lock, button } => write!(f, "Button {button} of lock {lock}"),
+                SpecialGrave => write!(f, "The special grave"),
+                Portal => write!(f, "A portal"),
+                Stairs => write!(f, "A staircase"),
+                Exit(i) => write!(f, "Exit {i}"),
             }
         }
+    }
 
-        let sfx_opt = if input.pressed_this_frame(Button::UP) {
-            self.move_player(Dir::Up)
-        } else if input.pressed_this_frame(Button::DOWN) {
-            self.move_player(Dir::Down)
-        } else if input.pressed_this_frame(Button::LEFT) {
-            self.move_player(Dir::Left)
-        } else if input.pressed_this_frame(Button::RIGHT) {
-            self.move_player(Dir::Right)
-        } else {
-            None
-        };
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum Problem {
+        WrongTileCount { expected: usize, found: usize },
+        OffTheMap { what: Object, x: X, y: Y },
+        /// On a tile that `movement::allowed_to` does not allow moving onto.
+        Blocked { what: Object, x: X, y: Y },
+        Unreachable { what: Object, x: X, y: Y },
+        SameSpot { what: Object, other: Object, x: X, y: Y },
+        UnknownMap { what: Object, map: MapIndex },
+        /// A map other than the starting one, that no exit leads to.
+        NoWayIn,
+    }
 
-        if input.pressed_this_frame(Button::A) {
-            if input.gamepad.contains(Button::UP) {
-                self.interact(Dir::Up)
-            } else if input.gamepad.contains(Button::DOWN) {
-                self.interact(Dir::Down)
-            } else if input.gamepad.contains(Button::LEFT) {
-                self.interact(Dir::Left)
-            } else if input.gamepad.contains(Button::RIGHT) {
-                self.interact(Dir::Right)
+    impl core::fmt::Display for Problem {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            use Problem::*;
+            match self {
+                WrongTileCount { expected, found } => write!(f, "The size calls for {expected} tiles, but there are {found}"),
+                OffTheMap { what, x, y } => write!(f, "{what}, at ({}, {}), is off the map", x.usize(), y.usize()),
+                Blocked { what, x, y } => write!(f, "{what}, at ({}, {}), is on a tile nothing can move onto", x.usize(), y.usize()),
+                Unreachable { what, x, y } => write!(f, "{what}, at ({}, {}), cannot be reached", x.usize(), y.usize()),
+                SameSpot { what, other, x, y } => write!(f, "{what} and {} are both at ({}, {})", other.to_string().to_lowercase(), x.usize(), y.usize()),
+                UnknownMap { what, map } => write!(f, "{what} leads to map {map}, which is not in the world"),
+                NoWayIn => write!(f, "No exit leads to this map"),
             }
         }
+    }
 
-        if let Some(sfx) = sfx_opt {
-            speaker.request_sfx(sfx);
+    /// Which tiles the player can get to from `starts`, by the same rules that
+    /// `movement::plan_entity_on_path_towards` uses. The player can open
+    /// closed doors too.
+    ///
+    /// `locked_doors` are the doors that keys open. A locked door's tile is
+    /// whatever is shown while it is locked, so once `unlocked` they let the
+    /// player through regardless of it, and before then they never do.
+    fn reachable(map: Map, starts: &[(X, Y)], locked_doors: &[(X, Y)], unlocked: bool) -> Vec<bool> {
+        let entities = Entities::default();
+        let mut seen = vec![false; map.tiles.len()];
+        let mut stack = Vec::new();
+
+        for &(x, y) in starts {
+            if let Some(index) = xy_to_i(map, x, y) {
+                seen[index] = true;
+                stack.push((x, y));
+            }
         }
 
-        if let Screen::Congraturation = self.screen {
-        } else {
-            match self.frame_count.checked_add(1) {
-                Some(count) => {
-                    self.frame_count = count;
+        while let Some((x, y)) = stack.pop() {
+            let doors = four_neighbours(x, y)
+            .filter(|&(x, y)| {
+                (unlocked && locked_doors.contains(&(x, y)))
+                || get_effective_tile(map, &entities, x, y) == Some(tile::CLOSED_DOOR)
+            });
+
+            for (x, y) in movement::neighbours(map, &entities, x, y, 0).chain(doors) {
+                let Some(index) = xy_to_i(map, x, y) else {
+                    continue
+                };
+
+                if !unlocked && locked_doors.contains(&(x, y)) {
+                    continue
                 }
-                None => {
-                    self.reset_time();
+
+                if !seen[index] {
+                    seen[index] = true;
+                    stack.push((x, y));
                 }
             }
         }
 
-        use std::io::Write;
-        let _ = write!(&mut self.hud_prints[0].text[..], "{} ({}, {})", self.frame_count, self.entities.player.x.usize(), self.entities.player.y.usize());
+        seen
     }
 
-    fn add_entity(&mut self, entity: Entity) {
-        let index = xy_to_i(self.map, entity.x, entity.y);
+    /// Reachable, or next to a reachable tile, for things like graves that
+    /// get pushed instead of stood on, or the key, which appears on whatever
+    /// tile it is placed on.
+    fn touchable(map: Map, seen: &[bool], x: X, y: Y) -> bool {
+        core::iter::once((x, y))
+            .chain(four_neighbours(x, y))
+            .any(|(x, y)| xy_to_i(map, x, y).is_some_and(|index| seen[index]))
+    }
 
-        self.entities.dynamic.insert(index, entity);
+    /// Checks every map in the world, returning each problem along with the
+    /// index of the map it is on. Only the starting map's special grave is
+    /// checked, since that is the only one the game uses.
+    pub fn lint(world: maps::World) -> Vec<(MapIndex, Problem)> {
+        lint_maps(world.maps, world.start)
     }
 
-    fn remove_entity(&mut self, x: X, y: Y) -> Option<Entity> {
-        let index = xy_to_i(self.map, x, y);
+    /// Like `lint`, but with the map at `index` swapped for `edited`, so an
+    /// edit can be checked against the rest of the world before it is saved.
+    pub fn lint_edited(world: maps::World, index: MapIndex, edited: Map) -> Vec<(MapIndex, Problem)> {
+        let maps: Vec<Map> = world.maps.iter().enumerate()
+            .map(|(i, &map)| if i == usize::from(index) { edited } else { map })
+            .collect();
 
-        self.entities.dynamic.remove(&index)
+        lint_maps(&maps, world.start)
     }
 
-    /// Returns the tile after any entities have replaced it, as opposed to the initial set of tiles.
-    fn get_effective_tile(&mut self, x: X, y: Y) -> Option<TileKind> {
-        get_effective_tile(self.map, &self.entities, x, y)
-    }
+    fn lint_maps(maps: &[Map], start: MapIndex) -> Vec<(MapIndex, Problem)> {
+        let mut problems = Vec::new();
 
-    fn interact(&mut self, dir: Dir) {
-        let (target_x, target_y) = xy_in_dir(dir, self.entities.player.x, self.entities.player.y);
+        for (index, &map) in maps.iter().enumerate() {
+            let index = index as MapIndex;
 
-        macro_rules! ask_for_password {
-            ($index: expr) => {
-                if self.previous_password_reveal_index == Some($index)
-                || self.previous_password_reveal_index == None {
-                    self.message_info = MessageInfo::PasswordReveal {
-                        index: $index,
-                    };
-                    self.previous_password_reveal_index = Some($index);
-                } else {
-                    self.message_info = MessageInfo::PasswordRevealRefused;
-                }
+            // Where the player can come onto this map from.
+            let mut entrances = Vec::new();
+            if index == start {
+                entrances.push((map.player_x, map.player_y));
             }
+            for other in maps {
+                entrances.extend(other.exits.iter()
+                    .filter(|exit| exit.map == index)
+                    .map(|exit| (exit.x, exit.y)));
+            }
+
+            problems.extend(
+                lint_map(maps, map, index == start, &entrances)
+                    .into_iter()
+                    .map(|problem| (index, problem))
+            );
+        }
+
+        problems
+    }
+
+    fn lint_map(maps: &[Map], map: Map, is_start: bool, entrances: &[(X, Y)]) -> Vec<Problem> {
+        use Object::*;
+
+        let mut problems = Vec::new();
+
+        let expected = map.width.usize() * map.height.usize();
+        if map.tiles.len() != expected {
+            // The rest of the checks rely on the tiles matching the size.
+            problems.push(Problem::WrongTileCount { expected, found: map.tiles.len() });
+            return problems
+        }
+
+        if entrances.is_empty() {
+            problems.push(Problem::NoWayIn);
+        }
+
+        // Things that need to be stood on, and the flags of whatever stands there.
+        let mut standing = vec![(Player, map.player_x, map.player_y, 0)];
+        standing.extend(map.mobs.iter().enumerate().map(|(i, &(x, y, mob))| (Mob(i), x, y, mob.flags)));
+        for (lock, l) in map.locks.iter().enumerate() {
+            standing.extend(l.buttons.iter().enumerate().map(|(button, b)| (Button { lock, button }, b.x, b.y, 0)));
         }
 
-        match self.get_effective_tile(target_x, target_y) {
-            Some(tile::PERSON_0) => {
-                ask_for_password!(0)
+        let mut placed = Vec::with_capacity(standing.len() + 1);
+        placed.extend(standing.iter().map(|&(what, x, y, _)| (what, x, y)));
+        let mut keys = Vec::new();
+        let mut locked_doors = Vec::new();
+        for (lock, l) in map.locks.iter().enumerate() {
+            match l.reward {
+                locks::Reward::Key { x, y, door_x, door_y } => {
+                    keys.push((Key(lock), x, y));
+                    locked_doors.push((door_x, door_y));
+                    placed.push((Key(lock), x, y));
+                    placed.push((LockedDoor(lock), door_x, door_y));
+                },
+                locks::Reward::Action(timeline::Action::OpenDoor { x, y }) => {
+                    placed.push((LockedDoor(lock), x, y));
+                },
+                locks::Reward::Action(_) => {},
             }
-            Some(tile::PERSON_1) => {
-                ask_for_password!(1)
+        }
+        if is_start {
+            placed.push((SpecialGrave, map.special_grave_x, map.special_grave_y));
+        }
+        for (i, exit) in map.exits.iter().enumerate() {
+            if let Trigger::At(x, y) = exit.trigger {
+                placed.push((Exit(i), x, y));
             }
-            Some(tile::PERSON_2) => {}
-            Some(tile::PERSON_3) => {
-                self.message_info = MessageInfo::ForgotPassword;
+        }
+
+        let mut all_in_bounds = true;
+        for &(what, x, y) in &placed {
+            if !in_bounds(map, x, y) {
+                problems.push(Problem::OffTheMap { what, x, y });
+                all_in_bounds = false;
             }
-            Some(tile::PERSON_4) => {
-                if let MessageInfo::Ramble(ref mut index) = self.message_info {
-                    *index += 1;
-                    if *index as usize >= RAMBLE_MESSAGES.len() {
-                        self.message_info = MessageInfo::NoMessage;
-                    }
-                } else {
-                    self.message_info = MessageInfo::Ramble(0);
+        }
+
+        for (i, exit) in map.exits.iter().enumerate() {
+            let Some(&target) = maps.get(exit.map as usize) else {
+                problems.push(Problem::UnknownMap { what: Exit(i), map: exit.map });
+                continue
+            };
+
+            // For edge exits, one of these is the position along the edge
+            // instead, which could be anything.
+            if let Trigger::At(..) = exit.trigger {
+                if !in_bounds(target, exit.x, exit.y) {
+                    problems.push(Problem::OffTheMap { what: Exit(i), x: exit.x, y: exit.y });
+                } else if let movement::Allowed::Not(_) = movement::allowed_to(target, &Entities::default(), exit.x, exit.y, 0) {
+                    problems.push(Problem::Blocked { what: Exit(i), x: exit.x, y: exit.y });
                 }
             }
-            Some(tile::EXCLAMATION_BUBBLE) => {
-                // If it is the special grave bubble
-                if target_x == self.map.special_grave_x
-                && target_y == self.map.special_grave_y {
-                    self.message_info = MessageInfo::PasswordReveal {
-                        index: 3,
-                    };
-                }
+        }
+
+        if !all_in_bounds {
+            // The rest of the checks rely on everything being on the map.
+            return problems
+        }
+
+        let entities = Entities::default();
+        for &(what, x, y, flags) in &standing {
+            if let movement::Allowed::Not(_) = movement::allowed_to(map, &entities, x, y, flags) {
+                problems.push(Problem::Blocked { what, x, y });
             }
-            Some(tile::GHOST_1) => {
-                self.message_info = MessageInfo::GhostOoo;
+        }
+
+        // The player and the mobs are entities, which cannot share a tile.
+        for (i, &(what, x, y, _)) in standing.iter().enumerate() {
+            if !matches!(what, Player | Mob(_)) {
+                continue
             }
-            Some(tile::CLOSED_DOOR) => {
-                self.add_entity(Entity {
-                    kind: tile::OPEN_DOOR,
-                    x: target_x,
-                    y: target_y,
-                });
+
+            if let Some(&(other, ..)) = standing[..i].iter()
+                .find(|&&(other, other_x, other_y, _)| matches!(other, Player | Mob(_)) && (other_x, other_y) == (x, y)) {
+                problems.push(Problem::SameSpot { what: other, other: what, x, y });
             }
-            Some(tile::OPEN_DOOR) => {
-                self.remove_entity(target_x, target_y);
+        }
+
+        // The keys and buttons are how the locked doors get opened, so they
+        // need to be reachable while the doors are still locked.
+        let before_unlocking = reachable(map, entrances, &locked_doors, false);
+
+        for &(what, x, y) in &keys {
+            if !touchable(map, &before_unlocking, x, y) {
+                problems.push(Problem::Unreachable { what, x, y });
             }
-            None => {}
-            _ => {}
         }
-    }
 
-    #[must_use]
-    fn entity_on_button(&mut self, x: X, y: Y) -> Option<SFX> {
-        let output = Some(SFX::ButtonPress);
+        for &(what, x, y, _) in &standing {
+            if matches!(what, Button { .. })
+            && !xy_to_i(map, x, y).is_some_and(|index| before_unlocking[index]) {
+                problems.push(Problem::Unreachable { what, x, y });
+            }
+        }
 
-        let button_count = self.password_lock.open.len() as ButtonCount;
-        for i in 0..button_count {
-            if self.password_lock.open[i] {
-                continue
+        let after_unlocking = reachable(map, entrances, &locked_doors, true);
+
+        let mut needed = Vec::new();
+        if is_start {
+            needed.push((SpecialGrave, map.special_grave_x, map.special_grave_y));
+        }
+        for (i, &kind) in map.tiles.iter().enumerate() {
+            let what = match kind {
+                tile::PORTAL => Portal,
+                tile::STAIRS_DOWN => Stairs,
+                _ => continue,
+            };
+            let (x, y) = i_to_xy(map, i);
+            needed.push((what, x, y));
+        }
+        for (i, exit) in map.exits.iter().enumerate() {
+            if let Trigger::At(x, y) = exit.trigger {
+                needed.push((Exit(i), x, y));
             }
+        }
 
-            if self.password_lock.xs[i] == x
-            && self.password_lock.ys[i] == y
-            {
-                if self.password_lock.press_count == i {
-                    self.password_lock.open[i] = true;
-                }
-                self.password_lock.press_count += 1;
+        for (what, x, y) in needed {
+            if !touchable(map, &after_unlocking, x, y) {
+                problems.push(Problem::Unreachable { what, x, y });
+            }
+        }
 
-                self.add_entity(Entity {
-                    kind: tile::BUTTON_DARK,
-                    x,
-                    y,
-                });
+        problems
+    }
+}
 
-                break
+#[cfg(feature = "editor")]
+pub mod editor {
+    //! A screen for editing the map the player is on, then saving it in the
+    //! format from the `map_format` crate. The game being played is left as it
+    //! was, so load the saved file to play on the edited map.
+    //!
+    //! The controls are:
+    //! * Start: Opens the editor during gameplay, and closes it again.
+    //! * The directions: Move the cursor.
+    //! * A: Picks up the object under the cursor, or puts down the one being
+    //!   carried. With nothing to pick up, paints with the brush instead.
+    //! * Select: Opens the picker, to choose the brush from every tile on the
+    //!   graphics sheet, the mobs, and buttons. In the picker, the directions
+    //!   move, and A picks.
+    //! * B and left: Undo. B and right: Redo.
+    //! * B and A: Removes the mob or button under the cursor.
+    //! * B and select: Saves.
+    //!
+    //! New buttons get added to the map's last lock, so locks themselves still
+    //! need to be added in the map file. The special grave and the locked doors
+    //! are where tiles are, so moving them moves those tiles too.
+    use super::*;
+    use lint::Object;
+    use std::borrow::Cow;
+    use map_format::{Coord, Description, Placement, Reference, Reward, TileProperties};
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Brush {
+        Tile(TileKind),
+        /// One of `maps::mobs::NAMES`.
+        Mob(&'static str),
+        Button,
+    }
+
+    impl Brush {
+        /// What to draw for it.
+        pub fn kind(self) -> TileKind {
+            match self {
+                Brush::Tile(kind) => kind,
+                Brush::Mob(name) => mob_kind(name),
+                Brush::Button => tile::BUTTON_DARK,
             }
         }
+    }
 
-        // If lock is open
-        if self.password_lock.open.iter().all(|&b| b) {
-            self.add_entity(Entity {
-                kind: tile::KEY,
-                x: self.map.key_x,
-                y: self.map.key_y,
-            });
+    fn mob_kind(name: &str) -> TileKind {
+        maps::mobs::by_name(name)
+            .and_then(|mob| mob.kinds.first().copied())
+            .unwrap_or(tile::EXCLAMATION_BUBBLE)
+    }
+
+    /// How many tiles there are on the graphics sheet.
+    const SHEET_TILE_COUNT: usize = 256;
+
+    pub const PALETTE_LEN: usize = SHEET_TILE_COUNT + maps::mobs::NAMES.len() + 1;
+
+    const PICKER_COLUMNS: usize = 16;
+    const PICKER_LEFT: X = xy::x(8);
+    const PICKER_TOP: Y = xy::y(3);
+
+    /// The brushes in the picker, in order: Every tile, then every mob, then
+    /// the button.
+    pub fn palette(index: usize) -> Option<Brush> {
+        let names = &maps::mobs::NAMES;
+
+        if index < SHEET_TILE_COUNT {
+            Some(Brush::Tile(index as TileKind))
+        } else if let Some(&name) = names.get(index - SHEET_TILE_COUNT) {
+            Some(Brush::Mob(name))
+        } else if index == SHEET_TILE_COUNT + names.len() {
+            Some(Brush::Button)
         } else {
-            // If all the buttons were pressed without unlocking
-            if self.password_lock.press_count >= button_count {
-                // Reset all the buttons because a mistake was made
-                // entering it.
-                self.password_lock.reset();
-
-                for i in 0..button_count {
-                    self.remove_entity(
-                        self.password_lock.xs[i],
-                        self.password_lock.ys[i],
-                    );
-                }
-            }
+            None
         }
+    }
 
-        output
+    fn palette_index(brush: Brush) -> usize {
+        (0..PALETTE_LEN)
+            .find(|&i| palette(i) == Some(brush))
+            .unwrap_or_default()
     }
 
-    #[must_use]
-    fn move_player(&mut self, dir: Dir) -> Option<SFX> {
-        let mut output = None;
+    /// Names for new buttons, used in this order.
+    const BUTTON_NAMES: [&str; 8] = [
+        "red", "green", "blue", "yellow", "white", "black", "purple", "orange",
+    ];
 
-        self.message_info = MessageInfo::NoMessage;
+    /// How many edits can be undone.
+    const UNDO_LIMIT: usize = 64;
 
-        match self.screen {
-            Screen::Gameplay => {},
-            Screen::Congraturation => return output,
+    fn coords(x: X, y: Y) -> (Coord, Coord) {
+        (x.usize() as Coord, y.usize() as Coord)
+    }
+
+    fn placement((x, y): (Coord, Coord), name: &str) -> Placement {
+        Placement { x, y, name: name.to_owned(), line: 0 }
+    }
+
+    /// The words for the properties on a `tile` line. The depth is only
+    /// written when it differs from the kind's built-in one.
+    fn property_words(kind: TileKind, properties: properties::Properties) -> Vec<String> {
+        let depth = properties::DEPTH_NAMES.into_iter()
+            .find(|&(_, depth)| depth == properties.depth && depth != properties::of(kind).depth)
+            .map(|(name, _)| format!("depth={name}"));
+
+        properties::FLAG_NAMES.into_iter()
+            .filter(|&(_, flag)| properties.has(flag))
+            .map(|(name, _)| name.to_owned())
+            .chain(properties.name.map(|name| format!("name={}", name.replace(' ', "_"))))
+            .chain(depth)
+            .collect()
+    }
+
+    pub struct Editor {
+        /// The map as it was when the editor opened. The edits are drawn on
+        /// top of it, rather than changing it.
+        pub base: Map,
+        pub description: Description,
+        pub cursor_x: X,
+        pub cursor_y: Y,
+        pub brush: Brush,
+        pub carrying: Option<Object>,
+        /// The index of the highlighted brush, while the picker is open.
+        pub picker: Option<usize>,
+        undo: Vec<Description>,
+        redo: Vec<Description>,
+        /// The edited tiles, and what to draw for each object, to lay over
+        /// `base`.
+        pub entities: Entities,
+        pub status: Cow<'static, [u8]>,
+        /// The text of the last save, until something takes it to write out.
+        pub saved: Option<String>,
+        /// The text the map was last loaded from or saved as, if there is one,
+        /// so saving can keep its comments.
+        source: Option<String>,
+        /// Counts frames, for blinking.
+        blink: u8,
+    }
+
+    impl Editor {
+        pub fn new(map: Map, source: Option<String>) -> Self {
+            let mut status: Cow<'static, [u8]> = b"editing. press select to pick a brush".into();
+
+            let mut lock_list = Vec::with_capacity(map.locks.len());
+            for lock in map.locks {
+                let reward = match lock.reward {
+                    locks::Reward::Key { x, y, door_x, door_y } => {
+                        let (x, y) = coords(x, y);
+                        let (door_x, door_y) = coords(door_x, door_y);
+                        Reward::Key { x, y, door_x, door_y }
+                    },
+                    locks::Reward::Action(timeline::Action::OpenDoor { x, y }) => {
+                        let (x, y) = coords(x, y);
+                        Reward::OpenDoor(x, y)
+                    },
+                    locks::Reward::Action(_) => {
+                        status = b"some locks cannot be written to a map file, so they were left out".into();
+                        continue
+                    },
+                };
+
+                lock_list.push(map_format::Lock {
+                    length: lock.length.into(),
+                    buttons: lock.buttons.iter()
+                        .map(|button| placement(coords(button.x, button.y), button.name))
+                        .collect(),
+                    reward,
+                    line: 0,
+                });
+            }
+
+            let mut mob_list = Vec::with_capacity(map.mobs.len());
+            for &(x, y, mob) in map.mobs {
+                match maps::mobs::name_of(mob) {
+                    Some(name) => mob_list.push(placement(coords(x, y), name)),
+                    None => {
+                        status = b"some mobs have no name to write to a map file, so they were left out".into();
+                    },
+                }
+            }
+
+            let exit_list = map.exits.iter()
+                .map(|exit| {
+                    use maps::exits::{Side, Trigger};
+
+                    let (x, y) = coords(exit.x, exit.y);
+                    map_format::Exit {
+                        trigger: match exit.trigger {
+                            Trigger::At(at_x, at_y) => {
+                                let (at_x, at_y) = coords(at_x, at_y);
+                                map_format::Trigger::At(at_x, at_y)
+                            },
+                            Trigger::Edge(side) => map_format::Trigger::Edge(match side {
+                                Side::Top => map_format::Side::Top,
+                                Side::Bottom => map_format::Side::Bottom,
+                                Side::Left => map_format::Side::Left,
+                                Side::Right => map_format::Side::Right,
+                            }),
+                        },
+                        map: exit.map.into(),
+                        x,
+                        y,
+                        line: 0,
+                    }
+                })
+                .collect();
+
+            let mut editor = Editor {
+                base: map,
+                description: Description {
+                    width: map.width.usize() as Coord,
+                    height: map.height.usize() as Coord,
+                    tiles: map.tiles.iter().map(|&kind| kind.into()).collect(),
+                    loop_length: map.loop_length,
+                    player: coords(map.player_x, map.player_y),
+                    special_grave: coords(map.special_grave_x, map.special_grave_y),
+                    dialogue: dialogue::name_of(map.dialogue)
+                        .map(|name| Reference { name: name.to_owned(), line: 0 }),
+                    locks: lock_list,
+                    mobs: mob_list,
+                    exits: exit_list,
+                    tile_properties: map.tile_properties.iter()
+                        .map(|&(kind, properties)| TileProperties {
+                            kind: kind.into(),
+                            properties: property_words(kind, properties),
+                            line: 0,
+                        })
+                        .collect(),
+                },
+                cursor_x: map.player_x,
+                cursor_y: map.player_y,
+                brush: Brush::Tile(tile::FLOOR),
+                carrying: None,
+                picker: None,
+                undo: Vec::new(),
+                redo: Vec::new(),
+                entities: Entities::default(),
+                status,
+                saved: None,
+                source,
+                blink: 0,
+            };
+
+            editor.refresh();
+
+            editor
         }
 
-        move_entity(
-            self.entities.player.x,
-            self.entities.player.y,
-            &mut self.entities,
-            self.map,
-            dir,
-        );
+        fn cursor(&self) -> (Coord, Coord) {
+            coords(self.cursor_x, self.cursor_y)
+        }
 
-        let (target_x, target_y) = xy_in_dir(dir, self.entities.player.x, self.entities.player.y);
+        fn index(&self, (x, y): (Coord, Coord)) -> Option<usize> {
+            let d = &self.description;
 
-        // if we would push the special grave for the first time
-        if target_x == self.map.special_grave_x
-        && target_y == self.map.special_grave_y
-        && get_effective_tile(self.map, &self.entities, target_x, target_y) == Some(tile::SPECIAL_GRAVE) {
-            let (pushed_x, pushed_y) = xy_in_dir(dir, target_x, target_y);
+            if x < d.width && y < d.height {
+                Some(y as usize * d.width as usize + x as usize)
+            } else {
+                None
+            }
+        }
 
-            self.add_entity(Entity {
-                kind: tile::SPECIAL_GRAVE,
-                x: pushed_x,
-                y: pushed_y,
-            });
+        /// Everything that can be picked up, with where it is. Those drawn on
+        /// top of the tiles come first, so they get picked up first.
+        fn objects(&self) -> Vec<(Object, (Coord, Coord))> {
+            let d = &self.description;
 
-            self.add_entity(Entity {
-                kind: tile::EXCLAMATION_BUBBLE,
-                x: target_x,
-                y: target_y,
-            });
+            let mut objects = vec![(Object::Player, d.player)];
+
+            for (i, mob) in d.mobs.iter().enumerate() {
+                objects.push((Object::Mob(i), (mob.x, mob.y)));
+            }
+
+            for (lock, l) in d.locks.iter().enumerate() {
+                for (button, b) in l.buttons.iter().enumerate() {
+                    objects.push((Object::Button { lock, button }, (b.x, b.y)));
+                }
+            }
+
+            for (lock, l) in d.locks.iter().enumerate() {
+                match l.reward {
+                    Reward::Key { x, y, door_x, door_y } => {
+                        objects.push((Object::Key(lock), (x, y)));
+                        objects.push((Object::LockedDoor(lock), (door_x, door_y)));
+                    },
+                    Reward::OpenDoor(x, y) => {
+                        objects.push((Object::LockedDoor(lock), (x, y)));
+                    },
+                }
+            }
+
+            objects.push((Object::SpecialGrave, d.special_grave));
+
+            objects
         }
 
-        match get_effective_tile_custom(self.map, &self.entities, self.entities.player.x, self.entities.player.y, NO_MOBS) {
-            Some(tile::PORTAL) => {
-                output = Some(SFX::CardPlace);
+        fn object_at(&self, at: (Coord, Coord)) -> Option<Object> {
+            self.objects().into_iter()
+                .find(|&(_, xy)| xy == at)
+                .map(|(object, _)| object)
+        }
 
-                self.reset_time();
+        fn position_mut(&mut self, object: Object) -> Option<(&mut Coord, &mut Coord)> {
+            let d = &mut self.description;
+
+            match object {
+                Object::Player => Some((&mut d.player.0, &mut d.player.1)),
+                Object::SpecialGrave => Some((&mut d.special_grave.0, &mut d.special_grave.1)),
+                Object::Mob(i) => d.mobs.get_mut(i).map(|mob| (&mut mob.x, &mut mob.y)),
+                Object::Button { lock, button } => {
+                    d.locks.get_mut(lock)?.buttons.get_mut(button).map(|b| (&mut b.x, &mut b.y))
+                },
+                Object::Key(lock) => match &mut d.locks.get_mut(lock)?.reward {
+                    Reward::Key { x, y, .. } => Some((x, y)),
+                    Reward::OpenDoor(..) => None,
+                },
+                Object::LockedDoor(lock) => match &mut d.locks.get_mut(lock)?.reward {
+                    Reward::Key { door_x, door_y, .. } => Some((door_x, door_y)),
+                    Reward::OpenDoor(x, y) => Some((x, y)),
+                },
+                Object::Portal | Object::Stairs | Object::Exit(_) => None,
             }
-            Some(tile::STAIRS_DOWN) => {
-                self.screen = Screen::Congraturation;
+        }
+
+        /// Rebuilds `entities` from the description.
+        fn refresh(&mut self) {
+            let map = self.base;
+            let mut entities = Entities::default();
+
+            for (i, &kind) in self.description.tiles.iter().enumerate() {
+                let kind = kind as TileKind;
+                if map.tiles.get(i) != Some(&kind) {
+                    let (x, y) = i_to_xy(map, i);
+                    // Edited tiles replace the map's own, like ground does.
+                    entities.insert(map, Depth::Ground, Entity { kind, x, y });
+                }
             }
-            Some(tile::KEY) => {
-                output = Some(SFX::CardSlide);
 
-                self.add_entity(Entity {
-                    kind: tile::DOOR_2,
-                    x: self.map.locked_door_x,
-                    y: self.map.locked_door_y,
-                });
+            for (object, (x, y)) in self.objects() {
+                let kind = match object {
+                    Object::Mob(i) => mob_kind(&self.description.mobs[i].name),
+                    Object::Button { .. } => tile::BUTTON_DARK,
+                    Object::Key(_) => tile::KEY,
+                    Object::Player => PLAYER_KIND,
+                    // These are drawn as the tiles they are on.
+                    Object::LockedDoor(_)
+                    | Object::SpecialGrave
+                    | Object::Portal
+                    | Object::Stairs
+                    | Object::Exit(_) => continue,
+                };
+
+                let entity = Entity { kind, x: xy::x(x as _), y: xy::y(y as _) };
+
+                if let Object::Player = object {
+                    entities.player = entity;
+                } else {
+                    entities.mobs.push(entity);
+                }
+            }
+
+            self.entities = entities;
+        }
+
+        /// Call before each edit, so it can be undone.
+        fn checkpoint(&mut self) {
+            if self.undo.len() >= UNDO_LIMIT {
+                self.undo.remove(0);
+            }
+            self.undo.push(self.description.clone());
+            self.redo.clear();
+        }
+
+        /// `world` and `index` are where the map being edited is, so that saving
+        /// can check it along with the maps it connects to.
+        pub fn frame(&mut self, input: Input, world: maps::World, index: MapIndex) {
+            self.blink = self.blink.wrapping_add(1);
+
+            let pressed = |button| input.pressed_this_frame(button);
+
+            if let Some(mut index) = self.picker {
+                if pressed(Button::UP) {
+                    index = index.checked_sub(PICKER_COLUMNS).unwrap_or(index);
+                } else if pressed(Button::DOWN) && index + PICKER_COLUMNS < PALETTE_LEN {
+                    index += PICKER_COLUMNS;
+                } else if pressed(Button::LEFT) {
+                    index = index.saturating_sub(1);
+                } else if pressed(Button::RIGHT) {
+                    index = (index + 1).min(PALETTE_LEN - 1);
+                }
+                self.picker = Some(index);
+
+                if pressed(Button::A) {
+                    if let Some(brush) = palette(index) {
+                        self.brush = brush;
+                    }
+                    self.picker = None;
+                    self.status = b"picked a brush".into();
+                } else if pressed(Button::SELECT) || pressed(Button::B) {
+                    self.picker = None;
+                    self.status = b"kept the brush".into();
+                }
+
+                return
+            }
+
+            if input.gamepad.contains(Button::B) {
+                if pressed(Button::LEFT) {
+                    self.undo();
+                } else if pressed(Button::RIGHT) {
+                    self.redo();
+                } else if pressed(Button::A) {
+                    self.remove();
+                } else if pressed(Button::SELECT) {
+                    self.save(world, index);
+                }
+
+                return
+            }
+
+            if pressed(Button::SELECT) {
+                self.picker = Some(palette_index(self.brush));
+                self.status = b"pick a brush with a".into();
+                return
+            }
+
+            if pressed(Button::UP) {
+                self.cursor_y -= H::ONE;
+            } else if pressed(Button::DOWN) {
+                self.cursor_y = (self.cursor_y + H::ONE).min(Y::ZERO + (self.base.height - H::ONE));
+            } else if pressed(Button::LEFT) {
+                self.cursor_x -= W::ONE;
+            } else if pressed(Button::RIGHT) {
+                self.cursor_x = (self.cursor_x + W::ONE).min(X::ZERO + (self.base.width - W::ONE));
+            }
+
+            if pressed(Button::A) {
+                self.act();
+            }
+        }
+
+        fn act(&mut self) {
+            let at = self.cursor();
+            let here = self.object_at(at);
+
+            if let Some(carried) = self.carrying {
+                match here {
+                    Some(other) if other != carried => {
+                        self.status = b"something is already there".into();
+                    },
+                    _ => {
+                        self.checkpoint();
+                        self.move_object(carried, at);
+                        self.carrying = None;
+                        self.refresh();
+                        self.status = b"put it down".into();
+                    },
+                }
+                return
+            }
+
+            if let Some(object) = here {
+                self.carrying = Some(object);
+                self.status = b"picked it up. press a to put it down".into();
+                return
+            }
+
+            self.paint(at);
+        }
+
+        fn move_object(&mut self, object: Object, to: (Coord, Coord)) {
+            let Some((x, y)) = self.position_mut(object) else { return };
+            let from = (*x, *y);
+            (*x, *y) = to;
+
+            if let Object::SpecialGrave | Object::LockedDoor(_) = object {
+                // These are where their tiles are, so bring the tile along.
+                if let (Some(from), Some(to)) = (self.index(from), self.index(to)) {
+                    self.description.tiles.swap(from, to);
+                }
+            }
+        }
+
+        fn paint(&mut self, at: (Coord, Coord)) {
+            match self.brush {
+                Brush::Tile(kind) => {
+                    let Some(i) = self.index(at) else { return };
+                    if self.description.tiles[i] == kind.into() {
+                        return
+                    }
+
+                    self.checkpoint();
+                    self.description.tiles[i] = kind.into();
+                    self.status = b"painted a tile".into();
+                },
+                Brush::Mob(name) => {
+                    self.checkpoint();
+                    self.description.mobs.push(placement(at, name));
+                    self.status = b"added a mob".into();
+                },
+                Brush::Button => {
+                    if self.description.locks.is_empty() {
+                        self.status = b"there is no lock to add the button to. add one in the map file".into();
+                        return
+                    }
+
+                    let count: usize = self.description.locks.iter()
+                        .map(|lock| lock.buttons.len())
+                        .sum();
+                    let name = BUTTON_NAMES.get(count)
+                        .map_or_else(|| format!("button_{}", count + 1), |&name| name.to_owned());
+
+                    self.checkpoint();
+                    if let Some(lock) = self.description.locks.last_mut() {
+                        lock.buttons.push(Placement { name, ..placement(at, "") });
+                    }
+                    self.status = b"added a button to the last lock".into();
+                },
+            }
+
+            self.refresh();
+        }
+
+        fn remove(&mut self) {
+            match self.object_at(self.cursor()) {
+                Some(Object::Mob(i)) => {
+                    self.checkpoint();
+                    self.description.mobs.remove(i);
+                    self.status = b"removed the mob".into();
+                },
+                Some(Object::Button { lock, button }) => {
+                    let l = &self.description.locks[lock];
+                    if l.buttons.len() as u32 <= l.length {
+                        self.status = b"a lock needs at least as many buttons as its length".into();
+                        return
+                    }
+
+                    self.checkpoint();
+                    self.description.locks[lock].buttons.remove(button);
+                    self.status = b"removed the button".into();
+                },
+                _ => {
+                    self.status = b"only mobs and buttons can be removed".into();
+                    return
+                },
+            }
+
+            // The indexes after the removed one have shifted.
+            self.carrying = None;
+            self.refresh();
+        }
+
+        fn undo(&mut self) {
+            let Some(description) = self.undo.pop() else {
+                self.status = b"nothing to undo".into();
+                return
+            };
+
+            self.redo.push(core::mem::replace(&mut self.description, description));
+            self.carrying = None;
+            self.refresh();
+            self.status = b"undid an edit".into();
+        }
+
+        fn redo(&mut self) {
+            let Some(description) = self.redo.pop() else {
+                self.status = b"nothing to redo".into();
+                return
+            };
+
+            self.undo.push(core::mem::replace(&mut self.description, description));
+            self.carrying = None;
+            self.refresh();
+            self.status = b"redid an edit".into();
+        }
+
+        /// Saves, unless `lint` finds problems with the edited map, in place of
+        /// the one at `index` in `world`. The first problem is shown instead.
+        fn save(&mut self, world: maps::World, index: MapIndex) {
+            let text = match &self.source {
+                Some(source) => map_format::rewrite(source, &self.description),
+                None => map_format::write(&self.description),
+            };
+
+            let Ok(text) = text else {
+                self.status = b"could not save, since there are too many kinds of tiles".into();
+                return
+            };
+
+            // Loading leaks the map, but saves are few and far between.
+            let problem = match maps::load(&text) {
+                Ok(map) => lint::lint_edited(world, index, Box::leak(Box::new(map)))
+                    .into_iter()
+                    .next()
+                    .map(|(map, problem)| format!("map {map}: {problem}")),
+                Err(err) => Some(err.to_string()),
+            };
+
+            if let Some(problem) = problem {
+                self.status = format!("not saved. {problem}").to_lowercase().into_bytes().into();
+                return
+            }
+
+            self.source = Some(text.clone());
+            self.saved = Some(text);
+            self.status = b"saved".into();
+        }
+
+        /// The cursor, in world coordinates. It blinks, and shows what it is
+        /// carrying, or else the brush.
+        pub fn cursor_tile(&self) -> Option<Tile> {
+            if self.blink & 0b1_0000 != 0 {
+                return None
+            }
+
+            let kind = match self.carrying {
+                Some(object) => {
+                    let (x, y) = self.objects().into_iter()
+                        .find(|&(o, _)| o == object)
+                        .map(|(_, xy)| xy)?;
+                    get_effective_tile(self.base, &self.entities, xy::x(x as _), xy::y(y as _))?
+                },
+                None => self.brush.kind(),
+            };
+
+            Some(Tile { kind, x: self.cursor_x, y: self.cursor_y })
+        }
+
+        /// The picker, in screen coordinates, with the highlighted brush
+        /// blinking.
+        pub fn picker_tiles(&self) -> Vec<Tile> {
+            let Some(highlighted) = self.picker else { return Vec::new() };
+
+            (0..PALETTE_LEN)
+                .filter(|&i| i != highlighted || self.blink & 0b1000 == 0)
+                .filter_map(|i| Some(Tile {
+                    kind: palette(i)?.kind(),
+                    x: PICKER_LEFT + xy::w((i % PICKER_COLUMNS) as _),
+                    y: PICKER_TOP + xy::h((i / PICKER_COLUMNS) as _),
+                }))
+                .collect()
+        }
+
+        pub fn write_hud(&self, prints: &mut [Print; 2]) {
+            use std::io::Write;
+
+            for print in prints.iter_mut() {
+                print.text = [0; 16];
+            }
+
+            let _ = write!(&mut prints[0].text[..], "edit {}, {}", self.cursor_x.usize(), self.cursor_y.usize());
+
+            let mut text = &mut prints[1].text[..];
+            let _ = match (self.carrying, self.picker.and_then(palette).unwrap_or(self.brush)) {
+                (Some(_), _) => write!(text, "carrying"),
+                (None, Brush::Tile(kind)) => match self.base.properties(kind).name {
+                    Some(name) => write!(text, "{name}"),
+                    None => write!(text, "tile {kind}"),
+                },
+                (None, Brush::Mob(name)) => write!(text, "{name}"),
+                (None, Brush::Button) => write!(text, "button"),
+            };
+        }
+    }
+}
+
+#[derive(Clone, Default, Hash, PartialEq, Eq)]
+pub enum MessageInfo {
+    #[default]
+    NoMessage,
+    PasswordReveal {
+        lock: LockId,
+        /// Which press of the lock's password.
+        index: ButtonIndex,
+    },
+    Timeline(TimelineIndex),
+    /// The message given as the reward for opening the lock.
+    LockReward(LockId),
+    Dialogue {
+        speaker: SpeakerIndex,
+        node: NodeIndex,
+        /// Which of the choices that are currently offered is selected.
+        choice: ChoiceIndex,
+    },
+}
+
+/// Something the player has learned. These are kept across time resets, since
+/// the player remembers them even though the world does not.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Fact {
+    PasswordReveal {
+        lock: LockId,
+        index: ButtonIndex,
+    },
+    SpecialGrave,
+    /// A dialogue node with the `Remember` effect, from the given map's dialogue.
+    Heard {
+        map: MapIndex,
+        node: NodeIndex,
+    },
+}
+
+impl MessageInfo {
+    fn fact(&self) -> Option<Fact> {
+        match *self {
+            MessageInfo::PasswordReveal { lock, index } => Some(Fact::PasswordReveal { lock, index }),
+            MessageInfo::NoMessage
+            | MessageInfo::Timeline(_)
+            | MessageInfo::LockReward(_)
+            // Dialogue nodes say whether they should be remembered themselves.
+            | MessageInfo::Dialogue { .. } => None,
+        }
+    }
+}
+
+pub type JournalIndex = usize;
+
+/// The facts the player has learned, in the order they learned them, along
+/// with any dialogue flags, which are not shown to the player.
+#[derive(Clone, Debug, Default, Hash)]
+pub struct Journal {
+    entries: Vec<Fact>,
+    flags: Vec<Flag>,
+}
+
+impl Journal {
+    /// Returns whether the fact was new.
+    pub fn learn(&mut self, fact: Fact) -> bool {
+        if self.knows(fact) {
+            return false
+        }
+
+        self.entries.push(fact);
+        true
+    }
+
+    pub fn knows(&self, fact: Fact) -> bool {
+        self.entries.contains(&fact)
+    }
+
+    pub fn entries(&self) -> &[Fact] {
+        &self.entries
+    }
+
+    pub fn set_flag(&mut self, flag: Flag) {
+        if !self.has_flag(flag) {
+            self.flags.push(flag);
+        }
+    }
+
+    pub fn has_flag(&self, flag: Flag) -> bool {
+        self.flags.contains(&flag)
+    }
+}
+
+/// The tiles the player has stood on, on each map, as one bit per tile. Like
+/// the journal, this is what the player remembers, so it is kept across time
+/// resets and rewinds.
+#[derive(Clone, Debug, Default, Hash)]
+pub struct Visited(Vec<Vec<u64>>);
+
+impl Visited {
+    fn visit(&mut self, map_index: MapIndex, map: Map, x: X, y: Y) {
+        let Some(index) = xy_to_i(map, x, y) else { return };
+        let map_index = map_index as usize;
+
+        if self.0.len() <= map_index {
+            self.0.resize(map_index + 1, Vec::new());
+        }
+        let bits = &mut self.0[map_index];
+        if bits.len() <= index / 64 {
+            bits.resize(index / 64 + 1, 0);
+        }
+
+        bits[index / 64] |= 1 << (index % 64);
+    }
+
+    pub fn has(&self, map_index: MapIndex, map: Map, x: X, y: Y) -> bool {
+        let Some(index) = xy_to_i(map, x, y) else { return false };
+
+        self.0.get(map_index as usize)
+            .and_then(|bits| bits.get(index / 64))
+            .is_some_and(|bits| bits >> (index % 64) & 1 == 1)
+    }
+}
+
+const FRAMES_PER_SECOND: u32 = 60;
+
+/// When to start warning the player that time is about to reset.
+const WARNING_FRAMES: u32 = 10 * FRAMES_PER_SECOND;
+
+const CLOCK_BAR_WIDTH: u8 = 8;
+
+/// A frame count as a `u32`, to compare with loop lengths, which can be one
+/// more than the largest frame count.
+// With the `wide-frame-count` feature, this conversion does nothing.
+#[allow(clippy::useless_conversion)]
+fn widen(frame_count: FrameCount) -> u32 {
+    u32::from(frame_count)
+}
+
+/// How long B has to be held on its own before rewinding starts, so there is
+/// time to press a direction with it, to pull something instead.
+const REWIND_DELAY_FRAMES: u8 = 8;
+
+/// How many snapshots to keep for rewinding. One is taken every 8 frames, so
+/// this is a bit over 8 seconds worth.
+const REWIND_SNAPSHOT_COUNT: usize = 64;
+
+/// Recent snapshots of the state, so the player can step back after a
+/// misstep. These are not kept across time resets, and are not saved.
+#[derive(Default)]
+pub struct Rewind {
+    snapshots: std::collections::VecDeque<Snapshot>,
+    held_frames: u8,
+}
+
+/// The parts of the state that can change from frame to frame within a loop.
+/// These are kept as they are, rather than saved, so that going back to one
+/// cannot fail.
+///
+/// The entities and the other maps' layers are copied whole each time. They
+/// only hold what moves, not the tiles, so that is a few kilobytes for the
+/// built-in maps. The recording and the echo's steps only grow or stay put
+/// within a loop, so just how far along they were is kept for those.
+struct Snapshot {
+    frame_count: FrameCount,
+    rng: Xs,
+    map_index: MapIndex,
+    map: Map,
+    screen: Screen,
+    entities: Entities,
+    password_locks: Vec<Vec<PasswordLock>>,
+    message_info: MessageInfo,
+    said: Vec<bool>,
+    mob_states: Vec<MobState>,
+    mob_kinds: Vec<TileKind>,
+    layers: Vec<Layer>,
+    timeline_fired: Vec<bool>,
+    recording_len: usize,
+    echo_next: usize,
+}
+
+impl Snapshot {
+    fn of(state: &State) -> Snapshot {
+        // Destructure so we get a compile error when a field is added.
+        let State {
+            frame_count,
+            rng,
+            // This stays the same for the whole game.
+            world: _,
+            map_index,
+            map,
+            screen,
+            entities,
+            password_locks,
+            // This stays the same for the whole game.
+            special_grave: _,
+            message_info,
+            // Snapshots are not kept across time resets.
+            loop_count: _,
+            said,
+            // This is derived from the rest of the state each frame.
+            hud_prints: _,
+            mob_states,
+            mob_kinds,
+            layers,
+            // Rewinding doesn't make the player forget things.
+            journal: _,
+            visited: _,
+            rewind: _,
+            timeline_fired,
+            recording,
+            echo,
+            // This is a cache, made from the rest of the state.
+            distance_fields: _,
+            // This is made from the rest of the state after each frame.
+            texts: _,
+            // The game does not change while editing.
+            #[cfg(feature = "editor")]
+            editor: _,
+        } = state;
+
+        Snapshot {
+            frame_count: *frame_count,
+            rng: *rng,
+            map_index: *map_index,
+            map,
+            screen: *screen,
+            entities: entities.clone(),
+            password_locks: password_locks.clone(),
+            message_info: message_info.clone(),
+            said: said.clone(),
+            mob_states: mob_states.clone(),
+            mob_kinds: mob_kinds.clone(),
+            layers: layers.clone(),
+            timeline_fired: timeline_fired.clone(),
+            recording_len: recording.len(),
+            echo_next: echo.next,
+        }
+    }
+
+    fn restore(self, state: &mut State) {
+        let Snapshot {
+            frame_count,
+            rng,
+            map_index,
+            map,
+            screen,
+            entities,
+            password_locks,
+            message_info,
+            said,
+            mob_states,
+            mob_kinds,
+            layers,
+            timeline_fired,
+            recording_len,
+            echo_next,
+        } = self;
+
+        state.frame_count = frame_count;
+        state.rng = rng;
+        state.map_index = map_index;
+        state.map = map;
+        state.screen = screen;
+        state.entities = entities;
+        state.password_locks = password_locks;
+        state.message_info = message_info;
+        state.said = said;
+        state.mob_states = mob_states;
+        state.mob_kinds = mob_kinds;
+        state.layers = layers;
+        state.timeline_fired = timeline_fired;
+        state.recording.truncate(recording_len);
+        state.echo.next = echo_next;
+    }
+}
+
+impl Rewind {
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
+
+/// What a mob that runs between landmarks is doing.
+#[derive(Clone, Debug, Default, Hash)]
+pub enum SeekState {
+    #[default]
+    PreSniffing, // This state exists to reduce sniffing indicator spam/flickering
+    Sniffing,
+    MovingTowards(X, Y),
+}
+
+#[derive(Clone, Debug, Default, Hash)]
+pub struct MobState {
+    /// Whether an oscillating mob is currently reversing its moves.
+    pub inverted: bool,
+    pub seek: SeekState,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum EchoAction {
+    Move(Dir),
+    Interact(Dir),
+    Pull(Dir),
+}
+
+/// Something the player did, and when they did it.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct EchoStep {
+    pub frame: FrameCount,
+    pub action: EchoAction,
+}
+
+/// Replays what the player did during the previous loop. The echo only opens
+/// and closes doors when interacting, since no one has anything to say to it.
+#[derive(Clone, Debug, Default, Hash)]
+pub struct Echo {
+    pub steps: Vec<EchoStep>,
+    /// The index of the next step to replay.
+    pub next: usize,
+}
+
+/// What is going on on one map during the current loop, apart from the player.
+#[derive(Clone, Default)]
+pub struct Layer {
+    pub dynamic: HashMap<usize, Stack>,
+    /// In the same order as the map's mobs.
+    pub mobs: Vec<Entity>,
+    pub echo: Option<Entity>,
+    pub mob_states: Vec<MobState>,
+    pub mob_kinds: Vec<TileKind>,
+    pub said: Vec<bool>,
+    pub timeline_fired: Vec<bool>,
+}
+
+impl core::hash::Hash for Layer {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        let Layer {
+            dynamic,
+            mobs,
+            echo,
+            mob_states,
+            mob_kinds,
+            said,
+            timeline_fired,
+        } = self;
+
+        hash_dynamic(dynamic, state);
+        mobs.hash(state);
+        echo.hash(state);
+        mob_states.hash(state);
+        mob_kinds.hash(state);
+        said.hash(state);
+        timeline_fired.hash(state);
+    }
+}
+
+impl Layer {
+    /// The map as it is at the start of a loop.
+    fn new(map: Map, mob_kinds: Vec<TileKind>) -> Self {
+        Layer {
+            dynamic: <_>::default(),
+            mobs: map.mobs.iter()
+                .zip(&mob_kinds)
+                .map(|(&(x, y, _), &kind)| Entity { kind, x, y })
+                .collect(),
+            echo: None,
+            mob_states: vec![MobState::default(); map.mobs.len()],
+            mob_kinds,
+            said: vec![false; map.dialogue.nodes.len()],
+            timeline_fired: vec![false; map.timeline.len()],
+        }
+    }
+}
+
+fn pick_mob_kinds(map: Map, rng: &mut Xs) -> Vec<TileKind> {
+    let mut mob_kinds = Vec::with_capacity(map.mobs.len());
+
+    for (_, _, mob) in map.mobs {
+        mob_kinds.push(match mob.kinds {
+            [] => tile::FLOOR,
+            [kind] => *kind,
+            kinds => kinds[xs::range(rng, 0..kinds.len() as _) as usize],
+        });
+    }
+
+    mob_kinds
+}
+
+/// The exit taken by walking off the edge of the map from `x`, `y` in `dir`,
+/// as the map to go to, and where on it to arrive, if there is one.
+fn edge_exit(map: Map, x: X, y: Y, dir: Dir) -> Option<(MapIndex, X, Y)> {
+    use maps::exits::{Side, Trigger};
+
+    let side = match dir {
+        Dir::Up if y.usize() == 0 => Side::Top,
+        Dir::Down if y.usize() + 1 >= map.height.usize() => Side::Bottom,
+        Dir::Left if x.usize() == 0 => Side::Left,
+        Dir::Right if x.usize() + 1 >= map.width.usize() => Side::Right,
+        _ => return None,
+    };
+
+    map.exits.iter()
+        .find(|exit| exit.trigger == Trigger::Edge(side))
+        .map(|exit| match side {
+            Side::Top | Side::Bottom => (exit.map, x, exit.y),
+            Side::Left | Side::Right => (exit.map, exit.x, y),
+        })
+}
+
+/// The exit taken by stepping onto `x`, `y`, as the map to go to, and where on
+/// it to arrive, if there is one.
+fn tile_exit(map: Map, x: X, y: Y) -> Option<(MapIndex, X, Y)> {
+    map.exits.iter()
+        .find(|exit| exit.trigger == maps::exits::Trigger::At(x, y))
+        .map(|exit| (exit.map, exit.x, exit.y))
+}
+
+pub struct State {
+    pub frame_count: FrameCount,
+    pub rng: Xs,
+    pub world: maps::World,
+    /// The index of `map` in the world.
+    pub map_index: MapIndex,
+    /// The map the player is on.
+    pub map: Map,
+    pub screen: Screen,
+    pub entities: Entities,
+    /// One list for each map in the world, with one entry for each of that
+    /// map's locks. Unlike the rest of what happens on a map, these are not in
+    /// the layers, since the passwords stay the same across time resets.
+    pub password_locks: Vec<Vec<PasswordLock>>,
+    /// Where the special grave of the world's starting map is. Like the
+    /// passwords, this is picked when the game starts, and kept across time
+    /// resets.
+    pub special_grave: (X, Y),
+    pub message_info: MessageInfo,
+    /// How many times time has reset this game.
+    pub loop_count: LoopCount,
+    /// Which of the map's dialogue nodes have been said this loop.
+    pub said: Vec<bool>,
+    pub hud_prints: [Print; 2],
+    /// In the same order as the map's mobs.
+    pub mob_states: Vec<MobState>,
+    /// The kind picked for each of the map's mobs, at the start of the game.
+    pub mob_kinds: Vec<TileKind>,
+    /// One for each map in the world. The entry for the current map is left
+    /// empty, since what is going on there is in the rest of the state.
+    pub layers: Vec<Layer>,
+    pub journal: Journal,
+    pub visited: Visited,
+    pub rewind: Rewind,
+    /// Which of the map's timeline events have happened this loop.
+    pub timeline_fired: Vec<bool>,
+    /// What the player has done so far this loop.
+    pub recording: Vec<EchoStep>,
+    pub echo: Echo,
+    /// For mobs that pathfind, shared between them.
+    pub distance_fields: movement::DistanceFields,
+    /// Made from the rest of the state at the end of each frame.
+    texts: Texts,
+    /// The editor, once it has been opened. It is kept while playing, so the
+    /// edits are still there when coming back to it.
+    #[cfg(feature = "editor")]
+    pub editor: Option<editor::Editor>,
+}
+
+impl State {
+    pub fn new(seed: Seed) -> State {
+        Self::new_in_world(seed, maps::WORLD)
+    }
+
+    /// Starts a game in a world with just this map in it. The world's list of
+    /// maps gets leaked, so this is meant for tests and tools, not for calling
+    /// every frame.
+    pub fn new_on_map(seed: Seed, map: Map) -> State {
+        Self::new_in_world(seed, maps::World {
+            maps: Box::leak(Box::new([map])),
+            start: 0,
+        })
+    }
+
+    pub fn new_in_world(seed: Seed, world: maps::World) -> State {
+        let mut rng = xs::from_seed(seed);
+
+        let mob_kinds = world.maps.iter()
+            .map(|&map| pick_mob_kinds(map, &mut rng))
+            .collect();
+
+        Self::new_inner(rng, world, mob_kinds)
+    }
+
+    /// `mob_kinds` has a list for each map in the world.
+    fn new_inner(mut rng: Xs, world: maps::World, mob_kinds: Vec<Vec<TileKind>>) -> State {
+        let map = world.start_map();
+
+        let mut entities = Entities::default();
+
+        entities.player = Entity {
+            kind: PLAYER_KIND,
+            x: map.player_x,
+            y: map.player_y,
+        };
+
+        let mut state = State {
+            frame_count: 0,
+            rng,
+            world,
+            map_index: world.start,
+            map,
+            screen: Screen::default(),
+            entities,
+            password_locks: new_password_locks(world, &mut rng),
+            special_grave: pick_special_grave(map, &mut rng),
+            message_info: MessageInfo::default(),
+            loop_count: 0,
+            said: <_>::default(),
+            hud_prints: <_>::default(),
+            mob_states: <_>::default(),
+            mob_kinds: <_>::default(),
+            layers: world.maps.iter()
+                .zip(mob_kinds)
+                .map(|(&map, kinds)| Layer::new(map, kinds))
+                .collect(),
+            journal: <_>::default(),
+            visited: <_>::default(),
+            rewind: <_>::default(),
+            timeline_fired: <_>::default(),
+            recording: <_>::default(),
+            echo: <_>::default(),
+            distance_fields: <_>::default(),
+            texts: <_>::default(),
+            #[cfg(feature = "editor")]
+            editor: None,
+        };
+
+        state.swap_layer(world.start);
+        state.visited.visit(world.start, map, map.player_x, map.player_y);
+
+        state
+    }
+
+    /// Swaps what is going on on the current map with the given map's layer.
+    /// Swapping with the same layer twice puts everything back.
+    fn swap_layer(&mut self, index: MapIndex) {
+        use core::mem::swap;
+
+        let Some(layer) = self.layers.get_mut(index as usize) else { return };
+
+        swap(&mut self.entities.dynamic, &mut layer.dynamic);
+        swap(&mut self.entities.mobs, &mut layer.mobs);
+        swap(&mut self.entities.echo, &mut layer.echo);
+        swap(&mut self.mob_states, &mut layer.mob_states);
+        swap(&mut self.mob_kinds, &mut layer.mob_kinds);
+        swap(&mut self.said, &mut layer.said);
+        swap(&mut self.timeline_fired, &mut layer.timeline_fired);
+    }
+
+    /// Moves the player to `x`, `y` on the given map.
+    fn enter_map(&mut self, index: MapIndex, x: X, y: Y) {
+        let Some(&map) = self.world.maps.get(index as usize) else { return };
+
+        self.swap_layer(self.map_index);
+        self.swap_layer(index);
+        self.map_index = index;
+        self.map = map;
+
+        self.entities.player.x = xy::x(x.usize().min(map.width.usize().saturating_sub(1)) as _);
+        self.entities.player.y = xy::y(y.usize().min(map.height.usize().saturating_sub(1)) as _);
+
+        // Whoever the player was talking to is back on the other map.
+        self.message_info = MessageInfo::NoMessage;
+    }
+
+    fn reset_time(&mut self) {
+        let mut password_locks = core::mem::take(&mut self.password_locks);
+        // Retain the combinations for this game across resets.
+        for lock in password_locks.iter_mut().flatten() {
+            lock.reset();
+        }
+
+        let special_grave = self.special_grave;
+
+        // The player remembers what they learned, even if no one else does.
+        let journal = core::mem::take(&mut self.journal);
+        let visited = core::mem::take(&mut self.visited);
+        let loop_count = self.loop_count.saturating_add(1);
+        let recording = core::mem::take(&mut self.recording);
+        #[cfg(feature = "editor")]
+        let editor = self.editor.take();
+
+        // Put the current map back with the others, so they all reset together.
+        self.swap_layer(self.map_index);
+        let mob_kinds = self.layers.iter_mut()
+            .map(|layer| core::mem::take(&mut layer.mob_kinds))
+            .collect();
+
+        // New seed for the rng, so different resets are slightly different.
+        *self = State::new_inner(
+            xs::from_seed(xs::new_seed(&mut self.rng)),
+            self.world,
+            mob_kinds,
+        );
+
+        self.password_locks = password_locks;
+        self.special_grave = special_grave;
+        self.journal = journal;
+        self.visited = visited;
+        self.loop_count = loop_count;
+        #[cfg(feature = "editor")]
+        {
+            self.editor = editor;
+        }
+
+        if !recording.is_empty() {
+            self.entities.echo = Some(Entity {
+                kind: tile::ECHO,
+                x: self.map.player_x,
+                y: self.map.player_y,
+            });
+            self.echo = Echo { steps: recording, next: 0 };
+        }
+    }
+
+    /// Where the special grave of the current map is.
+    fn special_grave(&self) -> (X, Y) {
+        if self.map_index == self.world.start {
+            self.special_grave
+        } else {
+            (self.map.special_grave_x, self.map.special_grave_y)
+        }
+    }
+
+    pub fn knows(&self, fact: Fact) -> bool {
+        self.journal.knows(fact)
+    }
+
+    /// Opens the editor on the current map, keeping any earlier edits to it.
+    #[cfg(feature = "editor")]
+    pub fn open_editor(&mut self) {
+        if !self.editor.as_ref().is_some_and(|editor| core::ptr::eq(editor.base, self.map)) {
+            self.editor = Some(editor::Editor::new(self.map, maps::source(self.map).map(str::to_owned)));
+        }
+
+        self.screen = Screen::Editor;
+        self.refresh_texts();
+    }
+
+    /// Starts a game on the map in `text`, in the format from the `map_format`
+    /// crate, with the editor open on it.
+    #[cfg(feature = "editor")]
+    pub fn new_editing(seed: Seed, text: &str) -> Result<State, maps::LoadError> {
+        let map: Map = Box::leak(Box::new(maps::load(text)?));
+
+        let mut state = Self::new_on_map(seed, map);
+        state.editor = Some(editor::Editor::new(map, Some(text.to_owned())));
+        state.open_editor();
+
+        Ok(state)
+    }
+
+    /// The text of the map, if it was saved in the editor since the last call.
+    #[cfg(feature = "editor")]
+    pub fn take_editor_save(&mut self) -> Option<String> {
+        self.editor.as_mut()?.saved.take()
+    }
+
+    pub fn frame(&mut self, input: Input, speaker: &mut Speaker) {
+        self.frame_inner(input, speaker);
+        self.refresh_texts();
+    }
+
+    fn frame_inner(&mut self, input: Input, speaker: &mut Speaker) {
+        #[cfg(feature = "editor")]
+        {
+            if input.pressed_this_frame(Button::START) {
+                match self.screen {
+                    Screen::Editor => {
+                        self.screen = Screen::Gameplay;
+                        return
+                    },
+                    Screen::Gameplay => {
+                        self.open_editor();
+                        return
+                    },
+                    // Closing the editor goes back to gameplay, so it only
+                    // opens from there.
+                    _ => {},
+                }
+            }
+
+            if let (Screen::Editor, Some(editor)) = (self.screen, &mut self.editor) {
+                // The world is paused while editing.
+                editor.frame(input, self.world, self.map_index);
+                editor.write_hud(&mut self.hud_prints);
+                return
+            }
+        }
+
+        if input.pressed_this_frame(Button::SELECT) {
+            self.screen = match self.screen {
+                Screen::Gameplay => Screen::Journal { scroll: 0 },
+                Screen::Journal { .. } => {
+                    let (x, y) = overview_start(self.map, self.entities.player.x, self.entities.player.y);
+                    Screen::Overview { x, y, show_visited: false }
+                },
+                Screen::Overview { .. } => Screen::Gameplay,
+                Screen::Congraturation => Screen::Congraturation,
+                #[cfg(feature = "editor")]
+                Screen::Editor => Screen::Editor,
+            };
+        }
+
+        if let Screen::Journal { ref mut scroll } = self.screen {
+            // The world is paused while the journal is open.
+            let max_scroll = self.journal.entries().len().saturating_sub(1);
+
+            if input.pressed_this_frame(Button::UP) {
+                *scroll = scroll.saturating_sub(1);
+            } else if input.pressed_this_frame(Button::DOWN) {
+                *scroll = (*scroll + 1).min(max_scroll);
+            }
+
+            return
+        }
+
+        if let Screen::Overview { ref mut x, ref mut y, ref mut show_visited } = self.screen {
+            // The world is paused while looking at the map, too.
+            let (max_x, max_y) = overview_max(self.map);
+
+            if input.gamepad.contains(Button::LEFT) {
+                *x -= W::ONE;
+            } else if input.gamepad.contains(Button::RIGHT) {
+                *x = (*x + W::ONE).min(max_x);
+            }
+
+            if input.gamepad.contains(Button::UP) {
+                *y -= H::ONE;
+            } else if input.gamepad.contains(Button::DOWN) {
+                *y = (*y + H::ONE).min(max_y);
+            }
+
+            if input.pressed_this_frame(Button::A) {
+                *show_visited = !*show_visited;
+            }
+
+            return
+        }
+
+        if let Screen::Gameplay = self.screen {
+            let direction_held = [Button::UP, Button::DOWN, Button::LEFT, Button::RIGHT]
+                .into_iter()
+                .any(|button| input.gamepad.contains(button));
+
+            // B walks away from a choice, before it starts rewinding.
+            if input.pressed_this_frame(Button::B) && self.offered_choices().next().is_some() {
+                self.message_info = MessageInfo::NoMessage;
+            }
+
+            // B with a direction pulls things instead, and starts the delay
+            // before rewinding over.
+            if direction_held || !input.gamepad.contains(Button::B) {
+                self.rewind.held_frames = 0;
+            } else {
+                // Step back one snapshot every few frames, so the player
+                // can see time going backwards and let go at the right moment.
+                if self.rewind.held_frames >= REWIND_DELAY_FRAMES
+                && (self.rewind.held_frames - REWIND_DELAY_FRAMES) & 0b11 == 0 {
+                    self.restore_snapshot();
+                }
+                self.rewind.held_frames = self.rewind.held_frames.checked_add(1)
+                    .unwrap_or(REWIND_DELAY_FRAMES);
+
+                self.write_hud();
+                return
+            }
+
+            if self.frame_count & 0b111 == 0 {
+                if self.rewind.snapshots.len() >= REWIND_SNAPSHOT_COUNT {
+                    self.rewind.snapshots.pop_front();
+                }
+                self.rewind.snapshots.push_back(Snapshot::of(self));
+            }
+
+            self.run_timeline(speaker);
+        }
+
+        self.move_mobs(speaker);
+
+        self.replay_echo(speaker);
+
+        let mut sfx_opt = None;
+
+        if self.offered_choices().next().is_none() {
+            let dir = if input.pressed_this_frame(Button::UP) {
+                Some(Dir::Up)
+            } else if input.pressed_this_frame(Button::DOWN) {
+                Some(Dir::Down)
+            } else if input.pressed_this_frame(Button::LEFT) {
+                Some(Dir::Left)
+            } else if input.pressed_this_frame(Button::RIGHT) {
+                Some(Dir::Right)
+            } else {
+                None
+            };
+
+            sfx_opt = dir.and_then(|dir| {
+                if input.gamepad.contains(Button::B) {
+                    self.pull_player(dir)
+                } else {
+                    self.move_player(dir)
+                }
+            });
+
+            if input.pressed_this_frame(Button::A) {
+                if input.gamepad.contains(Button::UP) {
+                    self.interact(Dir::Up)
+                } else if input.gamepad.contains(Button::DOWN) {
+                    self.interact(Dir::Down)
+                } else if input.gamepad.contains(Button::LEFT) {
+                    self.interact(Dir::Left)
+                } else if input.gamepad.contains(Button::RIGHT) {
+                    self.interact(Dir::Right)
+                }
+            }
+        } else {
+            // The player picks with up, down and A, instead of moving around.
+            self.choose(input);
+        }
+
+        if let Some(sfx) = sfx_opt {
+            speaker.request_sfx(sfx);
+        }
+
+        self.visited.visit(self.map_index, self.map, self.entities.player.x, self.entities.player.y);
+
+        if let Screen::Congraturation = self.screen {
+        } else {
+            match self.frame_count.checked_add(1) {
+                Some(count) if widen(count) < self.loop_length() => {
+                    self.frame_count = count;
+
+                    let frames_left = self.frames_left();
+                    if frames_left <= WARNING_FRAMES
+                    && frames_left.is_multiple_of(FRAMES_PER_SECOND) {
+                        speaker.request_sfx(SFX::TimeWarning);
+                    }
+                },
+                // Either the loop is over, or there are no more frames to
+                // count, which the loop length allows for.
+                _ => self.reset_time(),
+            }
+        }
+
+        self.write_hud();
+    }
+
+    /// How many frames are left until time resets.
+    pub fn frames_left(&self) -> u32 {
+        self.loop_length().saturating_sub(widen(self.frame_count))
+    }
+
+    /// Time resets everywhere at once, so this is the starting map's.
+    fn loop_length(&self) -> u32 {
+        self.world.start_map().loop_length
+    }
+
+    fn write_hud(&mut self) {
+        use std::io::Write;
+        let _ = write!(&mut self.hud_prints[0].text[..], "{} ({}, {})", self.frame_count, self.entities.player.x.usize(), self.entities.player.y.usize());
+
+        // The clock: the time left, then a bar that empties as the loop goes on.
+        let frames_left = self.frames_left();
+        let seconds_left = frames_left.div_ceil(FRAMES_PER_SECOND);
+        let loop_length = self.loop_length().max(1);
+
+        let clock = &mut self.hud_prints[1];
+        clock.text = [0; 16];
+        clock.y = unscaled::Y(8);
+
+        let mut text = &mut clock.text[..];
+        let _ = write!(text, "{}:{:02} [", seconds_left / 60, seconds_left % 60);
+
+        let filled = (u64::from(frames_left) * u64::from(CLOCK_BAR_WIDTH)).div_ceil(u64::from(loop_length));
+        // In the final seconds, blink the bar, as a warning.
+        let blink = frames_left <= WARNING_FRAMES
+            && self.frame_count & 0b1_0000 == 0;
+
+        for i in 0..u64::from(CLOCK_BAR_WIDTH) {
+            let _ = text.write_all(if i < filled && !blink { b"#" } else { b"." });
+        }
+        let _ = text.write_all(b"]");
+    }
+
+    fn restore_snapshot(&mut self) {
+        if let Some(snapshot) = self.rewind.snapshots.pop_back() {
+            snapshot.restore(self);
+        }
+    }
+
+    /// Puts the entity at its kind's depth, replacing only what was already
+    /// there at that depth. Does nothing if the entity is off the map, since
+    /// it would have nowhere to go.
+    fn add_entity(&mut self, entity: Entity) {
+        let depth = self.map.properties(entity.kind).depth;
+
+        self.entities.insert(self.map, depth, entity);
+    }
+
+    /// Removes the topmost dynamic entity, revealing whatever is below it.
+    fn remove_entity(&mut self, x: X, y: Y) -> Option<Entity> {
+        self.entities.remove_top(self.map, x, y)
+            .map(|(_, entity)| entity)
+    }
+
+    /// Removes the dynamic entity of that kind, leaving any others on the
+    /// tile alone.
+    fn remove_kind(&mut self, x: X, y: Y, kind: TileKind) -> Option<Entity> {
+        let stack = self.entities.stack(self.map, x, y)?;
+        let depth = Depth::ALL.into_iter()
+            .find(|&depth| stack.get(depth).is_some_and(|entity| entity.kind == kind))?;
+
+        self.entities.remove(self.map, x, y, depth)
+    }
+
+    /// Returns the tile after any entities have replaced it, as opposed to the initial set of tiles.
+    fn get_effective_tile(&mut self, x: X, y: Y) -> Option<TileKind> {
+        get_effective_tile(self.map, &self.entities, x, y)
+    }
+
+    fn interact(&mut self, dir: Dir) {
+        self.recording.push(EchoStep {
+            frame: self.frame_count,
+            action: EchoAction::Interact(dir),
+        });
+
+        let Some((target_x, target_y)) = xy_in_dir(dir, self.entities.player.x, self.entities.player.y) else {
+            return
+        };
+
+        let target_tile = self.get_effective_tile(target_x, target_y);
+
+        let speaker = self.map.dialogue.speakers.iter()
+            .position(|speaker| match speaker.who {
+                dialogue::Who::Kind(kind) => target_tile == Some(kind),
+                dialogue::Who::At(x, y) => x == target_x && y == target_y,
+            });
+
+        if let Some(speaker) = speaker {
+            self.talk(speaker as SpeakerIndex);
+            return
+        }
+
+        if !target_tile.is_some_and(|kind| self.map.properties(kind).has(properties::INTERACTABLE)) {
+            return
+        }
+
+        match target_tile {
+            // If it is the special grave bubble
+            Some(tile::EXCLAMATION_BUBBLE) if (target_x, target_y) == self.special_grave() => {
+                // The grave tells the last press of the map's first lock.
+                let lock = LockId { map: self.map_index, lock: 0 };
+                if let Some(password_lock) = self.password_lock(lock) {
+                    self.message_info = MessageInfo::PasswordReveal {
+                        lock,
+                        index: password_lock.sequence.len().saturating_sub(1) as ButtonIndex,
+                    };
+                }
+                self.journal.learn(Fact::SpecialGrave);
+            }
+            Some(tile::CLOSED_DOOR | tile::OPEN_DOOR) => {
+                self.use_door(target_x, target_y);
+            }
+            None => {}
+            _ => {}
+        }
+
+        if let Some(fact) = self.message_info.fact() {
+            self.journal.learn(fact);
+        }
+    }
+
+    /// Opens the door if it is closed, and closes it if it is open.
+    fn use_door(&mut self, x: X, y: Y) {
+        match self.get_effective_tile(x, y) {
+            Some(tile::CLOSED_DOOR) => {
+                self.add_entity(Entity {
+                    kind: tile::OPEN_DOOR,
+                    x,
+                    y,
+                });
+            }
+            Some(tile::OPEN_DOOR) => {
+                self.remove_kind(x, y, tile::OPEN_DOOR);
+            }
+            _ => {}
+        }
+    }
+
+    fn replay_echo(&mut self, speaker: &mut Speaker) {
+        while let Some(step) = self.echo.steps.get(self.echo.next) {
+            if step.frame > self.frame_count {
+                break
+            }
+            let action = step.action;
+            self.echo.next += 1;
+
+            // The echo may be on a map the player is not on, in which case
+            // it misses what it would have done here.
+            let Some(echo) = &self.entities.echo else { continue };
+            let (x, y) = (echo.x, echo.y);
+
+            match action {
+                EchoAction::Move(dir) | EchoAction::Pull(dir) => {
+                    // The echo cannot follow the player's steps on other maps,
+                    // so it fades away instead of leaving this one.
+                    if edge_exit(self.map, x, y, dir).is_some() {
+                        self.entities.echo = None;
+                        continue
+                    }
+
+                    let planned = if let EchoAction::Pull(_) = action {
+                        movement::plan_pull(x, y, self.map, &self.entities, dir, 0)
+                    } else {
+                        movement::plan_custom(x, y, self.map, &self.entities, dir, 0)
+                    };
+
+                    // The echo starts on the same tile as the player, so it
+                    // gets moved directly, instead of by position.
+                    let moved = match planned {
+                        Ok(planned) => movement::perform_pushes(&mut self.entities, self.map, planned),
+                        // The player may have been blocked the same way, so
+                        // the echo just stays put.
+                        Err(_) => None,
+                    };
+                    if let Some((new_x, new_y)) = moved {
+                        if let Some(echo) = &mut self.entities.echo {
+                            echo.x = new_x;
+                            echo.y = new_y;
+                        }
+
+                        if tile_exit(self.map, new_x, new_y).is_some() {
+                            self.entities.echo = None;
+                            continue
+                        }
+
+                        self.button_check(new_x, new_y, speaker);
+                    }
+                },
+                EchoAction::Interact(dir) => {
+                    if let Some((target_x, target_y)) = xy_in_dir(dir, x, y) {
+                        self.use_door(target_x, target_y);
+                    }
+                },
+            }
+        }
+    }
+
+    fn talk(&mut self, speaker: SpeakerIndex) {
+        let next = match self.message_info {
+            MessageInfo::Dialogue { speaker: current, node, .. } if current == speaker => {
+                self.map.dialogue.nodes.get(node as usize)
+                    .map_or(dialogue::Next::Start, |node| node.next)
+            },
+            _ => dialogue::Next::Start,
+        };
+
+        let node = match next {
+            dialogue::Next::Start => {
+                self.map.dialogue.speakers.get(speaker as usize)
+                    .and_then(|s| s.entries.iter().find(|entry| self.all_hold(entry.conditions)))
+                    .map(|entry| entry.node)
+            },
+            dialogue::Next::Node(node) => Some(node),
+            dialogue::Next::Close => None,
+        };
+
+        match node {
+            Some(node) => self.say(speaker, node),
+            None => self.message_info = MessageInfo::NoMessage,
+        }
+    }
+
+    fn say(&mut self, speaker: SpeakerIndex, node: NodeIndex) {
+        self.message_info = MessageInfo::Dialogue { speaker, node, choice: 0 };
+
+        if let Some(said) = self.said.get_mut(node as usize) {
+            *said = true;
+        }
+
+        let Some(effects) = self.map.dialogue.nodes.get(node as usize).map(|n| n.effects) else {
+            return
+        };
+
+        for &effect in effects {
+            match effect {
+                dialogue::Effect::Remember => {
+                    self.journal.learn(Fact::Heard { map: self.map_index, node });
+                },
+                dialogue::Effect::SetFlag(flag) => {
+                    self.journal.set_flag(flag);
+                },
+                dialogue::Effect::Give { kind, x, y } => {
+                    self.add_entity(Entity { kind, x, y });
+                },
+            }
+        }
+    }
+
+    fn all_hold(&self, conditions: &[dialogue::Condition]) -> bool {
+        use dialogue::Condition::*;
+
+        let said = |node: NodeIndex| self.said.get(node as usize) == Some(&true);
+
+        conditions.iter().all(|&condition| match condition {
+            Knows(node) => self.knows(Fact::Heard { map: self.map_index, node }),
+            DoesNotKnow(node) => !self.knows(Fact::Heard { map: self.map_index, node }),
+            Said(node) => said(node),
+            NotSaid(node) => !said(node),
+            Flag(flag) => self.journal.has_flag(flag),
+            NotFlag(flag) => !self.journal.has_flag(flag),
+            LoopAtLeast(count) => self.loop_count >= count,
+            During(frames) => frames.contains(self.frame_count),
+        })
+    }
+
+    /// The choices currently on screen, if any.
+    fn offered_choices(&self) -> impl Iterator<Item = &'static dialogue::Choice> + '_ {
+        let choices = match self.message_info {
+            MessageInfo::Dialogue { node, .. } => self.map.dialogue.nodes
+                .get(node as usize)
+                .map_or(&[][..], |node| node.choices),
+            _ => &[],
+        };
+
+        choices.iter().filter(|choice| self.all_hold(choice.conditions))
+    }
+
+    fn choose(&mut self, input: Input) {
+        let MessageInfo::Dialogue { speaker, choice, .. } = self.message_info else {
+            return
+        };
+
+        let last = self.offered_choices().count().saturating_sub(1) as ChoiceIndex;
+        // Time may have passed since the choices were offered.
+        let mut choice = choice.min(last);
+
+        if input.pressed_this_frame(Button::UP) {
+            choice = choice.saturating_sub(1);
+        } else if input.pressed_this_frame(Button::DOWN) {
+            choice = (choice + 1).min(last);
+        } else if input.pressed_this_frame(Button::A) {
+            let picked = self.offered_choices().nth(choice as usize);
+            if let Some(picked) = picked {
+                self.say(speaker, picked.node);
+                return
+            }
+        }
+
+        if let MessageInfo::Dialogue { choice: ref mut current, .. } = self.message_info {
+            *current = choice;
+        }
+    }
+
+    /// Moves all the mobs at once. Each mob plans its move against the world
+    /// as it was at the start of the tick, then the moves are resolved against
+    /// each other, and all performed together, so the outcome does not depend
+    /// on the order of the mobs.
+    fn move_mobs(&mut self, speaker: &mut Speaker) {
+        use maps::mobs::Behaviour;
+
+        self.distance_fields.refresh(self.map, &self.entities);
+
+        // Drawn once a tick, whatever the mobs do with it, so that the
+        // randomness each mob gets does not depend on the other mobs.
+        let seed = xs::new_seed(&mut self.rng);
+
+        let mut movers = Vec::with_capacity(self.map.mobs.len());
+        let mut intents = Vec::with_capacity(self.map.mobs.len());
+        for i in 0..self.map.mobs.len() {
+            let Some(entity) = self.entities.mobs.get(i) else { continue };
+            let (x, y) = (entity.x, entity.y);
+
+            if let Some(intent) = self.mob_intent(i, seed) {
+                movers.push((i, x, y));
+                intents.push(intent);
+            }
+        }
+
+        movement::resolve(&mut intents);
+
+        let mut moved = Vec::with_capacity(intents.len());
+        for intent in intents {
+            moved.push(match intent {
+                Ok(planned) if !planned.is_empty() => {
+                    let performed = movement::perform(&mut self.entities, self.map, planned);
+                    // The plans were all made from these entities, and
+                    // resolving keeps them from getting in each other's way.
+                    debug_assert!(performed.is_ok(), "{performed:?}");
+                    Some(true)
+                },
+                Ok(_) => Some(false),
+                Err(_) => None,
+            });
+        }
+
+        for ((i, x, y), moved) in movers.into_iter().zip(moved) {
+            let Some(&(_, _, mob)) = self.map.mobs.get(i) else { continue };
+
+            match mob.behaviour {
+                Behaviour::Oscillate(_) => {
+                    if moved.is_none() {
+                        self.mob_states[i].inverted = !self.mob_states[i].inverted;
+                    }
+                },
+                Behaviour::PathfindToRandomLandmark { .. } => {
+                    if let SeekState::MovingTowards(target_x, target_y) = self.mob_states[i].seek {
+                        let Some(entity) = self.entities.mobs.get(i) else { continue };
+
+                        if
+                        // If we got there
+                        (
+                            entity.x == target_x
+                            && entity.y == target_y
+                        )
+                        || // Or we can't seem to get there
+                        (
+                            entity.x == x
+                            && entity.y == y
+                        )
+                        {
+                            self.mob_states[i].seek = SeekState::PreSniffing;
+                        }
+                    }
+                },
+                Behaviour::Static
+                | Behaviour::Pattern(_)
+                | Behaviour::Brownian => {},
+            }
+
+            if moved == Some(true) {
+                self.mob_button_check(i, mob.flags, speaker);
+            }
+        }
+    }
+
+    /// The move the mob wants to make this tick, if it wants to make one.
+    /// `seed` is this tick's seed for `mob_rng`.
+    fn mob_intent(&mut self, i: usize, seed: xs::Seed) -> Option<Result<movement::Planned, movement::Blocked>> {
+        use maps::mobs::Behaviour;
+
+        let &(spawn_x, spawn_y, mob) = self.map.mobs.get(i)?;
+        let mut rng = mob_rng(seed, spawn_x, spawn_y);
+
+        if !self.frame_count.is_multiple_of(mob.period.max(1)) {
+            return None
+        }
+
+        let entity = self.entities.mobs.get(i)?;
+        let (x, y) = (entity.x, entity.y);
+
+        match mob.behaviour {
+            Behaviour::Static => None,
+            Behaviour::Pattern(pattern) => {
+                let dir = pattern_dir(pattern, self.frame_count)?;
+
+                Some(movement::plan_custom(x, y, self.map, &self.entities, dir, mob.flags))
+            },
+            Behaviour::Oscillate(pattern) => {
+                let mut dir = pattern_dir(pattern, self.frame_count)?;
+
+                if self.mob_states[i].inverted {
+                    dir = !dir;
+                }
+
+                Some(movement::plan_custom(x, y, self.map, &self.entities, dir, mob.flags))
+            },
+            Behaviour::Brownian => {
+                let dir = gen_dir(&mut rng);
+
+                Some(movement::plan_custom(x, y, self.map, &self.entities, dir, mob.flags))
+            },
+            Behaviour::PathfindToRandomLandmark { restless_period, pathing } => {
+                match self.mob_states[i].seek {
+                    SeekState::MovingTowards(target_x, target_y) => {
+                        Some(self.distance_fields.plan_towards(
+                            x,
+                            y,
+                            &self.entities,
+                            (target_x, target_y),
+                            mob.flags,
+                            &pathing,
+                        ))
+                    },
+                    SeekState::PreSniffing | SeekState::Sniffing => {
+                        self.mob_states[i].seek = SeekState::Sniffing;
+
+                        if self.frame_count.is_multiple_of(restless_period.max(1))
+                        || !xy::checked_eight_neighbors(x, y).any(|(x, y)| is_xy_landmark(self.map, x, y))
+                        {
+                            // Run off to something else
+                            let target = random_landmark(
+                                &mut rng,
+                                self.map,
+                                |(target_x, target_y)| {
+                                    if x == target_x
+                                    && y == target_y {
+                                        return false
+                                    }
+
+                                    for xy in xy::checked_eight_neighbors(x, y) {
+                                        if xy.0 == target_x
+                                        && xy.1 == target_y {
+                                            return false
+                                        }
+                                    }
+
+                                    // It would never get onto it anyway.
+                                    !xy_to_i(self.map, target_x, target_y)
+                                        .and_then(|index| self.map.tiles.get(index))
+                                        .is_some_and(|&kind| pathing.avoids(kind, target_x, target_y))
+                                }
+                            );
+
+                            // With nowhere else to go, it keeps sniffing here.
+                            if let Some((target_x, target_y)) = target {
+                                self.mob_states[i].seek = SeekState::MovingTowards(target_x, target_y);
+                            }
+                        }
+
+                        None
+                    },
+                }
+            },
+        }
+    }
+
+    fn mob_button_check(&mut self, i: usize, flags: movement::Flags, speaker: &mut Speaker) {
+        // Things that pass through everything don't press buttons either.
+        if flags & movement::PASS_THROUGH != 0 {
+            return
+        }
+
+        let Some(entity) = self.entities.mobs.get(i) else { return };
+        let (x, y) = (entity.x, entity.y);
+
+        self.button_check(x, y, speaker);
+    }
+
+    /// Presses the button at the given position, if there is one.
+    fn button_check(&mut self, x: X, y: Y, speaker: &mut Speaker) {
+        if let Some(tile::BUTTON_LIT) = get_effective_tile_custom(self.map, &self.entities, x, y, NO_MOBS) {
+            if let Some(sfx) = self.entity_on_button(x, y) {
+                speaker.request_sfx(sfx);
+            }
+        }
+    }
+
+    fn run_timeline(&mut self, speaker: &mut Speaker) {
+        use timeline::Condition;
+
+        for (i, event) in self.map.timeline.iter().enumerate() {
+            if self.timeline_fired.get(i).copied().unwrap_or(true)
+            || !event.frames.contains(self.frame_count) {
+                continue
+            }
+
+            let player = &self.entities.player;
+
+            let condition_holds = match event.condition {
+                Condition::Always => true,
+                Condition::PlayerAt(x, y) => player.x == x && player.y == y,
+                Condition::PlayerNotAt(x, y) => player.x != x || player.y != y,
+                Condition::TileIs(x, y, kind) => self.get_effective_tile(x, y) == Some(kind),
+                Condition::TileIsNot(x, y, kind) => self.get_effective_tile(x, y) != Some(kind),
+            };
+
+            if !condition_holds {
+                continue
+            }
+
+            self.timeline_fired[i] = true;
+
+            if let Some(sfx) = self.perform(event.action, MessageInfo::Timeline(i as TimelineIndex)) {
+                speaker.request_sfx(sfx);
+            }
+        }
+    }
+
+    /// `message` is what to show if the action is a message, since that
+    /// depends on where the action came from. Returns the sound to play, if any.
+    #[must_use]
+    fn perform(&mut self, action: timeline::Action, message: MessageInfo) -> Option<SFX> {
+        use timeline::Action;
+
+        match action {
+            Action::Spawn { kind, x, y } => {
+                self.add_entity(Entity { kind, x, y });
+            },
+            Action::Remove { x, y } => {
+                self.remove_entity(x, y);
+            },
+            Action::OpenDoor { x, y } => {
+                if self.get_effective_tile(x, y) == Some(tile::CLOSED_DOOR) {
+                    self.add_entity(Entity {
+                        kind: tile::OPEN_DOOR,
+                        x,
+                        y,
+                    });
+                }
+            },
+            Action::Message(_) => {
+                self.message_info = message;
+            },
+            Action::Sfx(sfx) => {
+                return Some(sfx)
+            },
+        }
+
+        None
+    }
+
+    fn password_lock(&self, id: LockId) -> Option<&PasswordLock> {
+        self.password_locks.get(id.map as usize)?.get(id.lock as usize)
+    }
+
+    /// The name of the button to press for the given press of the lock's
+    /// password, if there is such a lock and press.
+    fn password_button_name(&self, id: LockId, index: ButtonIndex) -> Option<&'static str> {
+        let lock = self.world.maps.get(id.map as usize)?.locks.get(id.lock as usize)?;
+        let &button = self.password_lock(id)?.sequence.get(index as usize)?;
+
+        lock.buttons.get(button as usize).map(|button| button.name)
+    }
+
+    #[must_use]
+    fn entity_on_button(&mut self, x: X, y: Y) -> Option<SFX> {
+        let (lock_index, lock, button) = self.map.locks.iter().enumerate().find_map(|(i, lock)| {
+            lock.buttons.iter()
+                .position(|button| button.x == x && button.y == y)
+                .map(|button| (i, lock, button as ButtonIndex))
+        })?;
+
+        let id = LockId { map: self.map_index, lock: lock_index as locks::LockIndex };
+        let password_lock = self.password_locks.get_mut(self.map_index as usize)?
+            .get_mut(lock_index)?;
+
+        // The reward has already been given, for this loop.
+        if password_lock.is_open() {
+            return None
+        }
+
+        let mut output = Some(SFX::ButtonPress);
+
+        let press = password_lock.press_count as usize;
+        if password_lock.sequence.get(press) == Some(&button) {
+            if let Some(open) = password_lock.open.get_mut(press) {
+                *open = true;
+            }
+        }
+        password_lock.press_count = password_lock.press_count.saturating_add(1);
+
+        self.add_entity(Entity {
+            kind: tile::BUTTON_DARK,
+            x,
+            y,
+        });
+
+        let password_lock = self.password_locks.get_mut(self.map_index as usize)?
+            .get_mut(lock_index)?;
+        if password_lock.is_open() {
+            match lock.reward {
+                locks::Reward::Key { x, y, .. } => {
+                    self.add_entity(Entity {
+                        kind: tile::KEY,
+                        x,
+                        y,
+                    });
+                },
+                locks::Reward::Action(action) => {
+                    if let Some(sfx) = self.perform(action, MessageInfo::LockReward(id)) {
+                        output = Some(sfx);
+                    }
+                },
+            }
+        } else if password_lock.press_count as usize >= password_lock.sequence.len() {
+            // Reset all the buttons because a mistake was made entering it.
+            password_lock.reset();
+
+            for button in lock.buttons {
+                self.remove_kind(button.x, button.y, tile::BUTTON_DARK);
+            }
+        }
+
+        output
+    }
+
+    #[must_use]
+    fn move_player(&mut self, dir: Dir) -> Option<SFX> {
+        self.step_player(dir, false)
+    }
+
+    /// Moves the player, pulling whatever is behind them along.
+    #[must_use]
+    fn pull_player(&mut self, dir: Dir) -> Option<SFX> {
+        self.step_player(dir, true)
+    }
+
+    #[must_use]
+    fn step_player(&mut self, dir: Dir, pulling: bool) -> Option<SFX> {
+        let mut output = None;
+
+        self.message_info = MessageInfo::NoMessage;
+
+        match self.screen {
+            Screen::Gameplay => {},
+            Screen::Congraturation
+            | Screen::Journal { .. }
+            | Screen::Overview { .. } => return output,
+            #[cfg(feature = "editor")]
+            Screen::Editor => return output,
+        }
+
+        self.recording.push(EchoStep {
+            frame: self.frame_count,
+            action: if pulling { EchoAction::Pull(dir) } else { EchoAction::Move(dir) },
+        });
+
+        let (player_x, player_y) = (self.entities.player.x, self.entities.player.y);
+
+        if let Some((index, x, y)) = edge_exit(self.map, player_x, player_y, dir) {
+            self.enter_map(index, x, y);
+            return output
+        }
+
+        if pulling {
+            let planned = movement::plan_pull(player_x, player_y, self.map, &self.entities, dir, 0);
+
+            let moved = match planned {
+                Ok(planned) => movement::perform_pushes(&mut self.entities, self.map, planned),
+                Err(_) => {
+                    output = Some(SFX::Bump);
+                    None
+                },
+            };
+            if let Some((new_x, new_y)) = moved {
+                self.entities.player.x = new_x;
+                self.entities.player.y = new_y;
+            }
+        } else if move_entity(
+            self.entities.player.x,
+            self.entities.player.y,
+            &mut self.entities,
+            self.map,
+            dir,
+        ).is_err() {
+            output = Some(SFX::Bump);
+        }
+
+        if (self.entities.player.x, self.entities.player.y) != (player_x, player_y) {
+            if let Some((index, x, y)) = tile_exit(self.map, self.entities.player.x, self.entities.player.y) {
+                self.enter_map(index, x, y);
+                return output
+            }
+        }
+
+        // if we would push the special grave for the first time
+        let grave_push = xy_in_dir(dir, self.entities.player.x, self.entities.player.y)
+            .filter(|&(target_x, target_y)| {
+                !pulling
+                && (target_x, target_y) == self.special_grave()
+                && get_effective_tile(self.map, &self.entities, target_x, target_y) == Some(tile::SPECIAL_GRAVE)
+            })
+            .and_then(|target| Some((target, xy_in_dir(dir, target.0, target.1)?)));
+
+        if let Some(((target_x, target_y), (pushed_x, pushed_y))) = grave_push {
+            // The grave is part of the map, so something has to cover where
+            // it was. Use the same ground it is being pushed onto.
+            if let Some(ground) = tiles_at(self.map, &self.entities, pushed_x, pushed_y)
+                .and_then(|mut tiles| tiles.next()) {
+                self.add_entity(Entity {
+                    kind: ground,
+                    x: target_x,
+                    y: target_y,
+                });
+            }
+
+            self.add_entity(Entity {
+                kind: tile::SPECIAL_GRAVE,
+                x: pushed_x,
+                y: pushed_y,
+            });
+
+            self.add_entity(Entity {
+                kind: tile::EXCLAMATION_BUBBLE,
+                x: target_x,
+                y: target_y,
+            });
+        }
+
+        match get_effective_tile_custom(self.map, &self.entities, self.entities.player.x, self.entities.player.y, NO_MOBS) {
+            Some(tile::PORTAL) => {
+                output = Some(SFX::CardPlace);
+
+                self.reset_time();
+            }
+            Some(tile::STAIRS_DOWN) => {
+                self.screen = Screen::Congraturation;
+            }
+            Some(tile::KEY) => {
+                output = Some(SFX::CardSlide);
+
+                let (player_x, player_y) = (self.entities.player.x, self.entities.player.y);
+                for lock in self.map.locks {
+                    if let locks::Reward::Key { x, y, door_x, door_y } = lock.reward {
+                        if (x, y) == (player_x, player_y) {
+                            self.add_entity(Entity {
+                                kind: tile::DOOR_2,
+                                x: door_x,
+                                y: door_y,
+                            });
+                        }
+                    }
+                }
+
+                self.remove_kind(player_x, player_y, tile::KEY);
+
+                // Keys that are part of the map need covering up instead.
+                if get_effective_tile_custom(self.map, &self.entities, player_x, player_y, NO_MOBS) == Some(tile::KEY) {
+                    self.add_entity(Entity {
+                        kind: tile::FLOOR,
+                        x: player_x,
+                        y: player_y,
+                    });
+                }
+            }
+            Some(tile::BUTTON_LIT) => {
+                output = self.entity_on_button(self.entities.player.x, self.entities.player.y);
+            }
+            _ => {}
+        }
+
+        output
+    }
+}
+
+/// Implements a 64 bit FNV-1a hash. Unlike `std`'s default hasher, this is
+/// guaranteed to produce the same output across builds, which matters since
+/// the hashes get written to disk.
+mod hash {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Fnv(u64);
+
+    impl Default for Fnv {
+        fn default() -> Fnv {
+            Fnv(0xcbf29ce484222325)
+        }
+    }
+
+    impl core::hash::Hasher for Fnv {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for &b in bytes {
+                self.0 ^= b as u64;
+                self.0 = self.0.wrapping_mul(0x100000001b3);
+            }
+        }
+
+        fn write_usize(&mut self, i: usize) {
+            // Hash usizes the same way on every pointer width.
+            self.write_u64(i as u64);
+        }
+    }
+}
+
+impl State {
+    /// Returns a hash of everything that can affect future frames. Two states
+    /// with the same hash, fed the same inputs, ought to stay in lockstep.
+    pub fn hash(&self) -> u64 {
+        use core::hash::{Hash, Hasher};
+
+        let State {
+            frame_count,
+            rng,
+            // The maps are static data, so they cannot diverge.
+            world: _,
+            map_index,
+            map: _,
+            screen,
+            entities,
+            password_locks,
+            special_grave,
+            message_info,
+            loop_count,
+            said,
+            // This is derived from the rest of the state each frame.
+            hud_prints: _,
+            mob_states,
+            mob_kinds,
+            layers,
+            journal,
+            visited,
+            // This is a cache of earlier states, so it is not part of the
+            // state itself.
+            rewind: _,
+            timeline_fired,
+            recording,
+            echo,
+            // This is a cache, made from the rest of the state.
+            distance_fields: _,
+            // This is derived from the rest of the state each frame.
+            texts: _,
+            // The game does not change while editing, and the edits only
+            // go anywhere once saved.
+            #[cfg(feature = "editor")]
+            editor: _,
+        } = self;
+
+        let mut hasher = hash::Fnv::default();
+
+        frame_count.hash(&mut hasher);
+        rng.hash(&mut hasher);
+        screen.hash(&mut hasher);
+        entities.hash(&mut hasher);
+        password_locks.hash(&mut hasher);
+        message_info.hash(&mut hasher);
+        loop_count.hash(&mut hasher);
+        said.hash(&mut hasher);
+        mob_states.hash(&mut hasher);
+        mob_kinds.hash(&mut hasher);
+        journal.hash(&mut hasher);
+        timeline_fired.hash(&mut hasher);
+        recording.hash(&mut hasher);
+        echo.hash(&mut hasher);
+        map_index.hash(&mut hasher);
+        layers.hash(&mut hasher);
+        special_grave.hash(&mut hasher);
+        visited.hash(&mut hasher);
+
+        hasher.finish()
+    }
+}
+
+/// A versioned binary format for saving and loading the whole game state.
+///
+/// The format is as follows, with multi-byte numbers in little endian:
+/// * The 4 bytes of `MAGIC`
+/// * A `u16` version
+/// * The body, whose layout depends on the version.
+///
+/// Until a version has shipped, change its layout in place. Once it has, when
+/// the body layout changes, bump `VERSION`, and add a function to `MIGRATIONS`
+/// that converts a body of the previous version to the new one. That way old
+/// saves keep working.
+///
+/// Version 2 is for a world of maps. It has a layer and locks for each map,
+/// 16 bit coordinates, a special grave picked per seed, dynamic entities
+/// stacked by depth, and the places the player has visited.
+pub mod save {
+    use super::*;
+
+    pub const MAGIC: [u8; 4] = *b"TLCS";
+    pub type Version = u16;
+    pub const VERSION: Version = 2;
+
+    /// Converts a body from version `i + 1` into a body for version `i + 2`,
+    /// where `i` is the index in the slice. Saves do not hold the maps, so
+    /// this gets the world that the save is being loaded in.
+    type Migration = fn(Vec<u8>, maps::World) -> Result<Vec<u8>, LoadError>;
+
+    const MIGRATIONS: [Migration; VERSION as usize - 1] = [
+        version_1_to_2,
+    ];
+
+    /// Version 1 was from before there was more than one map, so it holds
+    /// only the start map's part of the state, with 8 bit coordinates, at
+    /// most one dynamic entity per tile, and a single lock of four buttons.
+    fn version_1_to_2(body: Vec<u8>, world: maps::World) -> Result<Vec<u8>, LoadError> {
+        let map = world.start_map();
+        let lock = LockId { map: world.start, lock: 0 };
+        let button_index = |index: u64| ButtonIndex::try_from(index).map_err(|_| LoadError::BadLock(lock));
+
+        fn narrow_x(r: &mut Reader) -> Result<X, LoadError> {
+            Ok(xy::x(u8::read(r)?.into()))
+        }
+
+        fn narrow_y(r: &mut Reader) -> Result<Y, LoadError> {
+            Ok(xy::y(u8::read(r)?.into()))
+        }
+
+        fn narrow_entity(r: &mut Reader) -> Result<Entity, LoadError> {
+            Ok(Entity { kind: TileKind::read(r)?, x: narrow_x(r)?, y: narrow_y(r)? })
+        }
+
+        fn list<T>(r: &mut Reader, mut read: impl FnMut(&mut Reader) -> Result<T, LoadError>) -> Result<Vec<T>, LoadError> {
+            (0..usize::read(r)?).map(|_| read(r)).collect()
+        }
+
+        let mut r = Reader { bytes: &body };
+        let mut w = Writer::default();
+
+        write_frame_count(read_frame_count(&mut r)?, &mut w);
+        Xs::read(&mut r)?.write(&mut w);
+        Screen::read(&mut r)?.write(&mut w);
+
+        let mut entities = Entities {
+            player: narrow_entity(&mut r)?,
+            mobs: list(&mut r, narrow_entity)?,
+            echo: match r.tag()? {
+                0 => None,
+                1 => Some(narrow_entity(&mut r)?),
+                tag => return Err(LoadError::InvalidTag { what: "Option", tag }),
+            },
+            ..<_>::default()
+        };
+        // The keys were tile indexes, which the entities' own positions say.
+        for (_, entity) in list(&mut r, |r| Ok((usize::read(r)?, narrow_entity(r)?)))? {
+            entities.insert(map, map.properties(entity.kind).depth, entity);
+        }
+        entities.write(&mut w);
+
+        // The lock's buttons were in the order of the password.
+        let mut sequence = Vec::with_capacity(4);
+        for _ in 0..4 {
+            sequence.push(u8::read(&mut r)?);
+        }
+        let password_lock = PasswordLock {
+            sequence,
+            open: <[bool; 4]>::read(&mut r)?.to_vec(),
+            press_count: button_index(u64::read(&mut r)?)?,
+        };
+        let mut password_locks = vec![Vec::new(); usize::from(world.start) + 1];
+        password_locks[usize::from(world.start)].push(password_lock);
+        password_locks.write(&mut w);
+
+        let message_info = match r.tag()? {
+            0 => MessageInfo::NoMessage,
+            1 => MessageInfo::PasswordReveal { lock, index: button_index(u64::read(&mut r)?)? },
+            6 => MessageInfo::Timeline(<_>::read(&mut r)?),
+            7 => MessageInfo::Dialogue {
+                speaker: <_>::read(&mut r)?,
+                node: <_>::read(&mut r)?,
+                choice: <_>::read(&mut r)?,
+            },
+            tag => return Err(LoadError::InvalidTag { what: "MessageInfo", tag }),
+        };
+        message_info.write(&mut w);
+
+        LoopCount::read(&mut r)?.write(&mut w);
+        Vec::<bool>::read(&mut r)?.write(&mut w);
+
+        let mob_states = list(&mut r, |r| Ok(MobState {
+            inverted: <_>::read(r)?,
+            seek: match r.tag()? {
+                0 => SeekState::PreSniffing,
+                1 => SeekState::Sniffing,
+                2 => SeekState::MovingTowards(narrow_x(r)?, narrow_y(r)?),
+                tag => return Err(LoadError::InvalidTag { what: "SeekState", tag }),
+            },
+        }))?;
+        mob_states.write(&mut w);
+        Vec::<TileKind>::read(&mut r)?.write(&mut w);
+
+        let entries = list(&mut r, |r| match r.tag()? {
+            0 => Ok(Fact::PasswordReveal { lock, index: button_index(u64::read(r)?)? }),
+            3 => Ok(Fact::SpecialGrave),
+            4 => Ok(Fact::Heard { map: world.start, node: <_>::read(r)? }),
+            tag => Err(LoadError::InvalidTag { what: "Fact", tag }),
+        })?;
+        Journal { entries, flags: <_>::read(&mut r)? }.write(&mut w);
+
+        Vec::<bool>::read(&mut r)?.write(&mut w);
+        Vec::<EchoStep>::read(&mut r)?.write(&mut w);
+        Echo::read(&mut r)?.write(&mut w);
+
+        if !r.bytes.is_empty() {
+            return Err(LoadError::TrailingBytes(r.bytes.len()))
+        }
+
+        world.start.write(&mut w);
+        // Loading makes layers for the maps that do not have one yet.
+        Vec::<Layer>::new().write(&mut w);
+        // There was only the map's own special grave then.
+        map.special_grave_x.write(&mut w);
+        map.special_grave_y.write(&mut w);
+        Visited::default().write(&mut w);
+
+        Ok(w.0)
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum LoadError {
+        BadMagic,
+        UnsupportedVersion(Version),
+        Truncated,
+        InvalidTag { what: &'static str, tag: u8 },
+        BadLock(LockId),
+        FrameCountTooLarge(u32),
+        WrongMobCount { expected: usize, found: usize },
+        UnknownMap(MapIndex),
+        TrailingBytes(usize),
+    }
+
+    impl core::fmt::Display for LoadError {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            use LoadError::*;
+            match self {
+                BadMagic => write!(f, "Not a save file: the magic bytes did not match"),
+                UnsupportedVersion(v) => write!(f, "Save version {v} is newer than the newest supported version, {VERSION}"),
+                Truncated => write!(f, "The save ended unexpectedly"),
+                InvalidTag { what, tag } => write!(f, "Invalid tag {tag} for {what}"),
+                BadLock(LockId { map, lock }) => write!(f, "The save's password for lock {lock} on map {map} does not fit that lock"),
+                FrameCountTooLarge(count) => write!(f, "The save's frame count, {count}, is too large for this build. Try enabling the wide-frame-count feature"),
+                WrongMobCount { expected, found } => write!(f, "The save has {found} mobs, but the map has {expected}"),
+                UnknownMap(index) => write!(f, "The save refers to map {index}, which is not in the world"),
+                TrailingBytes(count) => write!(f, "There were {count} unexpected bytes at the end of the save"),
+            }
+        }
+    }
+
+    impl std::error::Error for LoadError {}
+
+    #[derive(Default)]
+    pub(crate) struct Writer(Vec<u8>);
+
+    impl Writer {
+        fn bytes(&mut self, bytes: &[u8]) {
+            self.0.extend_from_slice(bytes);
+        }
+    }
+
+    pub(crate) struct Reader<'bytes> {
+        bytes: &'bytes [u8],
+    }
+
+    impl <'bytes> Reader<'bytes> {
+        fn bytes<const N: usize>(&mut self) -> Result<[u8; N], LoadError> {
+            if self.bytes.len() < N {
+                return Err(LoadError::Truncated)
+            }
+
+            let (taken, rest) = self.bytes.split_at(N);
+            self.bytes = rest;
+
+            let mut output = [0; N];
+            output.copy_from_slice(taken);
+            Ok(output)
+        }
+
+        fn tag(&mut self) -> Result<u8, LoadError> {
+            u8::read(self)
+        }
+    }
+
+    pub(crate) trait Persist: Sized {
+        fn write(&self, w: &mut Writer);
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError>;
+    }
+
+    macro_rules! persist_int {
+        ($($type: ty)+) => {$(
+            impl Persist for $type {
+                fn write(&self, w: &mut Writer) {
+                    w.bytes(&self.to_le_bytes());
+                }
+
+                fn read(r: &mut Reader) -> Result<Self, LoadError> {
+                    Ok(<$type>::from_le_bytes(r.bytes()?))
+                }
+            }
+        )+}
+    }
+
+    persist_int!{ u8 u16 u32 u64 }
+
+    impl Persist for usize {
+        fn write(&self, w: &mut Writer) {
+            // Use the same size on every pointer width.
+            (*self as u64).write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            Ok(u64::read(r)? as usize)
+        }
+    }
+
+    impl Persist for bool {
+        fn write(&self, w: &mut Writer) {
+            u8::from(*self).write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            match r.tag()? {
+                0 => Ok(false),
+                1 => Ok(true),
+                tag => Err(LoadError::InvalidTag { what: "bool", tag }),
+            }
+        }
+    }
+
+    impl Persist for core::num::Wrapping<u32> {
+        fn write(&self, w: &mut Writer) {
+            self.0.write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            Ok(core::num::Wrapping(u32::read(r)?))
+        }
+    }
+
+    impl <T: Persist> Persist for Option<T> {
+        fn write(&self, w: &mut Writer) {
+            match self {
+                None => 0u8.write(w),
+                Some(t) => {
+                    1u8.write(w);
+                    t.write(w);
+                }
+            }
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            match r.tag()? {
+                0 => Ok(None),
+                1 => Ok(Some(T::read(r)?)),
+                tag => Err(LoadError::InvalidTag { what: "Option", tag }),
+            }
+        }
+    }
+
+    impl <T: Persist + Default, const N: usize> Persist for [T; N] {
+        fn write(&self, w: &mut Writer) {
+            for t in self {
+                t.write(w);
+            }
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            let mut output: [T; N] = core::array::from_fn(|_| T::default());
+
+            for slot in &mut output {
+                *slot = T::read(r)?;
+            }
+
+            Ok(output)
+        }
+    }
+
+    impl Persist for X {
+        fn write(&self, w: &mut Writer) {
+            (self.usize() as xy::Inner).write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            Ok(xy::x(xy::Inner::read(r)?))
+        }
+    }
+
+    impl Persist for Y {
+        fn write(&self, w: &mut Writer) {
+            (self.usize() as xy::Inner).write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            Ok(xy::y(xy::Inner::read(r)?))
+        }
+    }
+
+    impl Persist for Entity {
+        fn write(&self, w: &mut Writer) {
+            let Entity { kind, x, y } = self;
+            kind.write(w);
+            x.write(w);
+            y.write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            Ok(Entity {
+                kind: TileKind::read(r)?,
+                x: X::read(r)?,
+                y: Y::read(r)?,
+            })
+        }
+    }
+
+    impl Persist for Entities {
+        fn write(&self, w: &mut Writer) {
+            self.player.write(w);
+            self.mobs.write(w);
+            self.echo.write(w);
+            self.dynamic.write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            Ok(Entities {
+                player: Entity::read(r)?,
+                mobs: <_>::read(r)?,
+                echo: <_>::read(r)?,
+                dynamic: <_>::read(r)?,
+            })
+        }
+    }
+
+    impl Persist for Stack {
+        fn write(&self, w: &mut Writer) {
+            self.0.write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            Ok(Stack(<_>::read(r)?))
+        }
+    }
+
+    impl Persist for HashMap<usize, Stack> {
+        fn write(&self, w: &mut Writer) {
+            // Sort so that the same state always produces the same bytes.
+            let mut keys: Vec<_> = self.keys().copied().collect();
+            keys.sort_unstable();
+
+            keys.len().write(w);
+            for key in keys {
+                key.write(w);
+                self[&key].write(w);
+            }
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            let mut dynamic = HashMap::new();
+
+            let len = usize::read(r)?;
+            for _ in 0..len {
+                let key = usize::read(r)?;
+                let stack = Stack::read(r)?;
+                dynamic.insert(key, stack);
+            }
+
+            Ok(dynamic)
+        }
+    }
+
+    impl Persist for Layer {
+        fn write(&self, w: &mut Writer) {
+            let Layer {
+                dynamic,
+                mobs,
+                echo,
+                mob_states,
+                mob_kinds,
+                said,
+                timeline_fired,
+            } = self;
+
+            dynamic.write(w);
+            mobs.write(w);
+            echo.write(w);
+            mob_states.write(w);
+            mob_kinds.write(w);
+            said.write(w);
+            timeline_fired.write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            Ok(Layer {
+                dynamic: <_>::read(r)?,
+                mobs: <_>::read(r)?,
+                echo: <_>::read(r)?,
+                mob_states: <_>::read(r)?,
+                mob_kinds: <_>::read(r)?,
+                said: <_>::read(r)?,
+                timeline_fired: <_>::read(r)?,
+            })
+        }
+    }
+
+    impl Persist for Screen {
+        fn write(&self, w: &mut Writer) {
+            match self {
+                Screen::Gameplay => 0u8.write(w),
+                Screen::Congraturation => 1u8.write(w),
+                Screen::Journal { scroll } => {
+                    2u8.write(w);
+                    scroll.write(w);
+                },
+                Screen::Overview { x, y, show_visited } => {
+                    3u8.write(w);
+                    x.write(w);
+                    y.write(w);
+                    show_visited.write(w);
+                },
+                // The editor is not saved, so come back to the game instead.
+                #[cfg(feature = "editor")]
+                Screen::Editor => 0u8.write(w),
+            }
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            match r.tag()? {
+                0 => Ok(Screen::Gameplay),
+                1 => Ok(Screen::Congraturation),
+                2 => Ok(Screen::Journal { scroll: JournalIndex::read(r)? }),
+                3 => Ok(Screen::Overview {
+                    x: X::read(r)?,
+                    y: Y::read(r)?,
+                    show_visited: bool::read(r)?,
+                }),
+                tag => Err(LoadError::InvalidTag { what: "Screen", tag }),
+            }
+        }
+    }
+
+    impl Persist for MessageInfo {
+        fn write(&self, w: &mut Writer) {
+            match self {
+                MessageInfo::NoMessage => 0u8.write(w),
+                // Tag 1 was for password reveals, before there were
+                // multiple locks. Tags 2 to 5 were for messages that are now
+                // dialogue.
+                MessageInfo::Timeline(index) => {
+                    6u8.write(w);
+                    index.write(w);
+                },
+                MessageInfo::Dialogue { speaker, node, choice } => {
+                    7u8.write(w);
+                    speaker.write(w);
+                    node.write(w);
+                    choice.write(w);
+                },
+                MessageInfo::PasswordReveal { lock, index } => {
+                    8u8.write(w);
+                    lock.write(w);
+                    index.write(w);
+                },
+                MessageInfo::LockReward(lock) => {
+                    9u8.write(w);
+                    lock.write(w);
+                },
+            }
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            match r.tag()? {
+                0 => Ok(MessageInfo::NoMessage),
+                6 => Ok(MessageInfo::Timeline(TimelineIndex::read(r)?)),
+                7 => Ok(MessageInfo::Dialogue {
+                    speaker: SpeakerIndex::read(r)?,
+                    node: NodeIndex::read(r)?,
+                    choice: ChoiceIndex::read(r)?,
+                }),
+                8 => Ok(MessageInfo::PasswordReveal {
+                    lock: LockId::read(r)?,
+                    index: ButtonIndex::read(r)?,
+                }),
+                9 => Ok(MessageInfo::LockReward(LockId::read(r)?)),
+                tag => Err(LoadError::InvalidTag { what: "MessageInfo", tag }),
+            }
+        }
+    }
+
+    impl Persist for SeekState {
+        fn write(&self, w: &mut Writer) {
+            match self {
+                SeekState::PreSniffing => 0u8.write(w),
+                SeekState::Sniffing => 1u8.write(w),
+                SeekState::MovingTowards(x, y) => {
+                    2u8.write(w);
+                    x.write(w);
+                    y.write(w);
+                },
+            }
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            match r.tag()? {
+                0 => Ok(SeekState::PreSniffing),
+                1 => Ok(SeekState::Sniffing),
+                2 => Ok(SeekState::MovingTowards(X::read(r)?, Y::read(r)?)),
+                tag => Err(LoadError::InvalidTag { what: "SeekState", tag }),
+            }
+        }
+    }
+
+    impl Persist for MobState {
+        fn write(&self, w: &mut Writer) {
+            self.inverted.write(w);
+            self.seek.write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            Ok(MobState {
+                inverted: <_>::read(r)?,
+                seek: <_>::read(r)?,
+            })
+        }
+    }
+
+    impl Persist for Fact {
+        fn write(&self, w: &mut Writer) {
+            match self {
+                // Tags 1 and 2 were for facts that are now dialogue. Tags 0
+                // and 4 were for facts from before there were multiple locks
+                // and maps.
+                Fact::SpecialGrave => 3u8.write(w),
+                Fact::PasswordReveal { lock, index } => {
+                    5u8.write(w);
+                    lock.write(w);
+                    index.write(w);
+                },
+                Fact::Heard { map, node } => {
+                    6u8.write(w);
+                    map.write(w);
+                    node.write(w);
+                },
+            }
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            match r.tag()? {
+                3 => Ok(Fact::SpecialGrave),
+                5 => Ok(Fact::PasswordReveal {
+                    lock: LockId::read(r)?,
+                    index: ButtonIndex::read(r)?,
+                }),
+                6 => Ok(Fact::Heard {
+                    map: MapIndex::read(r)?,
+                    node: NodeIndex::read(r)?,
+                }),
+                tag => Err(LoadError::InvalidTag { what: "Fact", tag }),
+            }
+        }
+    }
+
+    impl <T: Persist> Persist for Vec<T> {
+        fn write(&self, w: &mut Writer) {
+            self.len().write(w);
+            for t in self {
+                t.write(w);
+            }
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            let len = usize::read(r)?;
+            // Don't trust the length enough to preallocate with it.
+            let mut output = Vec::new();
+            for _ in 0..len {
+                output.push(T::read(r)?);
+            }
+            Ok(output)
+        }
+    }
+
+    impl Persist for Dir {
+        fn write(&self, w: &mut Writer) {
+            match self {
+                Dir::Up => 0u8.write(w),
+                Dir::Down => 1u8.write(w),
+                Dir::Left => 2u8.write(w),
+                Dir::Right => 3u8.write(w),
+            }
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            match r.tag()? {
+                0 => Ok(Dir::Up),
+                1 => Ok(Dir::Down),
+                2 => Ok(Dir::Left),
+                3 => Ok(Dir::Right),
+                tag => Err(LoadError::InvalidTag { what: "Dir", tag }),
+            }
+        }
+    }
+
+    impl Persist for EchoStep {
+        fn write(&self, w: &mut Writer) {
+            write_frame_count(self.frame, w);
+            match self.action {
+                EchoAction::Move(dir) => {
+                    0u8.write(w);
+                    dir.write(w);
+                },
+                EchoAction::Interact(dir) => {
+                    1u8.write(w);
+                    dir.write(w);
+                },
+                EchoAction::Pull(dir) => {
+                    2u8.write(w);
+                    dir.write(w);
+                },
+            }
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            let frame = read_frame_count(r)?;
+            let action = match r.tag()? {
+                0 => EchoAction::Move(Dir::read(r)?),
+                1 => EchoAction::Interact(Dir::read(r)?),
+                2 => EchoAction::Pull(Dir::read(r)?),
+                tag => return Err(LoadError::InvalidTag { what: "EchoAction", tag }),
+            };
+
+            Ok(EchoStep { frame, action })
+        }
+    }
+
+    impl Persist for Echo {
+        fn write(&self, w: &mut Writer) {
+            self.steps.write(w);
+            self.next.write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            Ok(Echo {
+                steps: <_>::read(r)?,
+                next: <_>::read(r)?,
+            })
+        }
+    }
+
+    impl Persist for Visited {
+        fn write(&self, w: &mut Writer) {
+            self.0.write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            Ok(Visited(<_>::read(r)?))
+        }
+    }
+
+    impl Persist for Journal {
+        fn write(&self, w: &mut Writer) {
+            self.entries.write(w);
+            self.flags.write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            Ok(Journal {
+                entries: <_>::read(r)?,
+                flags: <_>::read(r)?,
+            })
+        }
+    }
+
+    impl Persist for LockId {
+        fn write(&self, w: &mut Writer) {
+            self.map.write(w);
+            self.lock.write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            Ok(LockId {
+                map: <_>::read(r)?,
+                lock: <_>::read(r)?,
+            })
+        }
+    }
+
+    impl Persist for PasswordLock {
+        fn write(&self, w: &mut Writer) {
+            self.sequence.write(w);
+            self.open.write(w);
+            self.press_count.write(w);
+        }
+
+        fn read(r: &mut Reader) -> Result<Self, LoadError> {
+            Ok(PasswordLock {
+                sequence: <_>::read(r)?,
+                open: <_>::read(r)?,
+                press_count: <_>::read(r)?,
+            })
+        }
+    }
+
+    // With the `wide-frame-count` feature, `FrameCount` is already a `u32`.
+    #[allow(clippy::useless_conversion)]
+    fn write_frame_count(frame_count: FrameCount, w: &mut Writer) {
+        u32::from(frame_count).write(w);
+    }
+
+    // Without the `wide-frame-count` feature, this conversion can fail.
+    #[allow(clippy::unnecessary_fallible_conversions)]
+    fn read_frame_count(r: &mut Reader) -> Result<FrameCount, LoadError> {
+        let count = u32::read(r)?;
+
+        FrameCount::try_from(count)
+            .map_err(|_| LoadError::FrameCountTooLarge(count))
+    }
+
+    impl State {
+        pub fn save(&self) -> Vec<u8> {
+            // Destructure so we get a compile error when a field is added.
+            let State {
+                frame_count,
+                rng,
+                // The maps are not saved, so loading needs the same world.
+                world: _,
+                map_index,
+                map: _,
+                screen,
+                entities,
+                password_locks,
+                special_grave,
+                message_info,
+                loop_count,
+                said,
+                // This is derived from the rest of the state each frame.
+                hud_prints: _,
+                mob_states,
+                mob_kinds,
+                layers,
+                journal,
+                visited,
+                // Only meant to last for the current session.
+                rewind: _,
+                timeline_fired,
+                recording,
+                echo,
+                // This is remade as needed.
+                distance_fields: _,
+                // This is derived from the rest of the state each frame.
+                texts: _,
+                // Edits are saved as map files instead.
+                #[cfg(feature = "editor")]
+                editor: _,
+            } = self;
+
+            let mut w = Writer::default();
+            w.bytes(&MAGIC);
+            VERSION.write(&mut w);
+
+            write_frame_count(*frame_count, &mut w);
+            rng.write(&mut w);
+            screen.write(&mut w);
+            entities.write(&mut w);
+            password_locks.write(&mut w);
+            message_info.write(&mut w);
+            loop_count.write(&mut w);
+            said.write(&mut w);
+            mob_states.write(&mut w);
+            mob_kinds.write(&mut w);
+            journal.write(&mut w);
+            timeline_fired.write(&mut w);
+            recording.write(&mut w);
+            echo.write(&mut w);
+            map_index.write(&mut w);
+            layers.write(&mut w);
+            special_grave.0.write(&mut w);
+            special_grave.1.write(&mut w);
+            visited.write(&mut w);
+
+            w.0
+        }
+
+        /// Loads a game saved in the built-in world.
+        pub fn load(bytes: &[u8]) -> Result<State, LoadError> {
+            Self::load_in_world(bytes, maps::WORLD)
+        }
+
+        /// Loads a game saved in the given world. Saves do not hold the maps,
+        /// so this has to be the world the game was saved in.
+        pub fn load_in_world(bytes: &[u8], world: maps::World) -> Result<State, LoadError> {
+            let Some(rest) = bytes.strip_prefix(&MAGIC) else {
+                return Err(LoadError::BadMagic)
+            };
+
+            let mut r = Reader { bytes: rest };
+            let version = Version::read(&mut r)?;
+
+            if version == 0 || version > VERSION {
+                return Err(LoadError::UnsupportedVersion(version))
+            }
+
+            let mut body = r.bytes.to_vec();
+            for migration in &MIGRATIONS[version as usize - 1..] {
+                body = migration(body, world)?;
+            }
+
+            let mut r = Reader { bytes: &body };
+
+            let mut state = State {
+                frame_count: read_frame_count(&mut r)?,
+                rng: <_>::read(&mut r)?,
+                world,
+                // Filled in below, once we know which map the player is on.
+                map_index: world.start,
+                map: world.start_map(),
+                screen: <_>::read(&mut r)?,
+                entities: <_>::read(&mut r)?,
+                password_locks: <_>::read(&mut r)?,
+                // Read below, since it comes last.
+                special_grave: <_>::default(),
+                message_info: <_>::read(&mut r)?,
+                loop_count: <_>::read(&mut r)?,
+                said: <_>::read(&mut r)?,
+                hud_prints: <_>::default(),
+                mob_states: <_>::read(&mut r)?,
+                mob_kinds: <_>::read(&mut r)?,
+                journal: <_>::read(&mut r)?,
+                // Read below, since it comes last.
+                visited: <_>::default(),
+                rewind: <_>::default(),
+                timeline_fired: <_>::read(&mut r)?,
+                recording: <_>::read(&mut r)?,
+                echo: <_>::read(&mut r)?,
+                texts: <_>::default(),
+                layers: <_>::default(),
+                distance_fields: <_>::default(),
+                #[cfg(feature = "editor")]
+                editor: None,
+            };
+            state.map_index = <_>::read(&mut r)?;
+            state.layers = <_>::read(&mut r)?;
+            state.special_grave = (<_>::read(&mut r)?, <_>::read(&mut r)?);
+            state.visited = <_>::read(&mut r)?;
+
+            if !r.bytes.is_empty() {
+                return Err(LoadError::TrailingBytes(r.bytes.len()))
+            }
+
+            state.map = world.maps.get(state.map_index as usize)
+                .ok_or(LoadError::UnknownMap(state.map_index))?;
+
+            // Maps may have been added to the world since this was saved.
+            for i in state.layers.len()..world.maps.len() {
+                state.layers.push(if i == state.map_index as usize {
+                    Layer::default()
+                } else {
+                    let map = world.maps[i];
+                    Layer::new(map, pick_mob_kinds(map, &mut xs::from_seed(<_>::default())))
+                });
+            }
+
+            // Locks, or maps with locks, may have been added since this was
+            // saved. But the passwords that were saved need to fit their locks.
+            if state.password_locks.len() > world.maps.len() {
+                return Err(LoadError::UnknownMap(world.maps.len() as MapIndex))
+            }
+            state.password_locks.resize_with(world.maps.len(), Vec::new);
+
+            let mut rng = xs::from_seed(<_>::default());
+            for (i, (saved, &map)) in state.password_locks.iter_mut().zip(world.maps).enumerate() {
+                let id = |lock: usize| LockId { map: i as MapIndex, lock: lock as locks::LockIndex };
+
+                if saved.len() > map.locks.len() {
+                    return Err(LoadError::BadLock(id(map.locks.len())))
+                }
+
+                for (j, lock) in map.locks.iter().enumerate() {
+                    let Some(password_lock) = saved.get(j) else {
+                        saved.push(PasswordLock::new(lock, &mut rng));
+                        continue
+                    };
+
+                    let length = password_lock.sequence.len();
+                    if length != lock.length as usize
+                    || password_lock.open.len() != length
+                    || password_lock.press_count as usize > length
+                    || password_lock.sequence.iter().any(|&button| button as usize >= lock.buttons.len()) {
+                        return Err(LoadError::BadLock(id(j)))
+                    }
+                }
+            }
+
+            // Check the current map along with the others.
+            state.swap_layer(state.map_index);
+
+            for (layer, &map) in state.layers.iter_mut().zip(world.maps) {
+                // Events and dialogue may have been added to the map since
+                // this was saved.
+                layer.timeline_fired.resize(map.timeline.len(), false);
+                layer.said.resize(map.dialogue.nodes.len(), false);
+
+                // But the mobs need to match up with the map's one to one.
+                for found in [
+                    layer.mobs.len(),
+                    layer.mob_states.len(),
+                    layer.mob_kinds.len(),
+                ] {
+                    if found != map.mobs.len() {
+                        return Err(LoadError::WrongMobCount { expected: map.mobs.len(), found })
+                    }
+                }
+            }
+
+            state.swap_layer(state.map_index);
+            state.refresh_texts();
+
+            Ok(state)
+        }
+    }
+}
+
+#[cfg(test)]
+mod loop_length_works {
+    use super::*;
+
+    #[test]
+    fn on_a_short_loop() {
+        let map: Map = Box::leak(Box::new(maps::Map {
+            loop_length: 700,
+            ..maps::MAP
+        }));
+
+        let mut state = State::new_on_map([42; 16], map);
+
+        let mut speaker = Speaker::default();
+        let mut warned_at = Vec::new();
+
+        for frame in 1..700 {
+            speaker.clear();
+            state.frame(Input::default(), &mut speaker);
+
+            if speaker.slice().iter().any(|sfx| matches!(sfx, SFX::TimeWarning)) {
+                warned_at.push(frame);
+            }
+        }
+
+        assert_eq!(warned_at, (100..700).step_by(60).collect::<Vec<_>>());
+        assert_eq!(&state.hud_prints[1].text[..], b"0:01 [#.......]\0");
+
+        state.frame(Input::default(), &mut speaker);
+
+        assert_eq!(state.frame_count, 0);
+    }
+
+    #[test]
+    fn on_the_built_in_loop() {
+        let mut state = State::new([42; 16]);
+        let mut speaker = Speaker::default();
+
+        assert_eq!(state.frames_left(), 65536);
+
+        state.frame_count = 65534;
+        state.frame(Input::default(), &mut speaker);
+
+        assert_eq!(state.frame_count, 65535);
+        assert_eq!(state.frames_left(), 1);
+
+        state.frame(Input::default(), &mut speaker);
+
+        assert_eq!(state.frame_count, 0);
+    }
+}
+
+#[cfg(test)]
+mod move_mob_works {
+    use super::*;
+    use maps::mobs::{self, Behaviour, Mob, Pattern};
+
+    const PACER: Mob = Mob {
+        kinds: &[tile::CRAB],
+        behaviour: Behaviour::Oscillate(Pattern { dirs: "r", step_length: 1 }),
+        period: 4,
+        flags: 0,
+    };
+
+    static MOBS: [(X, Y, Mob); 2] = [
+        (xy::x(102), xy::y(88), PACER),
+        (xy::x(104), xy::y(88), mobs::LARGE_POT),
+    ];
+
+    #[test]
+    fn on_a_pacer_pushing_a_pot_in_a_room() {
+        let map: Map = Box::leak(Box::new(maps::Map {
+            mobs: &MOBS,
+            ..maps::MAP
+        }));
+
+        let mut state = State::new_on_map([42; 16], map);
+        let mut speaker = Speaker::default();
+
+        let mut xs = Vec::new();
+        for _ in 0..16 {
+            for _ in 0..4 {
+                state.frame(Input::default(), &mut speaker);
+            }
+            xs.push((state.entities.mobs[0].x.usize(), state.entities.mobs[1].x.usize()));
+        }
+
+        // The room's walls are at x 100 and 108. The pacer pushes the pot
+        // right until it hits the wall, then walks back left until it hits
+        // the other one, then turns around again.
+        assert_eq!(xs.iter().max(), Some(&(106, 107)), "{xs:?}");
+        assert_eq!(xs.iter().map(|&(pacer, _)| pacer).min(), Some(101), "{xs:?}");
+        assert_eq!(xs.last(), Some(&(106, 107)), "{xs:?}");
+    }
+}
+
+#[cfg(test)]
+mod run_timeline_works {
+    use super::*;
+    use timeline::{Action, Condition, Event, Frames};
+
+    static TIMELINE: [Event; 3] = [
+        Event {
+            frames: Frames::At(3),
+            condition: Condition::Always,
+            action: Action::Spawn { kind: tile::KEY, x: xy::x(102), y: xy::y(92) },
+        },
+        Event {
+            frames: Frames::At(4),
+            condition: Condition::Always,
+            action: Action::OpenDoor { x: xy::x(63), y: xy::y(109) },
+        },
+        Event {
+            frames: Frames::Range { start: 5, end: 20 },
+            condition: Condition::PlayerAt(xy::x(101), xy::y(90)),
+            action: Action::Message(b"would you like some pie?"),
+        },
+    ];
+
+    fn state_with_timeline() -> State {
+        let map: Map = Box::leak(Box::new(maps::Map {
+            timeline: &TIMELINE,
+            ..maps::MAP
+        }));
+
+        State::new_on_map([42; 16], map)
+    }
+
+    #[test]
+    fn on_this_example() {
+        let mut state = state_with_timeline();
+        let mut speaker = Speaker::default();
+
+        for _ in 0..10 {
+            state.frame(Input::default(), &mut speaker);
+        }
+
+        assert_eq!(state.get_effective_tile(xy::x(102), xy::y(92)), Some(tile::KEY));
+        assert_eq!(state.get_effective_tile(xy::x(63), xy::y(109)), Some(tile::OPEN_DOOR));
+        assert!(matches!(state.message_info, MessageInfo::NoMessage));
+
+        state.frame(Input { gamepad: Button::RIGHT, ..<_>::default() }, &mut speaker);
+        state.frame(Input::default(), &mut speaker);
+
+        assert!(matches!(state.message_info, MessageInfo::Timeline(2)));
+        assert_eq!(state.timeline_fired, [true, true, true]);
+    }
+
+    #[test]
+    fn when_the_player_misses_the_window() {
+        let mut state = state_with_timeline();
+        let mut speaker = Speaker::default();
+
+        for _ in 0..30 {
+            state.frame(Input::default(), &mut speaker);
+        }
+
+        state.frame(Input { gamepad: Button::RIGHT, ..<_>::default() }, &mut speaker);
+        state.frame(Input::default(), &mut speaker);
+
+        assert!(matches!(state.message_info, MessageInfo::NoMessage));
+        assert_eq!(state.timeline_fired, [true, true, false]);
+    }
+}
+
+#[cfg(test)]
+mod special_grave_works {
+    use super::*;
+
+    /// The rambling story, with the spaces taken out, since where the lines
+    /// break depends on the numbers.
+    fn story(state: &State) -> String {
+        let nodes = maps::MAP.dialogue.nodes.iter()
+            .enumerate()
+            .filter(|(_, node)| matches!(node.text, dialogue::Text::SpecialGrave(_)));
+
+        let mut output = String::new();
+        for (i, _) in nodes {
+            output.extend(state.node_text(&maps::MAP, i as NodeIndex).iter().map(|&b| b as char));
+        }
+        output.retain(|c| c != ' ');
+
+        output
+    }
+
+    #[test]
+    fn on_the_original_spot() {
+        assert_eq!(grave_counts(&maps::MAP, xy::x(155), xy::y(57)), (12, 8));
+        assert_eq!(number_words(12), "a dozen");
+        assert_eq!(number_words(8), "eight");
+        assert_eq!(number_words(21), "21");
+    }
+
+    #[test]
+    fn on_several_seeds() {
+        let mut spots = Vec::new();
+
+        for i in 0..8 {
+            let mut state = State::new([i; 16]);
+            let (x, y) = state.special_grave;
+            let (rows, graves) = grave_counts(&maps::MAP, x, y);
+
+            assert_eq!(state.get_effective_tile(x, y), Some(tile::SPECIAL_GRAVE));
+            let story = story(&state);
+            assert!(story.contains(&format!("past{}rows", number_words(rows).replace(' ', ""))), "{story}");
+            assert!(story.contains(&format!("all{}of", number_words(graves).replace(' ', ""))), "{story}");
+
+            state.reset_time();
+            assert_eq!(state.special_grave, (x, y));
+
+            spots.push((x, y));
+        }
+
+        spots.sort();
+        spots.dedup();
+        assert!(spots.len() > 1);
+    }
+
+    #[test]
+    fn on_pushing_it() {
+        let mut state = State::new([3; 16]);
+        let (x, y) = state.special_grave;
+        let below = y.checked_add(H::ONE).unwrap();
+
+        state.entities.player.x = x;
+        state.entities.player.y = below;
+        let _ = state.move_player(Dir::Up);
+
+        assert_eq!(state.get_effective_tile(x, y), Some(tile::EXCLAMATION_BUBBLE));
+        // Where the grave was gets covered, rather than drawn under the bubble.
+        assert!(!tiles_at(state.map, &state.entities, x, y).unwrap().any(|kind| kind == tile::SPECIAL_GRAVE));
+
+        state.interact(Dir::Up);
+        assert!(state.knows(Fact::SpecialGrave));
+    }
+}
+
+#[cfg(test)]
+mod talk_works {
+    use super::*;
+    use dialogue::{Choice, Condition, Dialogue, Effect, Entry, Node, Who};
+
+    static NODES: [Node; 5] = [
+        Node {
+            choices: &[
+                Choice { text: b"the left one", conditions: &[], node: 1 },
+                Choice { text: b"the secret one", conditions: &[Condition::Flag(0)], node: 3 },
+                Choice { text: b"the right one", conditions: &[], node: 2 },
+            ],
+            ..Node::say(b"which one?")
+        },
+        Node {
+            effects: &[Effect::SetFlag(0), Effect::Remember],
+            ..Node::say(b"good choice.")
+        },
+        Node {
+            effects: &[Effect::Give { kind: tile::KEY, x: xy::x(102), y: xy::y(92) }],
+            ..Node::say(b"here you go.")
+        },
+        Node::say(b"you knew!"),
+        Node::say(b"you again?"),
+    ];
+
+    static SPEAKERS: [dialogue::Speaker; 1] = [
+        dialogue::Speaker {
+            who: Who::At(xy::x(101), xy::y(90)),
+            entries: &[
+                Entry { conditions: &[Condition::Said(1)], node: 4 },
+                Entry { conditions: &[], node: 0 },
+            ],
+        },
+    ];
+
+    fn state_with_dialogue() -> State {
+        let map: Map = Box::leak(Box::new(maps::Map {
+            dialogue: Dialogue { nodes: &NODES, speakers: &SPEAKERS },
+            ..maps::MAP
+        }));
+
+        State::new_on_map([42; 16], map)
+    }
+
+    fn talk_right(state: &mut State) {
+        let mut gamepad = Button::RIGHT;
+        gamepad.insert(Button::A);
+
+        state.frame(Input { gamepad, previous_gamepad: Button::RIGHT }, &mut Speaker::default());
+    }
+
+    fn press(state: &mut State, gamepad: Button) {
+        state.frame(Input { gamepad, ..<_>::default() }, &mut Speaker::default());
+    }
+
+    fn node(state: &State) -> Option<(NodeIndex, ChoiceIndex)> {
+        match state.message_info {
+            MessageInfo::Dialogue { node, choice, .. } => Some((node, choice)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn on_this_example() {
+        let mut state = state_with_dialogue();
+
+        talk_right(&mut state);
+        assert_eq!(node(&state), Some((0, 0)));
+        assert_eq!(state.offered_choices().count(), 2);
+
+        // Picking a choice doesn't move the player.
+        press(&mut state, Button::DOWN);
+        assert_eq!(node(&state), Some((0, 1)));
+        assert_eq!(state.entities.player.y, xy::y(90));
+
+        press(&mut state, Button::A);
+        assert_eq!(node(&state), Some((2, 0)));
+        assert_eq!(state.get_effective_tile(xy::x(102), xy::y(92)), Some(tile::KEY));
+
+        talk_right(&mut state);
+        assert_eq!(node(&state), Some((0, 0)));
+
+        press(&mut state, Button::A);
+        assert_eq!(node(&state), Some((1, 0)));
+        assert!(state.knows(Fact::Heard { map: 0, node: 1 }));
+
+        talk_right(&mut state);
+        assert_eq!(node(&state), Some((4, 0)));
+    }
+
+    #[test]
+    fn after_time_resets() {
+        let mut state = state_with_dialogue();
+
+        talk_right(&mut state);
+        press(&mut state, Button::A);
+        assert_eq!(node(&state), Some((1, 0)));
+
+        state.reset_time();
+        assert_eq!(state.loop_count, 1);
+
+        // No one has said anything in this loop, but the flag is remembered.
+        talk_right(&mut state);
+        assert_eq!(node(&state), Some((0, 0)));
+        assert_eq!(state.offered_choices().count(), 3);
+
+        press(&mut state, Button::DOWN);
+        press(&mut state, Button::A);
+        assert_eq!(node(&state), Some((3, 0)));
+    }
+
+    #[test]
+    fn when_walking_away() {
+        let mut state = state_with_dialogue();
+
+        talk_right(&mut state);
+        assert_eq!(node(&state), Some((0, 0)));
+
+        press(&mut state, Button::B);
+        assert_eq!(node(&state), None);
+        assert_eq!(state.frame_count, 1);
+
+        // With the choices gone, the player can move again.
+        press(&mut state, Button::DOWN);
+        assert_eq!(state.entities.player.y, xy::y(91));
+    }
+}
+
+#[cfg(test)]
+mod replay_echo_works {
+    use super::*;
+
+    #[test]
+    fn on_a_walk_over_a_button() {
+        const LOCKS: &[locks::Lock] = &[
+            locks::Lock {
+                buttons: &[
+                    locks::Button { x: xy::x(102), y: xy::y(90), name: "north" },
+                    locks::Button { x: xy::x(102), y: xy::y(99), name: "east" },
+                    locks::Button { x: xy::x(102), y: xy::y(101), name: "south" },
+                    locks::Button { x: xy::x(101), y: xy::y(100), name: "west" },
+                ],
+                length: 4,
+                reward: locks::Reward::Key { x: xy::x(102), y: xy::y(100), door_x: xy::x(98), door_y: xy::y(89) },
+            },
+        ];
+
+        let map: Map = Box::leak(Box::new(maps::Map {
+            locks: LOCKS,
+            ..maps::MAP
+        }));
+
+        let mut state = State::new_on_map([42; 16], map);
+        let mut speaker = Speaker::default();
+
+        for _ in 0..4 {
+            state.frame(Input { gamepad: Button::RIGHT, ..<_>::default() }, &mut speaker);
+            state.frame(Input::default(), &mut speaker);
+        }
+
+        let walked_to = (state.entities.player.x, state.entities.player.y);
+        assert_ne!(walked_to, (map.player_x, map.player_y));
+
+        state.reset_time();
+
+        let start = Some((map.player_x, map.player_y));
+        assert_eq!(state.entities.echo.as_ref().map(|e| (e.x, e.y)), start);
+
+        state.add_entity(Entity {
+            kind: tile::BUTTON_LIT,
+            x: xy::x(102),
+            y: xy::y(90),
+        });
+
+        for _ in 0..8 {
+            state.frame(Input::default(), &mut speaker);
+        }
+
+        assert_eq!(state.entities.echo.as_ref().map(|e| (e.x, e.y)), Some(walked_to));
+        assert_eq!((state.entities.player.x, state.entities.player.y), (map.player_x, map.player_y));
+        assert_eq!(state.get_effective_tile(xy::x(102), xy::y(90)), Some(tile::BUTTON_DARK));
+        assert_eq!(state.password_locks[0][0].press_count, 1);
+    }
+
+    #[test]
+    fn without_being_pushed() {
+        let mut state = State::new_on_map([42; 16], Box::leak(Box::new(test_map(&[tile::FLOOR; 3], 3, (0, 0)))));
+        let mut speaker = Speaker::default();
+        state.entities.echo = Some(Entity { kind: tile::ECHO, x: xy::x(1), y: xy::y(0) });
+
+        assert!(matches!(
+            movement::plan_custom(xy::x(0), xy::y(0), state.map, &state.entities, Dir::Right, 0),
+            Err(movement::Blocked::Immovable)
+        ));
+
+        state.frame(Input { gamepad: Button::RIGHT, ..<_>::default() }, &mut speaker);
+
+        assert_eq!((state.entities.player.x, state.entities.player.y), (xy::x(0), xy::y(0)));
+        assert_eq!(state.entities.echo.as_ref().map(|e| (e.x, e.y)), Some((xy::x(1), xy::y(0))));
+    }
+}
+
+#[cfg(test)]
+mod entity_on_button_works {
+    use super::*;
+
+    const MESSAGE_LOCK: LockId = LockId { map: 0, lock: 0 };
+    const KEY_LOCK: LockId = LockId { map: 0, lock: 1 };
+
+    const LOCKS: &[locks::Lock] = &[
+        locks::Lock {
+            buttons: &[
+                locks::Button { x: xy::x(104), y: xy::y(92), name: "left" },
+                locks::Button { x: xy::x(105), y: xy::y(92), name: "middle" },
+                locks::Button { x: xy::x(106), y: xy::y(92), name: "right" },
+            ],
+            length: 2,
+            reward: locks::Reward::Action(timeline::Action::Message(b"click")),
+        },
+        locks::Lock {
+            buttons: &[
+                locks::Button { x: xy::x(104), y: xy::y(94), name: "up" },
+                locks::Button { x: xy::x(105), y: xy::y(94), name: "down" },
+            ],
+            length: 1,
+            reward: locks::Reward::Key { x: xy::x(106), y: xy::y(94), door_x: xy::x(98), door_y: xy::y(89) },
+        },
+    ];
+
+    fn state() -> State {
+        State::new_on_map([42; 16], Box::leak(Box::new(maps::Map {
+            locks: LOCKS,
+            ..maps::MAP
+        })))
+    }
+
+    /// Presses the button for the given press of the lock's password.
+    fn press(state: &mut State, id: LockId, index: usize) -> (X, Y) {
+        let button = state.password_locks[id.map as usize][id.lock as usize].sequence[index];
+        let button = LOCKS[id.lock as usize].buttons[button as usize];
+
+        let _ = state.entity_on_button(button.x, button.y);
+
+        (button.x, button.y)
+    }
+
+    #[test]
+    fn on_two_locks_of_different_lengths() {
+        let mut state = state();
+
+        assert_eq!(state.password_locks[0][0].sequence.len(), 2);
+        assert_eq!(state.password_locks[0][1].sequence.len(), 1);
+
+        let (x, y) = press(&mut state, MESSAGE_LOCK, 0);
+        assert_eq!(state.get_effective_tile(x, y), Some(tile::BUTTON_DARK));
+        assert!(matches!(state.message_info, MessageInfo::NoMessage));
+
+        press(&mut state, MESSAGE_LOCK, 1);
+        assert!(matches!(state.message_info, MessageInfo::LockReward(MESSAGE_LOCK)));
+
+        // The other lock is unaffected.
+        assert_eq!(state.password_locks[0][1].press_count, 0);
+        press(&mut state, KEY_LOCK, 0);
+        assert_eq!(state.get_effective_tile(xy::x(106), xy::y(94)), Some(tile::KEY));
+    }
+
+    #[test]
+    fn on_a_wrong_order() {
+        let mut state = state();
+
+        let (first_x, first_y) = press(&mut state, MESSAGE_LOCK, 1);
+        assert_eq!(state.get_effective_tile(first_x, first_y), Some(tile::BUTTON_DARK));
+        press(&mut state, MESSAGE_LOCK, 0);
+
+        assert!(matches!(state.message_info, MessageInfo::NoMessage));
+        assert_eq!(state.password_locks[0][0].press_count, 0);
+        assert_ne!(state.get_effective_tile(first_x, first_y), Some(tile::BUTTON_DARK));
+    }
+
+    #[test]
+    fn once_open() {
+        let mut state = state();
+
+        let (key_x, key_y) = (xy::x(106), xy::y(94));
+        press(&mut state, KEY_LOCK, 0);
+        assert_eq!(state.get_effective_tile(key_x, key_y), Some(tile::KEY));
+        state.remove_kind(key_x, key_y, tile::KEY);
+
+        let button = LOCKS[1].buttons[0];
+        assert!(state.entity_on_button(button.x, button.y).is_none());
+        assert_ne!(state.get_effective_tile(key_x, key_y), Some(tile::KEY));
+        assert_eq!(state.password_locks[0][1].press_count, 1);
+    }
+
+    #[test]
+    fn on_the_reveal_message() {
+        let state = state();
+
+        let button = state.password_locks[0][0].sequence[1];
+        let expected = format!("push the {} button second", LOCKS[0].buttons[button as usize].name);
+
+        assert_eq!(state.password_reveal_text(MESSAGE_LOCK, 1), expected.into_bytes());
+
+        assert_eq!(ordinal(11), "11th");
+        assert_eq!(ordinal(22), "22nd");
+    }
+}
+
+#[cfg(test)]
+mod enter_map_works {
+    use super::*;
+    use maps::exits::{Exit, Side, Trigger};
+
+    const MAIN_EXITS: &[Exit] = &[
+        Exit { trigger: Trigger::At(xy::x(100), xy::y(91)), map: 1, x: xy::x(6), y: xy::y(2) },
+    ];
+
+    const CRYPT_EXITS: &[Exit] = &[
+        Exit { trigger: Trigger::At(xy::x(6), xy::y(1)), map: 0, x: xy::x(101), y: xy::y(91) },
+        Exit { trigger: Trigger::Edge(Side::Left), map: 0, x: xy::x(195), y: xy::y(0) },
+    ];
+
+    fn state() -> State {
+        let main: Map = Box::leak(Box::new(maps::Map {
+            exits: MAIN_EXITS,
+            ..maps::MAP
+        }));
+        let crypt: Map = Box::leak(Box::new(maps::Map {
+            exits: CRYPT_EXITS,
+            ..maps::CRYPT
+        }));
+
+        State::new_in_world([42; 16], maps::World {
+            maps: Box::leak(Box::new([main, crypt])),
+            start: 0,
+        })
+    }
+
+    #[test]
+    fn on_a_round_trip() {
+        let mut state = state();
+
+        let pot = Entity { kind: tile::LARGE_POT, x: xy::x(5), y: xy::y(5) };
+        state.add_entity(pot.clone());
+
+        let _ = state.move_player(Dir::Down);
+
+        assert_eq!(state.map_index, 1);
+        assert_eq!((state.entities.player.x, state.entities.player.y), (xy::x(6), xy::y(2)));
+        assert!(state.entities.dynamic.is_empty());
+        assert_eq!(state.entities.mobs.len(), maps::CRYPT.mobs.len());
+        assert_eq!(state.get_effective_tile(xy::x(5), xy::y(5)), Some(tile::FLOOR));
+
+        let _ = state.move_player(Dir::Up);
+
+        assert_eq!(state.map_index, 0);
+        assert_eq!((state.entities.player.x, state.entities.player.y), (xy::x(101), xy::y(91)));
+        assert_eq!(state.entities.mobs.len(), maps::MAP.mobs.len());
+        assert_eq!(state.get_effective_tile(xy::x(5), xy::y(5)), Some(pot.kind));
+    }
+
+    #[test]
+    fn after_time_resets() {
+        let mut state = state();
+
+        state.add_entity(Entity { kind: tile::LARGE_POT, x: xy::x(5), y: xy::y(5) });
+        let _ = state.move_player(Dir::Down);
+        state.add_entity(Entity { kind: tile::LARGE_POT, x: xy::x(2), y: xy::y(2) });
+
+        state.reset_time();
+
+        assert_eq!(state.map_index, 0);
+        assert!(state.entities.dynamic.is_empty());
+        assert!(state.layers.iter().all(|layer| layer.dynamic.is_empty()));
+        assert_eq!(state.layers[1].mobs.len(), maps::CRYPT.mobs.len());
+    }
+
+    #[test]
+    fn on_an_edge() {
+        let crypt = state().world.maps[1];
+
+        assert_eq!(edge_exit(crypt, xy::x(0), xy::y(4), Dir::Left), Some((0, xy::x(195), xy::y(4))));
+        assert_eq!(edge_exit(crypt, xy::x(0), xy::y(4), Dir::Up), None);
+        assert_eq!(edge_exit(crypt, xy::x(12), xy::y(4), Dir::Right), None);
+    }
+}
+
+/// A map of just these tiles, in rows `width` long, with the player at
+/// `player` and nothing else on it, for tests to fill in the rest of.
+#[cfg(test)]
+fn test_map(tiles: &'static [TileKind], width: xy::Inner, player: (xy::Inner, xy::Inner)) -> maps::Map {
+    maps::Map {
+        tiles,
+        width: xy::w(width),
+        height: xy::h(tiles.len() as xy::Inner / width),
+        player_x: xy::x(player.0),
+        player_y: xy::y(player.1),
+        mobs: &[],
+        locks: &[],
+        timeline: &[],
+        exits: &[],
+        tile_properties: &[],
+        ..maps::MAP
+    }
+}
+
+#[cfg(test)]
+mod wide_maps_works {
+    use super::*;
+
+    const WIDTH: xy::Inner = 300;
+    const HEIGHT: xy::Inner = 3;
+
+    fn state() -> State {
+        let tiles = vec![tile::FLOOR; usize::from(WIDTH) * usize::from(HEIGHT)];
+
+        State::new_on_map([42; 16], Box::leak(Box::new(test_map(tiles.leak(), WIDTH, (254, 1)))))
+    }
+
+    #[test]
+    fn past_the_old_ceiling() {
+        let mut state = state();
+
+        for _ in 254..WIDTH - 1 {
+            let _ = state.move_player(Dir::Right);
+        }
+        assert_eq!(state.entities.player.x, xy::x(WIDTH - 1));
+
+        // Moving off the edge is refused rather than clamped.
+        let _ = state.move_player(Dir::Right);
+        assert_eq!(state.entities.player.x, xy::x(WIDTH - 1));
+        assert!(!in_bounds(state.map, xy::x(WIDTH), xy::y(1)));
+
+        let pot = Entity { kind: tile::LARGE_POT, x: xy::x(280), y: xy::y(2) };
+        state.add_entity(pot.clone());
+        assert_eq!(state.get_effective_tile(pot.x, pot.y), Some(pot.kind));
+
+        // Off-map entities are ignored.
+        state.add_entity(Entity { kind: tile::LARGE_POT, x: xy::x(WIDTH), y: xy::y(0) });
+        assert_eq!(state.get_effective_tile(xy::x(WIDTH), xy::y(0)), None);
+    }
+
+    #[test]
+    fn on_checked_arithmetic() {
+        assert_eq!(xy::x(WIDTH).checked_sub(xy::w(1)), Some(xy::x(WIDTH - 1)));
+        assert_eq!(xy::x(0).checked_sub(xy::w(1)), None);
+        assert_eq!(xy::y(xy::Inner::MAX).checked_add(xy::h(1)), None);
+    }
+}
+
+#[cfg(test)]
+mod tile_properties_works {
+    use super::*;
+    use properties::Properties;
+
+    /// A corridor of grass, a wall, and a closed door, with the player at the
+    /// far end.
+    fn state(tile_properties: &'static [(TileKind, Properties)]) -> State {
+        const TILES: &[TileKind] = &[tile::FLOOR, tile::GRASS_GROUND, tile::WALL_3, tile::CLOSED_DOOR, tile::FLOOR];
+
+        State::new_on_map([42; 16], Box::leak(Box::new(maps::Map {
+            tile_properties,
+            ..test_map(TILES, 5, (4, 0))
+        })))
+    }
+
+    const OVERRIDES: &[(TileKind, Properties)] = &[
+        (tile::GRASS_GROUND, Properties { flags: 0, name: Some("thorns"), depth: Depth::Ground }),
+        (tile::WALL_3, Properties { flags: properties::BLOCKS_GHOSTS, name: None, depth: Depth::Object }),
+        (tile::CLOSED_DOOR, Properties { flags: properties::OPAQUE, name: None, depth: Depth::Ground }),
+    ];
+
+    fn allowed(state: &State, x: xy::Inner, flags: movement::Flags) -> movement::Allowed {
+        movement::allowed_to(state.map, &state.entities, xy::x(x), xy::y(0), flags)
+    }
+
+    #[test]
+    fn on_the_built_in_table() {
+        let mut state = state(&[]);
+
+        assert_eq!(allowed(&state, 1, 0), movement::Allowed::Move);
+        assert_eq!(allowed(&state, 2, 0), movement::Allowed::Not(movement::Blocked::Wall));
+        assert_eq!(allowed(&state, 2, movement::PASS_THROUGH), movement::Allowed::Move);
+
+        state.interact(Dir::Left);
+        assert_eq!(state.get_effective_tile(xy::x(3), xy::y(0)), Some(tile::OPEN_DOOR));
+    }
+
+    #[test]
+    fn on_overrides() {
+        let mut state = state(OVERRIDES);
+
+        assert_eq!(state.map.properties(tile::GRASS_GROUND).name, Some("thorns"));
+        assert_eq!(allowed(&state, 1, 0), movement::Allowed::Not(movement::Blocked::Wall));
+        assert_eq!(allowed(&state, 2, movement::PASS_THROUGH), movement::Allowed::Not(movement::Blocked::Wall));
+
+        state.interact(Dir::Left);
+        assert_eq!(state.get_effective_tile(xy::x(3), xy::y(0)), Some(tile::CLOSED_DOOR));
+    }
+
+    /// The rules from before movement was described by tile properties.
+    fn baseline_allowed_to(tile_kind: TileKind) -> movement::Allowed {
+        match tile_kind {
+            tile::FLOOR
+            | tile::GROUND
+            | tile::GRASS_GROUND
+            | tile::DOOR_0
+            | tile::DOOR_1
+            | tile::DOOR_2
+            | tile::DOOR_3
+            | tile::DOOR_4
+            | tile::STAIRS_DOWN
+            | tile::KEY
+            | tile::BUTTON_LIT
+            | tile::BUTTON_DARK
+            | tile::BUTTON_PRESSED
+            | tile::PORTAL => movement::Allowed::Move,
+            tile::LARGE_POT => movement::Allowed::Push,
+            _ => movement::Allowed::Not(movement::Blocked::Wall),
+        }
+    }
+
+    #[test]
+    fn on_the_shipped_maps_as_before() {
+        for map in maps::WORLD.maps {
+            let state = State::new_on_map([42; 16], map);
+
+            for i in 0..map.tiles.len() {
+                let (x, y) = i_to_xy(map, i);
+                let tile_kind = get_effective_tile(state.map, &state.entities, x, y).unwrap();
+
+                let allowed = movement::allowed_to(state.map, &state.entities, x, y, 0);
+                let expected = baseline_allowed_to(tile_kind);
+
+                assert_eq!(
+                    core::mem::discriminant(&allowed),
+                    core::mem::discriminant(&expected),
+                    "{tile_kind} at {x:?}, {y:?}"
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod push_chain_works {
+    use super::*;
+    use movement::Blocked;
+
+    /// A corridor of floor `width` long with a wall at the right end, and a
+    /// line of `pots` pots right of the player, who is at the left end.
+    fn state(width: xy::Inner, pots: xy::Inner) -> State {
+        let mut tiles = vec![tile::FLOOR; usize::from(width)];
+        tiles[usize::from(width) - 1] = tile::WALL_3;
+
+        let mut state = State::new_on_map([42; 16], Box::leak(Box::new(test_map(tiles.leak(), width, (0, 0)))));
+        for x in 1..=pots {
+            state.add_entity(Entity { kind: tile::LARGE_POT, x: xy::x(x), y: xy::y(0) });
+        }
+
+        state
+    }
+
+    fn plan(state: &State, dir: Dir) -> Result<movement::Planned, Blocked> {
+        let player = &state.entities.player;
+        movement::plan_custom(player.x, player.y, state.map, &state.entities, dir, 0)
+    }
+
+    #[test]
+    fn past_fifteen_things() {
+        let mut state = state(40, 20);
+
+        assert_eq!(plan(&state, Dir::Right).map(|planned| planned.len()), Ok(21));
+
+        let _ = state.move_player(Dir::Right);
+        assert_eq!(state.entities.player.x, xy::x(1));
+        for x in 2..=21 {
+            assert_eq!(state.get_effective_tile(xy::x(x), xy::y(0)), Some(tile::LARGE_POT), "{x}");
+        }
+    }
+
+    #[test]
+    fn with_the_reason_for_failing() {
+        let mut state = state(22, 20);
+
+        assert_eq!(plan(&state, Dir::Left).err(), Some(Blocked::OutOfBounds));
+        assert_eq!(plan(&state, Dir::Up).err(), Some(Blocked::OutOfBounds));
+        assert_eq!(plan(&state, Dir::Right).err(), Some(Blocked::Wall));
+
+        state.remove_entity(xy::x(20), xy::y(0));
+        state.add_entity(Entity { kind: tile::WALL_3, x: xy::x(20), y: xy::y(0) });
+        assert_eq!(plan(&state, Dir::Right).err(), Some(Blocked::Immovable));
+
+        assert!(matches!(state.move_player(Dir::Right), Some(SFX::Bump)));
+        assert_eq!(state.entities.player.x, xy::x(0));
+        assert_eq!(state.get_effective_tile(xy::x(1), xy::y(0)), Some(tile::LARGE_POT));
+    }
+
+    #[test]
+    fn up_to_the_limit() {
+        let limit = movement::MAX_CHAIN_LENGTH as xy::Inner;
+
+        assert!(plan(&state(limit + 3, limit), Dir::Right).is_ok());
+        assert_eq!(plan(&state(limit + 4, limit + 1), Dir::Right).err(), Some(Blocked::ChainTooLong));
+    }
+
+    #[test]
+    fn and_say_when_nothing_was_there() {
+        let mut state = state(40, 3);
+        let planned = plan(&state, Dir::Right).unwrap();
+
+        state.entities.remove(state.map, xy::x(2), xy::y(0), Depth::Object);
+
+        assert_eq!(
+            movement::perform(&mut state.entities, state.map, planned),
+            Err(movement::NothingThere { x: xy::x(2), y: xy::y(0) })
+        );
+    }
+}
+
+#[cfg(test)]
+mod mob_movement_works {
+    use super::*;
+    use maps::mobs::{Behaviour, Mob, Pattern};
+
+    const fn mob(kind: &'static [TileKind], dirs: &'static str) -> Mob {
+        Mob {
+            kinds: kind,
+            behaviour: Behaviour::Pattern(Pattern { dirs, step_length: 1 }),
+            period: 1,
+            flags: 0,
+        }
+    }
+
+    const RIGHT_CRAB: Mob = mob(&[tile::CRAB], "r");
+    const LEFT_TURTLE: Mob = mob(&[tile::TURTLE], "l");
+    const DOWN_TURTLE: Mob = mob(&[tile::TURTLE], "d");
+
+    /// A 3 by 3 room of floor, with the player in the bottom right corner.
+    fn state(mobs: &'static [(X, Y, Mob)]) -> State {
+        State::new_on_map([42; 16], Box::leak(Box::new(maps::Map {
+            mobs,
+            ..test_map(&[tile::FLOOR; 9], 3, (2, 2))
+        })))
+    }
+
+    fn tiles_after_a_frame(mut state: State) -> Vec<Option<TileKind>> {
+        state.frame(Input::default(), &mut Speaker::default());
+
+        (0..3).flat_map(|y| (0..3).map(move |x| (x, y)))
+            .map(|(x, y)| state.get_effective_tile(xy::x(x), xy::y(y)))
+            .collect()
+    }
+
+    #[test]
+    fn onto_the_same_tile() {
+        const CRAB_FIRST: &[(X, Y, Mob)] = &[
+            (xy::x(0), xy::y(0), RIGHT_CRAB),
+            (xy::x(2), xy::y(0), LEFT_TURTLE),
+        ];
+        const TURTLE_FIRST: &[(X, Y, Mob)] = &[
+            (xy::x(2), xy::y(0), LEFT_TURTLE),
+            (xy::x(0), xy::y(0), RIGHT_CRAB),
+        ];
+
+        let tiles = tiles_after_a_frame(state(CRAB_FIRST));
+
+        // The crab is further left, so it goes, and the turtle stays put.
+        assert_eq!(&tiles[..3], &[Some(tile::FLOOR), Some(tile::CRAB), Some(tile::TURTLE)]);
+        assert_eq!(tiles, tiles_after_a_frame(state(TURTLE_FIRST)));
+    }
+
+    #[test]
+    fn when_pushing_the_same_thing() {
+        const CRAB_FIRST: &[(X, Y, Mob)] = &[
+            (xy::x(0), xy::y(1), RIGHT_CRAB),
+            (xy::x(1), xy::y(0), DOWN_TURTLE),
+        ];
+        const TURTLE_FIRST: &[(X, Y, Mob)] = &[
+            (xy::x(1), xy::y(0), DOWN_TURTLE),
+            (xy::x(0), xy::y(1), RIGHT_CRAB),
+        ];
+
+        let with_pot = |mobs| {
+            let mut state = state(mobs);
+            state.add_entity(Entity { kind: tile::LARGE_POT, x: xy::x(1), y: xy::y(1) });
+            state
+        };
+
+        let tiles = tiles_after_a_frame(with_pot(CRAB_FIRST));
+
+        // The turtle is higher up, so it gets to push the pot.
+        assert_eq!(tiles[1], Some(tile::FLOOR));
+        assert_eq!(tiles[3], Some(tile::CRAB));
+        assert_eq!(tiles[4], Some(tile::TURTLE));
+        assert_eq!(tiles[7], Some(tile::LARGE_POT));
+        assert_eq!(tiles, tiles_after_a_frame(with_pot(TURTLE_FIRST)));
+    }
+
+    #[test]
+    fn with_the_fewest_pushes_first() {
+        let mut state = state(&[]);
+        state.add_entity(Entity { kind: tile::LARGE_POT, x: xy::x(1), y: xy::y(0) });
+
+        // Pushing the pot into the top right corner, and walking into that
+        // same corner from below.
+        let mut intents = [
+            movement::plan_custom(xy::x(0), xy::y(0), state.map, &state.entities, Dir::Right, 0),
+            movement::plan_custom(xy::x(2), xy::y(1), state.map, &state.entities, Dir::Up, 0),
+        ];
+
+        movement::resolve(&mut intents);
+
+        // The pusher is higher up, but walking pushes fewer things.
+        assert_eq!(intents[0].as_ref().err(), Some(&movement::Blocked::Contested));
+        assert!(intents[1].is_ok());
+    }
+
+    #[test]
+    fn at_random_in_any_order() {
+        const fn wandering(kind: &'static [TileKind]) -> Mob {
+            Mob { behaviour: Behaviour::Brownian, ..mob(kind, "") }
+        }
+
+        const CRAB_FIRST: &[(X, Y, Mob)] = &[
+            (xy::x(0), xy::y(0), wandering(&[tile::CRAB])),
+            (xy::x(4), xy::y(0), wandering(&[tile::TURTLE])),
+            (xy::x(0), xy::y(4), wandering(&[tile::GHOST_1])),
+        ];
+        const CRAB_LAST: &[(X, Y, Mob)] = &[
+            (xy::x(0), xy::y(4), wandering(&[tile::GHOST_1])),
+            (xy::x(4), xy::y(0), wandering(&[tile::TURTLE])),
+            (xy::x(0), xy::y(0), wandering(&[tile::CRAB])),
+        ];
+
+        /// A 5 by 5 room of floor, with the player in the bottom right corner.
+        fn tiles_after_a_while(mobs: &'static [(X, Y, Mob)]) -> Vec<Option<TileKind>> {
+            let mut state = State::new_on_map([42; 16], Box::leak(Box::new(maps::Map {
+                mobs,
+                ..test_map(&[tile::FLOOR; 25], 5, (4, 4))
+            })));
+
+            for _ in 0..64 {
+                state.frame(Input::default(), &mut Speaker::default());
+            }
+
+            (0..5).flat_map(|y| (0..5).map(move |x| (x, y)))
+                .map(|(x, y)| state.get_effective_tile(xy::x(x), xy::y(y)))
+                .collect()
+        }
+
+        let tiles = tiles_after_a_while(CRAB_FIRST);
+
+        assert_ne!(tiles[0], Some(tile::CRAB));
+        assert_eq!(tiles, tiles_after_a_while(CRAB_LAST));
+    }
+}
+
+#[cfg(test)]
+mod distance_fields_works {
+    use super::*;
+
+    const F: TileKind = tile::FLOOR;
+    const W: TileKind = tile::WALL_3;
+
+    fn state(tiles: &'static [TileKind], width: xy::Inner) -> State {
+        State::new_on_map([42; 16], Box::leak(Box::new(test_map(tiles, width, (0, 2)))))
+    }
+
+    /// Moves the player one step towards the target, and returns whether
+    /// they moved.
+    fn step(state: &mut State, fields: &mut movement::DistanceFields, target_x: X, target_y: Y) -> bool {
+        fields.refresh(state.map, &state.entities);
+
+        let player = &state.entities.player;
+        let planned = fields.plan_towards(player.x, player.y, &state.entities, (target_x, target_y), 0, &movement::Pathing::PLAIN)
+            .unwrap();
+        let moved = !planned.is_empty();
+
+        movement::perform(&mut state.entities, state.map, planned).unwrap();
+
+        moved
+    }
+
+    #[test]
+    fn along_the_shortest_path() {
+        let mut state = state(&[
+            F, F, F, F, F,
+            F, F, W, F, F,
+            F, F, W, F, F,
+        ], 5);
+        let mut fields = movement::DistanceFields::default();
+
+        let mut steps = 0;
+        while step(&mut state, &mut fields, xy::x(4), xy::y(2)) {
+            steps += 1;
+            assert!(steps <= 8, "went the long way around");
+        }
+
+        assert_eq!((state.entities.player.x, state.entities.player.y), (xy::x(4), xy::y(2)));
+        assert_eq!(steps, 8);
+    }
+
+    #[test]
+    fn without_going_anywhere_when_it_cannot_get_there() {
+        let mut state = state(&[
+            F, F, F, W, F,
+            F, F, F, W, F,
+            F, F, F, W, F,
+        ], 5);
+        let mut fields = movement::DistanceFields::default();
+
+        assert!(!step(&mut state, &mut fields, xy::x(4), xy::y(2)));
+        assert_eq!((state.entities.player.x, state.entities.player.y), (xy::x(0), xy::y(2)));
+    }
+
+    #[test]
+    fn around_mobs_in_the_way() {
+        let mut state = state(&[F; 9], 3);
+        state.entities.player.y = xy::y(1);
+        state.entities.mobs.push(Entity { kind: tile::TURTLE, x: xy::x(1), y: xy::y(1) });
+        let mut fields = movement::DistanceFields::default();
+
+        assert!(step(&mut state, &mut fields, xy::x(2), xy::y(1)));
+        assert_eq!(state.entities.player.x, xy::x(0));
+        assert_ne!(state.entities.player.y, xy::y(1));
+
+        while step(&mut state, &mut fields, xy::x(2), xy::y(1)) {}
+
+        assert_eq!((state.entities.player.x, state.entities.player.y), (xy::x(2), xy::y(1)));
+    }
+}
+
+#[cfg(test)]
+mod pathing_works {
+    use super::*;
+    use movement::{Flags, Pathing, PASS_THROUGH};
+
+    const F: TileKind = tile::FLOOR;
+    const G: TileKind = tile::GRASS_GROUND;
+    const D: TileKind = tile::DOOR_0;
+    const P: TileKind = tile::PORTAL;
+    const W: TileKind = tile::WALL_3;
+
+    /// Where the player goes when heading for the target, until they stop.
+    /// Checks that following the distance fields goes the same way as
+    /// searching for a path each step.
+    fn route(tiles: &'static [TileKind], width: xy::Inner, target: (xy::Inner, xy::Inner), flags: Flags, pathing: Pathing) -> Vec<(X, Y)> {
+        let target = (xy::x(target.0), xy::y(target.1));
+
+        let mut routes = [true, false].map(|by_fields| {
+            let mut state = State::new_on_map([42; 16], Box::leak(Box::new(test_map(tiles, width, (0, 0)))));
+            let mut fields = movement::DistanceFields::default();
+            let mut route = Vec::new();
+
+            loop {
+                let player = &state.entities.player;
+                let planned = if by_fields {
+                    fields.refresh(state.map, &state.entities);
+                    fields.plan_towards(player.x, player.y, &state.entities, target, flags, &pathing)
+                } else {
+                    movement::plan_entity_on_path_towards(player.x, player.y, &state.entities, state.map, target, flags, &pathing)
+                }.unwrap();
+
+                if planned.is_empty() || route.len() > tiles.len() {
+                    break
+                }
+
+                movement::perform(&mut state.entities, state.map, planned).unwrap();
+                route.push((state.entities.player.x, state.entities.player.y));
+            }
+
+            route
+        });
+
+        assert_eq!(routes[0], routes[1], "fields, then search");
+
+        core::mem::take(&mut routes[0])
+    }
+
+    fn xys(xys: &[(xy::Inner, xy::Inner)]) -> Vec<(X, Y)> {
+        xys.iter().map(|&(x, y)| (xy::x(x), xy::y(y))).collect()
+    }
+
+    #[test]
+    fn by_cost() {
+        const ROOM: &[TileKind] = &[
+            F, F, F,
+            G, W, F,
+            G, G, F,
+        ];
+        const PREFER_GRASS: Pathing = Pathing { costs: &[(G, 1)], ..Pathing::PLAIN };
+
+        assert_eq!(
+            route(ROOM, 3, (2, 2), 0, PREFER_GRASS),
+            xys(&[(0, 1), (0, 2), (1, 2), (2, 2)])
+        );
+
+        const CORRIDOR: &[TileKind] = &[
+            F, D, F,
+            F, F, F,
+        ];
+        const AVOID_DOORS: Pathing = Pathing { costs: &[(D, 8)], ..Pathing::PLAIN };
+
+        assert_eq!(route(CORRIDOR, 3, (2, 0), 0, Pathing::PLAIN), xys(&[(1, 0), (2, 0)]));
+        assert_eq!(
+            route(CORRIDOR, 3, (2, 0), 0, AVOID_DOORS),
+            xys(&[(0, 1), (1, 1), (2, 1), (2, 0)])
+        );
+    }
+
+    #[test]
+    fn around_what_is_avoided() {
+        const AVOID_PORTALS: Pathing = Pathing { avoid: &[P], ..Pathing::PLAIN };
+        const AROUND: &[(xy::Inner, xy::Inner)] = &[(0, 1), (1, 1), (2, 1), (2, 0)];
+
+        const CORRIDOR: &[TileKind] = &[
+            F, P, F,
+            F, F, F,
+        ];
+        assert_eq!(route(CORRIDOR, 3, (2, 0), 0, AVOID_PORTALS), xys(AROUND));
+        assert_eq!(route(CORRIDOR, 3, (1, 0), 0, AVOID_PORTALS), vec![]);
+
+        const DEAD_END: &[TileKind] = &[
+            F, P, F,
+            W, W, W,
+        ];
+        assert_eq!(route(DEAD_END, 3, (2, 0), 0, AVOID_PORTALS), vec![]);
+
+        const AVOID_THE_MIDDLE: Pathing = Pathing {
+            avoid_areas: &[Rect { min_x: xy::x(1), min_y: xy::y(0), max_x: xy::x(1), max_y: xy::y(0) }],
+            ..Pathing::PLAIN
+        };
+        assert_eq!(route(&[F; 6], 3, (2, 0), 0, AVOID_THE_MIDDLE), xys(AROUND));
+    }
+
+    #[test]
+    fn through_walls_when_passing_through() {
+        const WALLED_OFF: &[TileKind] = &[F, W, F];
+
+        assert_eq!(route(WALLED_OFF, 3, (2, 0), 0, Pathing::PLAIN), vec![]);
+        assert_eq!(route(WALLED_OFF, 3, (2, 0), PASS_THROUGH, Pathing::PLAIN), xys(&[(1, 0), (2, 0)]));
+    }
+
+    #[test]
+    fn for_dogs_to_anything_but_the_portal() {
+        const S: TileKind = tile::STAIRS_DOWN;
+        const TILES: &[TileKind] = &[
+            P, F, F, F, F, F, F, S,
+            F, F, F, F, F, F, F, F,
+        ];
+        const DOG: &[(X, Y, maps::mobs::Mob)] = &[(xy::x(3), xy::y(0), maps::mobs::DOG)];
+
+        let mut state = State::new_on_map([42; 16], Box::leak(Box::new(maps::Map {
+            mobs: DOG,
+            ..test_map(TILES, 8, (0, 1))
+        })));
+        let mut speaker = Speaker::default();
+
+        let mut targets = std::collections::HashSet::new();
+        for _ in 0..1024 {
+            state.frame(Input::default(), &mut speaker);
+
+            if let SeekState::MovingTowards(x, y) = state.mob_states[0].seek {
+                targets.insert((x, y));
+            }
+        }
+
+        assert_eq!(targets, [(xy::x(7), xy::y(0))].into());
+    }
+}
+
+#[cfg(test)]
+mod layered_entities_works {
+    use super::*;
+
+    /// A corridor of floor, with the player at the far end.
+    fn state() -> State {
+        State::new_on_map([42; 16], Box::leak(Box::new(test_map(&[tile::FLOOR; 5], 5, (4, 0)))))
+    }
+
+    fn add(state: &mut State, kind: TileKind, x: xy::Inner) {
+        state.add_entity(Entity { kind, x: xy::x(x), y: xy::y(0) });
+    }
+
+    fn tiles(state: &State, x: xy::Inner) -> Vec<TileKind> {
+        tiles_at(state.map, &state.entities, xy::x(x), xy::y(0))
+            .map(Iterator::collect)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn on_stacking() {
+        let mut state = state();
+        let (x, y) = (xy::x(1), xy::y(0));
+
+        add(&mut state, tile::LARGE_POT, 1);
+        add(&mut state, tile::KEY, 1);
+        add(&mut state, tile::BUTTON_DARK, 1);
+
+        assert_eq!(tiles(&state, 1), [tile::BUTTON_DARK, tile::KEY, tile::LARGE_POT]);
+        assert_eq!(state.get_effective_tile(x, y), Some(tile::LARGE_POT));
+
+        state.remove_kind(x, y, tile::BUTTON_DARK);
+        assert_eq!(tiles(&state, 1), [tile::FLOOR, tile::KEY, tile::LARGE_POT]);
+
+        state.remove_entity(x, y);
+        assert_eq!(state.get_effective_tile(x, y), Some(tile::KEY));
+
+        state.remove_entity(x, y);
+        assert_eq!(tiles(&state, 1), [tile::FLOOR]);
+        assert!(state.entities.dynamic.is_empty());
+    }
+
+    #[test]
+    fn on_pushing_off_a_key() {
+        let mut state = state();
+        state.entities.player.x = xy::x(3);
+
+        add(&mut state, tile::KEY, 2);
+        add(&mut state, tile::LARGE_POT, 2);
+
+        let _ = state.move_player(Dir::Left);
+        assert_eq!(tiles(&state, 1), [tile::FLOOR, tile::LARGE_POT]);
+        assert_eq!(tiles(&state, 2), [tile::FLOOR, PLAYER_KIND]);
+        assert_eq!(state.entities.dynamic.len(), 1);
+    }
+
+    #[test]
+    fn on_the_camera() {
+        let mut state = state();
+        add(&mut state, tile::KEY, 0);
+        add(&mut state, tile::EXCLAMATION_BUBBLE, 0);
+
+        let camera = CameraIter {
+            tile: Tile::default(),
+            pending: Vec::new(),
+            pending_tile: Tile::default(),
+            done: false,
+            map: state.map,
+            entities: &state.entities,
+            offset_x: W::ZERO,
+            offset_y: H::ZERO,
+            output_width: xy::w(5),
+            output_height: xy::h(1),
+        };
+
+        let kinds: Vec<_> = camera.map(|tile| (tile.x.usize(), tile.kind)).collect();
+        assert_eq!(kinds, [
+            (0, tile::FLOOR),
+            (0, tile::KEY),
+            (0, tile::EXCLAMATION_BUBBLE),
+            (1, tile::FLOOR),
+            (2, tile::FLOOR),
+            (3, tile::FLOOR),
+            (4, tile::FLOOR),
+            (4, PLAYER_KIND),
+        ]);
+    }
+}
+
+#[cfg(test)]
+mod pull_works {
+    use super::*;
+
+    /// A corridor of floor with a wall at the right end, and a pot in the
+    /// left end, with the player next to it.
+    fn state() -> State {
+        const TILES: &[TileKind] = &[tile::FLOOR, tile::FLOOR, tile::FLOOR, tile::FLOOR, tile::WALL_3];
+
+        let mut state = State::new_on_map([42; 16], Box::leak(Box::new(test_map(TILES, 5, (1, 0)))));
+        state.add_entity(Entity { kind: tile::LARGE_POT, x: xy::x(0), y: xy::y(0) });
+
+        state
+    }
+
+    fn pot_x(state: &mut State) -> Option<xy::Inner> {
+        (0..5).find(|&x| state.get_effective_tile(xy::x(x), xy::y(0)) == Some(tile::LARGE_POT))
+    }
+
+    #[test]
+    fn out_of_a_corner() {
+        let mut state = state();
+
+        let _ = state.pull_player(Dir::Right);
+        assert_eq!(state.entities.player.x, xy::x(2));
+        assert_eq!(pot_x(&mut state), Some(1));
+
+        let _ = state.pull_player(Dir::Right);
+        let _ = state.pull_player(Dir::Right);
+        // The wall stops both of them.
+        assert_eq!(state.entities.player.x, xy::x(3));
+        assert_eq!(pot_x(&mut state), Some(2));
+
+        // Pulling towards the pot does not push it.
+        let _ = state.pull_player(Dir::Left);
+        assert_eq!(state.entities.player.x, xy::x(3));
+        assert_eq!(pot_x(&mut state), Some(2));
+
+        // Moving away without pulling leaves it behind.
+        state.entities.player.x = xy::x(1);
+        let _ = state.move_player(Dir::Left);
+        assert_eq!(pot_x(&mut state), Some(2));
+    }
+
+    #[test]
+    fn with_b_and_a_direction() {
+        let mut state = state();
+        let mut speaker = Speaker::default();
+
+        let mut b_and_right = Button::B;
+        b_and_right.insert(Button::RIGHT);
+
+        state.frame(Input { gamepad: Button::B, ..<_>::default() }, &mut speaker);
+        state.frame(Input { gamepad: b_and_right, previous_gamepad: Button::B }, &mut speaker);
+
+        assert_eq!(state.entities.player.x, xy::x(2));
+        assert_eq!(pot_x(&mut state), Some(1));
+        assert_eq!(state.recording.last().map(|step| step.action), Some(EchoAction::Pull(Dir::Right)));
+    }
+}
+
+#[cfg(test)]
+mod overview_works {
+    use super::*;
+
+    fn press(state: &mut State, gamepad: Button) {
+        state.frame(Input { gamepad, ..<_>::default() }, &mut Speaker::default());
+    }
+
+    fn open(state: &mut State) -> (X, Y) {
+        press(state, Button::SELECT);
+        press(state, Button::SELECT);
+
+        match state.screen {
+            Screen::Overview { x, y, .. } => (x, y),
+            _ => panic!("the overview map is not open"),
+        }
+    }
+
+    fn cells_of(state: &State, colour: PaletteIndex) -> usize {
+        state.render_info().overview.filter(|cell| cell.colour == colour).count()
+    }
+
+    #[test]
+    fn on_opening_it() {
+        let mut state = State::new([42; 16]);
+        let (x, y) = open(&mut state);
+        let frame_count = state.frame_count;
+
+        assert_eq!(overview_start(state.map, state.entities.player.x, state.entities.player.y), (x, y));
+        assert_eq!(cells_of(&state, overview_colour::RED), 1);
+        // Every tile in view, and the player.
+        assert_eq!(
+            state.render_info().overview.count(),
+            state.map.width.usize().min(OVERVIEW_W.usize())
+                * state.map.height.usize().min(OVERVIEW_H.usize())
+                + 1
+        );
+        assert_eq!(state.render_info().tiles.count(), 0);
+
+        // The world is paused while the overview map is open.
+        press(&mut state, Button::DOWN);
+        assert_eq!(state.frame_count, frame_count);
+
+        press(&mut state, Button::SELECT);
+        assert!(matches!(state.screen, Screen::Gameplay));
+    }
+
+    #[test]
+    fn on_scrolling() {
+        let mut state = State::new([42; 16]);
+        open(&mut state);
+        let (max_x, max_y) = overview_max(state.map);
+
+        for _ in 0..state.map.height.usize() {
+            press(&mut state, Button::DOWN);
+        }
+        let mut both = Button::default();
+        both.insert(Button::LEFT);
+        both.insert(Button::UP);
+        press(&mut state, both);
+
+        assert!(matches!(
+            state.screen,
+            Screen::Overview { x, y, .. } if x == X::ZERO && y == max_y - H::ONE
+        ));
+        assert_eq!(max_x, X::ZERO, "the map fits across the screen");
+    }
+
+    #[test]
+    fn on_visited_places() {
+        let mut state = State::new([42; 16]);
+        press(&mut state, Button::DOWN);
+        press(&mut state, Button::default());
+        press(&mut state, Button::DOWN);
+
+        state.reset_time();
+        open(&mut state);
+        assert_eq!(cells_of(&state, overview_colour::GREEN), 0);
+
+        press(&mut state, Button::A);
+        // The start, which is under the player, and the two steps.
+        assert_eq!(cells_of(&state, overview_colour::GREEN), 3);
+    }
+}
+
+#[cfg(all(test, feature = "editor"))]
+mod editor_works {
+    use super::*;
+    use editor::Brush;
+
+    fn press(state: &mut State, buttons: &[Button]) {
+        let mut gamepad = Button::default();
+        for &button in buttons {
+            gamepad.insert(button);
+        }
+
+        state.frame(Input { gamepad, ..<_>::default() }, &mut Speaker::default());
+    }
+
+    fn editor(state: &State) -> &editor::Editor {
+        state.editor.as_ref().unwrap()
+    }
+
+    fn tile_at(state: &State, x: xy::Inner, y: xy::Inner) -> Option<TileKind> {
+        let editor = editor(state);
+        get_effective_tile(editor.base, &editor.entities, xy::x(x), xy::y(y))
+    }
+
+    #[test]
+    fn on_painting_then_undoing_and_redoing() {
+        let mut state = State::new([42; 16]);
+        press(&mut state, &[Button::START]);
+        assert!(matches!(state.screen, Screen::Editor));
+
+        // The cursor starts on the player, so move off them first.
+        press(&mut state, &[Button::UP]);
+        let before = tile_at(&state, 100, 89);
+
+        // The picker starts on the floor, and the next tile is a pressed button.
+        press(&mut state, &[Button::SELECT]);
+        press(&mut state, &[Button::RIGHT]);
+        press(&mut state, &[Button::A]);
+        assert_eq!(editor(&state).brush, Brush::Tile(tile::BUTTON_PRESSED));
+        assert_ne!(before, Some(tile::BUTTON_PRESSED));
+
+        press(&mut state, &[Button::A]);
+        assert_eq!(tile_at(&state, 100, 89), Some(tile::BUTTON_PRESSED));
+
+        press(&mut state, &[Button::B, Button::LEFT]);
+        assert_eq!(tile_at(&state, 100, 89), before);
+
+        press(&mut state, &[Button::B, Button::RIGHT]);
+        assert_eq!(tile_at(&state, 100, 89), Some(tile::BUTTON_PRESSED));
+
+        // The game itself is untouched.
+        assert_eq!(state.get_effective_tile(xy::x(100), xy::y(89)), before);
+    }
+
+    #[test]
+    fn on_moving_the_player() {
+        let mut state = State::new([42; 16]);
+        press(&mut state, &[Button::START]);
+
+        press(&mut state, &[Button::A]);
+        press(&mut state, &[Button::DOWN]);
+        press(&mut state, &[Button::A]);
+
+        assert_eq!(editor(&state).description.player, (100, 91));
+        assert_eq!(tile_at(&state, 100, 91), Some(PLAYER_KIND));
+        assert_ne!(tile_at(&state, 100, 90), Some(PLAYER_KIND));
+    }
+
+    #[test]
+    fn on_a_save_round_trip() {
+        let mut state = State::new([42; 16]);
+        press(&mut state, &[Button::START]);
+
+        press(&mut state, &[Button::DOWN]);
+        state.editor.as_mut().unwrap().brush = Brush::Mob("dog");
+        press(&mut state, &[Button::A]);
+        press(&mut state, &[Button::B, Button::SELECT]);
+
+        let text = state.take_editor_save().unwrap();
+        assert_eq!(state.take_editor_save(), None);
+
+        let loaded = maps::load(&text).unwrap();
+        assert_eq!(loaded.tiles, maps::MAP.tiles);
+        assert_eq!(loaded.mobs.len(), maps::MAP.mobs.len() + 1);
+        assert_eq!(loaded.dialogue, maps::MAP.dialogue);
+        // The comments in the built-in map's file are kept.
+        assert!(text.contains("# The dog pen min/max corners are:"));
+
+        let &(x, y, dog) = loaded.mobs.last().unwrap();
+        assert_eq!((x, y), (xy::x(100), xy::y(91)));
+        assert_eq!(maps::mobs::name_of(dog), Some("dog"));
+    }
+
+    #[test]
+    fn without_saving_problems() {
+        let mut state = State::new([42; 16]);
+        press(&mut state, &[Button::START]);
+
+        // Onto the wall above the player.
+        press(&mut state, &[Button::UP]);
+        state.editor.as_mut().unwrap().brush = Brush::Mob("dog");
+        press(&mut state, &[Button::A]);
+        press(&mut state, &[Button::B, Button::SELECT]);
+
+        assert_eq!(state.take_editor_save(), None);
+        assert!(editor(&state).status.starts_with(b"not saved. map 0: mob "), "{:?}", String::from_utf8_lossy(&editor(&state).status));
+    }
+
+    #[test]
+    fn only_from_gameplay() {
+        let mut state = State::new([42; 16]);
+        press(&mut state, &[Button::SELECT]);
+        assert!(matches!(state.screen, Screen::Journal { .. }));
+
+        press(&mut state, &[Button::START]);
+        assert!(matches!(state.screen, Screen::Journal { .. }));
+    }
+}
+
+#[cfg(test)]
+mod save_works {
+    use super::*;
+
+    const SEED: Seed = [42; 16];
+
+    fn input_for(frame: u32) -> Input {
+        let button_for = |frame: u32| match frame % 48 {
+            0..=3 => Button::LEFT,
+            12..=15 => Button::UP,
+            24..=27 => Button::RIGHT,
+            36..=39 => Button::DOWN,
+            _ => Button::default(),
+        };
+
+        Input {
+            gamepad: button_for(frame),
+            previous_gamepad: button_for(frame.wrapping_sub(1)),
+        }
+    }
+
+    #[test]
+    fn when_resuming_after_1000_frames() {
+        let mut speaker = Speaker::default();
+        let mut state = State::new(SEED);
+
+        for frame in 0..1000 {
+            state.frame(input_for(frame), &mut speaker);
+        }
+
+        let mut loaded = State::load(&state.save()).unwrap();
+
+        assert_eq!(loaded.hash(), state.hash());
+
+        for frame in 1000..1500 {
+            state.frame(input_for(frame), &mut speaker);
+            loaded.frame(input_for(frame), &mut speaker);
+
+            assert_eq!(loaded.hash(), state.hash(), "diverged on frame {frame}");
+        }
+    }
+
+    #[test]
+    fn saving_a_loaded_state_produces_the_same_bytes() {
+        let mut speaker = Speaker::default();
+        let mut state = State::new(SEED);
+
+        for frame in 0..200 {
+            state.frame(input_for(frame), &mut speaker);
+        }
+
+        let bytes = state.save();
+
+        assert_eq!(State::load(&bytes).unwrap().save(), bytes);
+    }
+
+    #[test]
+    fn on_bad_input() {
+        let bytes = State::new(SEED).save();
+
+        assert_eq!(State::load(b"nope").err(), Some(save::LoadError::BadMagic));
+        assert_eq!(State::load(&bytes[..bytes.len() - 1]).err(), Some(save::LoadError::Truncated));
+
+        let mut future = bytes.clone();
+        future[save::MAGIC.len()..save::MAGIC.len() + 2]
+            .copy_from_slice(&(save::VERSION + 1).to_le_bytes());
+        assert_eq!(
+            State::load(&future).err(),
+            Some(save::LoadError::UnsupportedVersion(save::VERSION + 1))
+        );
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(State::load(&trailing).err(), Some(save::LoadError::TrailingBytes(1)));
+    }
+
+    #[test]
+    fn in_another_world() {
+        let mut speaker = Speaker::default();
+        let mut state = State::new_on_map(SEED, &maps::CRYPT);
+
+        for frame in 0..200 {
+            state.frame(input_for(frame), &mut speaker);
+        }
+
+        let loaded = State::load_in_world(&state.save(), state.world).unwrap();
+
+        assert!(core::ptr::eq(loaded.map, &maps::CRYPT));
+        assert_eq!(loaded.hash(), state.hash());
+    }
+
+    #[test]
+    fn on_wide_coordinates() {
+        let tiles = vec![tile::FLOOR; 300 * 3];
+        let mut state = State::new_on_map(SEED, Box::leak(Box::new(test_map(tiles.leak(), 300, (0, 0)))));
+        state.add_entity(Entity { kind: tile::LARGE_POT, x: xy::x(299), y: xy::y(2) });
+
+        let mut loaded = State::load_in_world(&state.save(), state.world).unwrap();
+
+        assert_eq!(loaded.get_effective_tile(xy::x(299), xy::y(2)), Some(tile::LARGE_POT));
+    }
+
+    /// Saved 300 frames into the second loop, with a button pressed, and
+    /// some things learned.
+    const VERSION_1: &[u8] = include_bytes!("saves/version_1.tlcs");
+
+    #[test]
+    fn on_a_version_1_save() {
+        let mut state = State::load(VERSION_1).unwrap();
+        let lock = LockId { map: 0, lock: 0 };
+
+        assert_eq!((state.frame_count, state.loop_count), (300, 1));
+        assert_eq!((state.entities.player.x, state.entities.player.y), (xy::x(101), xy::y(90)));
+        assert_eq!(state.entities.echo.as_ref().map(|echo| (echo.x, echo.y)), Some((xy::x(99), xy::y(91))));
+        assert_eq!((state.echo.next, state.echo.steps.len(), state.recording.len()), (17, 17, 25));
+        assert!(matches!(
+            state.mob_states[6].seek,
+            SeekState::MovingTowards(x, y) if (x, y) == (xy::x(54), xy::y(68))
+        ));
+        assert_eq!(state.get_effective_tile(xy::x(102), xy::y(99)), Some(tile::BUTTON_DARK));
+
+        let password_lock = &state.password_locks[0][0];
+        // South, east, north, then west.
+        assert_eq!(password_lock.sequence, [2, 1, 0, 3]);
+        assert_eq!(password_lock.open, [true, false, false, false]);
+        assert_eq!(password_lock.press_count, 1);
+
+        assert!(matches!(state.message_info, MessageInfo::PasswordReveal { lock: l, index: 1 } if l == lock));
+        assert_eq!(state.journal.entries(), [
+            Fact::PasswordReveal { lock, index: 2 },
+            Fact::SpecialGrave,
+            Fact::Heard { map: 0, node: 3 },
+        ]);
+        assert!(state.journal.has_flag(5));
+        assert_eq!(state.special_grave, (maps::MAP.special_grave_x, maps::MAP.special_grave_y));
+
+        let bytes = state.save();
+        assert_eq!(State::load(&bytes).unwrap().save(), bytes);
+
+        let mut speaker = Speaker::default();
+        for frame in 0..100 {
+            state.frame(input_for(frame), &mut speaker);
+        }
+    }
+}
+
+#[cfg(test)]
+mod rewind_works {
+    use super::*;
+
+    #[test]
+    fn on_another_map() {
+        let mut state = State::new_on_map([42; 16], &maps::CRYPT);
+        let mut speaker = Speaker::default();
+        let start = (state.entities.player.x, state.entities.player.y);
+
+        // Take a few steps, far enough apart for snapshots to be taken between them.
+        for frame in 0..48 {
+            let gamepad = if frame % 16 == 0 { Button::RIGHT } else { <_>::default() };
+            state.frame(Input { gamepad, ..<_>::default() }, &mut speaker);
+        }
+        assert_eq!(state.entities.player.x, start.0 + W::ONE + W::ONE + W::ONE);
+
+        let held = Input { gamepad: Button::B, previous_gamepad: Button::B };
+        for _ in 0..REWIND_SNAPSHOT_COUNT * 4 {
+            state.frame(held, &mut speaker);
+        }
+
+        assert_eq!((state.entities.player.x, state.entities.player.y), start);
+        assert!(core::ptr::eq(state.map, &maps::CRYPT));
+        assert_eq!(state.world.maps.len(), 1);
+    }
+
+    #[test]
+    fn forgetting_the_steps_undone() {
+        let mut state = State::new([42; 16]);
+        let mut speaker = Speaker::default();
+
+        for frame in 0..48 {
+            let gamepad = if frame % 16 == 0 { Button::RIGHT } else { <_>::default() };
+            state.frame(Input { gamepad, ..<_>::default() }, &mut speaker);
+        }
+        assert!(!state.recording.is_empty());
+
+        let held = Input { gamepad: Button::B, previous_gamepad: Button::B };
+        for _ in 0..REWIND_SNAPSHOT_COUNT * 4 {
+            state.frame(held, &mut speaker);
+        }
+
+        assert!(state.recording.is_empty());
+    }
+
+    #[test]
+    fn only_after_the_delay_since_the_last_pull() {
+        let mut state = State::new([42; 16]);
+        let mut speaker = Speaker::default();
 
-                self.add_entity(Entity {
-                    kind: tile::FLOOR,
-                    x: self.entities.player.x,
-                    y: self.entities.player.y,
-                });
-            }
-            Some(tile::BUTTON_LIT) => {
-                output = self.entity_on_button(self.entities.player.x, self.entities.player.y);
-            }
-            _ => {}
+        let held = Input { gamepad: Button::B, previous_gamepad: Button::B };
+        for _ in 0..REWIND_DELAY_FRAMES - 1 {
+            state.frame(held, &mut speaker);
         }
+        assert_eq!(state.rewind.held_frames, REWIND_DELAY_FRAMES - 1);
 
-        output
+        let mut b_and_right = Button::B;
+        b_and_right.insert(Button::RIGHT);
+
+        state.frame(Input { gamepad: b_and_right, previous_gamepad: Button::B }, &mut speaker);
+        assert_eq!(state.rewind.held_frames, 0);
+
+        state.frame(held, &mut speaker);
+        assert_eq!(state.rewind.held_frames, 1);
     }
 }
 
-#[derive(Debug)]
-pub struct Segment {
-    pub text: &'static [u8],
+#[derive(Clone, Debug)]
+pub struct Segment<'text> {
+    pub text: &'text [u8],
     pub start: usize,
     pub end: usize,
     pub x: X,
     pub y: Y,
 }
 
-impl Segment {
-    const DEFAULT: Self = Self {
-        text: b"",
-        start: 0,
-        end: 0,
-        x: X::ZERO,
-        y: Y::ZERO,
-    };
-
+impl Segment<'_> {
     pub fn as_slice(&self) -> &[u8] {
         &self.text[self.start..self.end]
     }
@@ -1324,7 +6607,7 @@ macro_rules! segment_literal {
 }
 
 
-static CONGRATURATION_LINES: [Segment; 2] =
+static CONGRATURATION_LINES: [Segment<'static>; 2] =
     [
         segment_literal!(
             text: b"congraturation",
@@ -1342,110 +6625,142 @@ const TEXT_BOX_TOP: Y = xy::y(24);
 const TEXT_BOX_FIRST_COLUMN: X = xy::x(1);
 const TEXT_BOX_FIRST_LINE: Y = xy::y(25);
 const TEXT_BOX_USUABLE_WIDTH: usize = 30;
+const CHOICE_MARKER_COLUMN: X = xy::x(1);
+const CHOICE_COLUMN: X = xy::x(3);
+
+const JOURNAL_FIRST_LINE: Y = xy::y(3);
+/// The title, then what goes under it when there is nothing in the journal.
+static JOURNAL_LITERALS: [Segment<'static>; 2] = [
+    segment_literal!(
+        text: b"what you know",
+        x: xy::x(1),
+        y: xy::y(1),
+    ),
+    segment_literal!(
+        text: b"nothing yet",
+        x: TEXT_BOX_FIRST_COLUMN,
+        y: JOURNAL_FIRST_LINE,
+    ),
+];
+// Leave the last line blank, to match the bottom of the text box.
+const JOURNAL_LAST_LINE: Y = xy::y(29);
 
 
-struct SegmentSlice {
-    segments: [Segment; 16],
-    length: usize,
-}
-
-impl SegmentSlice {
-    fn as_slice(&self) -> &[Segment] {
-        &self.segments[..self.length]
-    }
-}
-
-static NORTH_0_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the north button first");
-static NORTH_1_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the north button second");
-static NORTH_2_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the north button third");
-static NORTH_3_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the north button fourth");
-static EAST_0_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the east button first");
-static EAST_1_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the east button second");
-static EAST_2_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the east button third");
-static EAST_3_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the east button fourth");
-static SOUTH_0_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the south button first");
-static SOUTH_1_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the south button second");
-static SOUTH_2_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the south button third");
-static SOUTH_3_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the south button fourth");
-static WEST_0_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the west button first");
-static WEST_1_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the west button second");
-static WEST_2_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the west button third");
-static WEST_3_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"push the west button fourth");
-static PASSWORD_REVEAL_REFUSAL_MESSAGE: SegmentSlice = fit_in_text_box(b"someone else told already. i won't.");
-static MISSING_PASSWORD_REVEAL_MESSAGE: SegmentSlice = fit_in_text_box(b"missing_password_reveal_message");
-static FORGOT_PASSWORD_MESSAGE: SegmentSlice = fit_in_text_box(b"i forgot my part of the password");
-
-static RAMBLE_MESSAGES: [SegmentSlice; 10] = [
-    fit_in_text_box(b"let me tell you a story from my childhood."),
-    // TODO randomize the special gravestone placement?
-    fit_in_text_box(b"one day we were all playing in the graveyard. we must have run past a dozen rows of graves."),
-    fit_in_text_box(b"but then we were bushed so we walked past all eight of the johnson family's graves."),
-    fit_in_text_box(b"that's the trouble with kids these days, they don't know how to walk it off!"),
-    fit_in_text_box(b"then blair had the \"bright idea\" to stop and lean against one of the gravestones."),
-    fit_in_text_box(b"that kid always had an aversion to laying down right in a field for some reason. anyway..."),
-    fit_in_text_box(b"well blair ended up pushing the bloody thing out of its proper place!"),
-    fit_in_text_box(b"we quickly put the thing back and booked it out of there before anyone noticed"),
-    fit_in_text_box(b"and no one ever did! that's the moral of the story kid: it only counts if you get caught"),
-    fit_in_text_box(b"that's the trouble with kids these days, imagining some kind of absolute morality, independent of the consquences! pshaw!"),
-];
+const MISSING_PASSWORD_REVEAL_MESSAGE: &[u8] = b"missing_password_reveal_message";
+const MISSING_LOCK_REWARD_MESSAGE: &[u8] = b"missing_lock_reward_message";
+const MISSING_TIMELINE_MESSAGE: &[u8] = b"missing_timeline_message";
+const MISSING_DIALOGUE_MESSAGE: &[u8] = b"missing_dialogue_message";
 
-static RAMBLE_FALLBACK_MESSAGE: SegmentSlice = fit_in_text_box(b"... blah ... blah ... blah ...");
+const SPECIAL_GRAVE_JOURNAL_ENTRY: &[u8] = b"one of the johnson family's graves was out of place. someone was hiding something behind it.";
 
-static GHOST_OOO_MESSAGE: SegmentSlice = fit_in_text_box(b"... Ooooo ... ooooo .. ooooo! ...");
+/// "push the north button first", say.
+fn password_reveal_text(name: &str, index: ButtonIndex) -> Vec<u8> {
+    format!("push the {name} button {}", ordinal(index as usize + 1)).into_bytes()
+}
+
+/// A `dialogue::Text::SpecialGrave` with the directions filled in.
+fn special_grave_text(template: &[u8], rows: usize, graves: usize) -> Vec<u8> {
+    String::from_utf8_lossy(template)
+        .replace("{rows}", &number_words(rows))
+        .replace("{graves}", &number_words(graves))
+        .into_bytes()
+}
+
+/// "one", "two", and so on up to "twenty", except that twelve is "a dozen",
+/// then "21", "22", and so on.
+fn number_words(n: usize) -> String {
+    const WORDS: [&str; 21] = [
+        "zero", "one", "two", "three", "four", "five", "six", "seven", "eight",
+        "nine", "ten", "eleven", "a dozen", "thirteen", "fourteen", "fifteen",
+        "sixteen", "seventeen", "eighteen", "nineteen", "twenty",
+    ];
+
+    match WORDS.get(n) {
+        Some(word) => (*word).to_owned(),
+        None => n.to_string(),
+    }
+}
 
-const fn fit_in_text_box(s: &'static [u8]) -> SegmentSlice {
-    let mut segments = [Segment::DEFAULT; 16];
-    let mut length = 0;
+/// "first", "second", and so on, then "11th", "12th", and so on.
+fn ordinal(n: usize) -> String {
+    const WORDS: [&str; 10] = [
+        "first", "second", "third", "fourth", "fifth",
+        "sixth", "seventh", "eighth", "ninth", "tenth",
+    ];
+
+    if let Some(word) = n.checked_sub(1).and_then(|i| WORDS.get(i)) {
+        return (*word).to_owned()
+    }
+
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+
+    format!("{n}{suffix}")
+}
 
-    // This curently panics if we have too many segments!
+/// Where each line of `s` starts and ends, when broken between words so that
+/// each line fits in the text box.
+fn fit_in_text_box(s: &[u8]) -> Vec<(usize, usize)> {
+    let mut lines = Vec::new();
 
     let mut line_start_index = 0;
     let mut end_of_last_word = 0;
-    let mut y = TEXT_BOX_FIRST_LINE;
     // Iterating like this assumes we are dealing with ASCII, not Unicode!
-    let mut i = 0;
-    while i < s.len() {
-        if s[i] == b' ' {
-            let mut end_of_word = i + 1;
-            while end_of_word < s.len() {
-                if s[end_of_word] == b' ' {
-                    break
-                }
-                end_of_word += 1;
-            }
+    for (i, &c) in s.iter().enumerate() {
+        if c == b' ' {
+            let end_of_word = s[i + 1..].iter()
+                .position(|&c| c == b' ')
+                .map_or(s.len(), |length| i + 1 + length);
+
             let width = end_of_word - line_start_index;
             if width >= TEXT_BOX_USUABLE_WIDTH {
-                segments[length] = Segment {
-                    text: s,
-                    start: line_start_index,
-                    end: end_of_last_word,
-                    x: TEXT_BOX_FIRST_COLUMN,
-                    y,
-                };
-                length += 1;
-                y = xy::const_add_h(y, H::ONE);
+                lines.push((line_start_index, end_of_last_word));
                 line_start_index = i + 1;
             }
             end_of_last_word = end_of_word;
         }
-
-        i += 1;
     }
-    segments[length] = Segment {
-        text: s,
-        start: line_start_index,
-        end: s.len(),
-        x: TEXT_BOX_FIRST_COLUMN,
-        y,
-    };
-    length += 1;
+    lines.push((line_start_index, s.len()));
 
-    SegmentSlice {
-        segments,
-        length,
+    lines
+}
+
+/// Text laid out to fit in the text box. It is owned, since some of it gets
+/// made up as the game goes, like which button to push.
+#[derive(Default)]
+struct FittedText {
+    text: Vec<u8>,
+    /// Where each line starts and ends in `text`.
+    lines: Vec<(usize, usize)>,
+}
+
+impl FittedText {
+    fn new(text: Vec<u8>) -> Self {
+        let lines = fit_in_text_box(&text);
+
+        Self { text, lines }
     }
 }
 
+/// The text that can be on screen, laid out when it changes, instead of every
+/// time it is rendered.
+#[derive(Default)]
+struct Texts {
+    /// What `message` was made from. The choice is left at zero, since which
+    /// choice is selected does not change the text.
+    message_key: (MapIndex, MessageInfo),
+    message: FittedText,
+    /// One for each entry in the journal.
+    journal: Vec<FittedText>,
+    #[cfg(feature = "editor")]
+    editor_status: FittedText,
+}
+
 #[cfg(test)]
 mod fit_in_text_box_works {
     use super::*;
@@ -1454,16 +6769,10 @@ mod fit_in_text_box_works {
         bytes.split(|&c| c == b' ').collect()
     }
 
-    fn segment_slice_words(segment_slice: &SegmentSlice) -> Vec<&[u8]> {
-        let segments = segment_slice.as_slice();
-
-        let mut output = Vec::with_capacity(segments.len() * 16);
-
-        for segment in segments {
-            output.extend(bytes_words(segment.as_slice()));
-        }
-
-        output
+    fn lines_words<'text>(text: &'text [u8], lines: &[(usize, usize)]) -> Vec<&'text [u8]> {
+        lines.iter()
+            .flat_map(|&(start, end)| bytes_words(&text[start..end]))
+            .collect()
     }
 
     #[test]
@@ -1472,21 +6781,66 @@ mod fit_in_text_box_works {
 
         let expected: Vec<_> = bytes_words(TEXT);
 
-        let ss = fit_in_text_box(TEXT);
+        let lines = fit_in_text_box(TEXT);
 
-        let actual = segment_slice_words(&ss);
+        let actual = lines_words(TEXT, &lines);
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn on_text_longer_than_sixteen_lines() {
+        let text = b"pshaw! ".repeat(100);
+
+        let lines = fit_in_text_box(&text);
+
+        assert!(lines.len() > 16, "{}", lines.len());
+        assert!(lines.iter().all(|&(start, end)| end - start < TEXT_BOX_USUABLE_WIDTH));
+        assert_eq!(lines_words(&text, &lines), bytes_words(&text));
+    }
+}
+
+#[cfg(test)]
+mod refresh_texts_works {
+    use super::*;
+
+    #[test]
+    fn on_a_message_that_stays_up() {
+        let mut state = State::new([42; 16]);
+        let mut speaker = Speaker::default();
+
+        state.message_info = MessageInfo::PasswordReveal { lock: LockId::default(), index: 0 };
+        state.frame(Input::default(), &mut speaker);
+
+        let laid_out = state.texts.message.text.as_ptr();
+        let segments: Vec<_> = state.render_info().message_segments.collect();
+        assert!(!segments.is_empty());
+        assert!(segments.iter().all(|segment| segment.text.as_ptr() == laid_out));
+
+        state.frame(Input::default(), &mut speaker);
+        assert_eq!(state.texts.message.text.as_ptr(), laid_out);
+
+        state.message_info = MessageInfo::NoMessage;
+        state.frame(Input::default(), &mut speaker);
+        assert_eq!(state.render_info().message_segments.count(), 0);
+    }
 }
 
 pub struct RenderInfo<'state> {
     pub tiles: CurrentTiles<'state>,
     pub text_boxes: TextBoxes,
-    pub message_segments: MessageSegments,
+    pub message_segments: MessageSegments<'state>,
+    pub overview: OverviewCells<'state>,
     pub hud: Hud<'state>,
 }
 
+/// A patch of the screen to fill with one colour, for the overview map.
+#[derive(Clone, Debug)]
+pub struct OverviewCell {
+    pub rect: unscaled::Rect,
+    pub colour: PaletteIndex,
+}
+
 #[derive(Default)]
 pub struct Print {
     pub text: [u8; 16],
@@ -1499,18 +6853,301 @@ pub struct Hud<'prints> {
 }
 
 pub type TextBoxes = core::option::IntoIter<Rect>;
-pub type MessageSegments = std::slice::Iter<'static, Segment>;
+
+/// The text on screen, taken straight from what was laid out in `Texts`.
+pub struct MessageSegments<'state> {
+    state: &'state State,
+    /// Segments at fixed places, shown before anything else.
+    literals: core::slice::Iter<'static, Segment<'static>>,
+    /// Laid out texts, one below the other.
+    texts: core::slice::Iter<'state, FittedText>,
+    /// The text that `lines` are from.
+    text: Option<&'state [u8]>,
+    lines: core::slice::Iter<'state, (usize, usize)>,
+    /// Where the next line goes.
+    y: Y,
+    /// How many blank lines to leave after each text.
+    gap: H,
+    /// The last line a text can reach. A text that would go past it is left
+    /// out, along with every text after it.
+    last_line: Option<Y>,
+    /// The dialogue choices to list under the texts, some of which may not be
+    /// on offer.
+    choices: core::slice::Iter<'static, dialogue::Choice>,
+    /// How many choices on offer have been listed so far.
+    choice_count: ChoiceIndex,
+    selected: ChoiceIndex,
+    /// The selected choice, which comes after its marker.
+    pending: Option<Segment<'static>>,
+}
+
+impl<'state> MessageSegments<'state> {
+    fn new(state: &'state State) -> Self {
+        Self {
+            state,
+            literals: [].iter(),
+            texts: [].iter(),
+            text: None,
+            lines: [].iter(),
+            y: TEXT_BOX_FIRST_LINE,
+            gap: H::ZERO,
+            last_line: None,
+            choices: [].iter(),
+            choice_count: 0,
+            selected: 0,
+            pending: None,
+        }
+    }
+}
+
+impl<'state> Iterator for MessageSegments<'state> {
+    type Item = Segment<'state>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(literal) = self.literals.next() {
+            return Some(literal.clone())
+        }
+
+        loop {
+            if let (Some(text), Some(&(start, end))) = (self.text, self.lines.next()) {
+                let y = self.y;
+                self.y += H::ONE;
+                return Some(Segment { text, start, end, x: TEXT_BOX_FIRST_COLUMN, y })
+            }
+
+            if self.text.take().is_some() {
+                self.y += self.gap;
+            }
+
+            let Some(fitted) = self.texts.next() else {
+                break
+            };
+
+            if self.last_line.is_some_and(|last_line| {
+                self.y + xy::h(fitted.lines.len() as _) > last_line + H::ONE
+            }) {
+                self.texts = [].iter();
+                break
+            }
+
+            self.text = Some(&fitted.text);
+            self.lines = fitted.lines.iter();
+        }
+
+        if let Some(segment) = self.pending.take() {
+            return Some(segment)
+        }
+
+        let offered = self.choices.by_ref()
+            .find(|choice| self.state.all_hold(choice.conditions))?;
+
+        let y = self.y;
+        self.y += H::ONE;
+
+        let segment = Segment {
+            text: offered.text,
+            start: 0,
+            end: offered.text.len(),
+            x: CHOICE_COLUMN,
+            y,
+        };
+
+        let is_selected = self.choice_count == self.selected;
+        self.choice_count = self.choice_count.saturating_add(1);
+
+        if is_selected {
+            self.pending = Some(segment);
+
+            return Some(segment_literal!(
+                text: b">",
+                x: CHOICE_MARKER_COLUMN,
+                y: y,
+            ))
+        }
+
+        Some(segment)
+    }
+}
+
+/// How many pixels across, and down, each tile is on the overview map.
+const OVERVIEW_CELL_SIZE: unscaled::Inner = 2;
+/// Below the HUD.
+const OVERVIEW_TOP: unscaled::Y = unscaled::Y(16);
+
+/// How many tiles across the overview map shows at once.
+const OVERVIEW_W: W = xy::w(command::WIDTH / OVERVIEW_CELL_SIZE);
+/// How many tiles down the overview map shows at once.
+const OVERVIEW_H: H = xy::h((command::HEIGHT - OVERVIEW_TOP.0) / OVERVIEW_CELL_SIZE);
+
+mod overview_colour {
+    use super::PaletteIndex;
+
+    pub const BLUE: PaletteIndex = 0;
+    pub const GREEN: PaletteIndex = 1;
+    pub const RED: PaletteIndex = 2;
+    pub const YELLOW: PaletteIndex = 3;
+    pub const PURPLE: PaletteIndex = 4;
+    pub const GREY: PaletteIndex = 5;
+    pub const BLACK: PaletteIndex = 7;
+}
+
+/// The colour of a tile on the overview map, from what the tile does, so new
+/// kinds of tiles show up without any extra work.
+fn overview_colour(properties: properties::Properties) -> PaletteIndex {
+    use overview_colour::*;
+
+    if properties.has(properties::INTERACTABLE) {
+        BLUE
+    } else if properties.has(properties::OPAQUE) {
+        PURPLE
+    } else if properties.has(properties::LANDMARK) {
+        YELLOW
+    } else if properties.has(properties::WALKABLE) {
+        GREY
+    } else {
+        BLACK
+    }
+}
+
+/// The furthest the overview map can scroll on the map.
+fn overview_max(map: Map) -> (X, Y) {
+    (X::ZERO + (map.width - OVERVIEW_W), Y::ZERO + (map.height - OVERVIEW_H))
+}
+
+/// Where to scroll the overview map to when it is opened, so that `x`, `y`
+/// is in the middle, if there is room for that.
+fn overview_start(map: Map, x: X, y: Y) -> (X, Y) {
+    let (max_x, max_y) = overview_max(map);
+
+    ((x - OVERVIEW_W.halve()).min(max_x), (y - OVERVIEW_H.halve()).min(max_y))
+}
+
+/// The tiles of the overview map, scrolled so `view_x`, `view_y` is at the
+/// top left, then a bigger cell for the player.
+pub struct OverviewCells<'state> {
+    state: &'state State,
+    view_x: X,
+    view_y: Y,
+    show_visited: bool,
+    /// How far the next tile is from the top left of the view.
+    x_offset: usize,
+    y_offset: usize,
+    /// How many tiles across, and down, to show.
+    width: usize,
+    height: usize,
+    player: Option<OverviewCell>,
+}
+
+impl<'state> OverviewCells<'state> {
+    fn new(state: &'state State) -> Self {
+        let Screen::Overview { x: view_x, y: view_y, show_visited } = state.screen else {
+            return Self {
+                state,
+                view_x: X::ZERO,
+                view_y: Y::ZERO,
+                show_visited: false,
+                x_offset: 0,
+                y_offset: 0,
+                width: 0,
+                height: 0,
+                player: None,
+            }
+        };
+
+        let map = state.map;
+        let (player_x, player_y) = (state.entities.player.x, state.entities.player.y);
+
+        let mut output = Self {
+            state,
+            view_x,
+            view_y,
+            show_visited,
+            x_offset: 0,
+            y_offset: 0,
+            width: OVERVIEW_W.usize().min(map.width.usize()),
+            height: OVERVIEW_H.usize().min(map.height.usize()),
+            player: None,
+        };
+
+        if (view_x..view_x + OVERVIEW_W).contains(&player_x)
+        && (view_y..view_y + OVERVIEW_H).contains(&player_y) {
+            output.player = Some(OverviewCell {
+                rect: output.cell(player_x, player_y, 1),
+                colour: overview_colour::RED,
+            });
+        }
+
+        output
+    }
+
+    fn cell(&self, x: X, y: Y, grow: unscaled::Inner) -> unscaled::Rect {
+        let size = OVERVIEW_CELL_SIZE + grow * 2;
+        unscaled::Rect {
+            x: unscaled::X(((x - self.view_x).get().get() * OVERVIEW_CELL_SIZE).saturating_sub(grow)),
+            y: OVERVIEW_TOP + unscaled::H(((y - self.view_y).get().get() * OVERVIEW_CELL_SIZE).saturating_sub(grow)),
+            w: unscaled::W(size),
+            h: unscaled::H(size),
+        }
+    }
+}
+
+impl Iterator for OverviewCells<'_> {
+    type Item = OverviewCell;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let state = self.state;
+        let map = state.map;
+
+        while self.y_offset < self.height {
+            if self.x_offset >= self.width {
+                self.x_offset = 0;
+                self.y_offset += 1;
+                continue
+            }
+
+            let x = self.view_x + xy::w(self.x_offset as _);
+            let y = self.view_y + xy::h(self.y_offset as _);
+            self.x_offset += 1;
+
+            let Some(kind) = get_effective_tile_custom(map, &state.entities, x, y, NO_MOBS) else {
+                continue
+            };
+
+            let colour = if self.show_visited && state.visited.has(state.map_index, map, x, y) {
+                overview_colour::GREEN
+            } else {
+                overview_colour(map.properties(kind))
+            };
+
+            return Some(OverviewCell { rect: self.cell(x, y, 0), colour })
+        }
+
+        self.player.take()
+    }
+}
 
 impl State {
+    /// The map and entities for the camera to show, and where to centre it.
+    fn camera_subject(&self) -> (Map, &Entities, X, Y) {
+        #[cfg(feature = "editor")]
+        if let (Screen::Editor, Some(editor)) = (self.screen, &self.editor) {
+            return (editor.base, &editor.entities, editor.cursor_x, editor.cursor_y)
+        }
+
+        (self.map, &self.entities, self.entities.player.x, self.entities.player.y)
+    }
+
     pub fn render_info(&self) -> RenderInfo<'_> {
-        let map_w = self.map.width;
-        let map_h = self.map.height;
+        let (map, entities, focus_x, focus_y) = self.camera_subject();
+
+        let map_w = map.width;
+        let map_h = map.height;
 
         let output_width = xy::w(32).clamp(W::ZERO, map_w);
         let output_height = (TEXT_BOX_TOP - Y::ZERO).clamp(H::ZERO, map_h);
 
-        let mut offset_x: W = self.entities.player.x - (X::ZERO + output_width.halve());
-        let mut offset_y: H = self.entities.player.y - (Y::ZERO + output_height.halve());
+        let mut offset_x: W = focus_x - (X::ZERO + output_width.halve());
+        let mut offset_y: H = focus_y - (Y::ZERO + output_height.halve());
 
         // Want to clamp the offset such that we never see the edge of the world.
         // So when output_width == self.map.width, we want the offset to always
@@ -1521,105 +7158,88 @@ impl State {
         offset_y = offset_y.clamp(H::ZERO, map_h - output_height);
 
         let mut camera = CameraIter {
-            map: self.map,
-            entities: &self.entities,
+            map,
+            entities,
             offset_x,
             offset_y,
             output_width,
             output_height,
             done: false,
             tile: Tile::default(),
+            pending: Vec::new(),
+            pending_tile: Tile::default(),
         };
 
-        let message_segments: &'static [Segment] = match (self.screen, &self.message_info) {
-            (Screen::Congraturation, _) => &CONGRATURATION_LINES,
-            (Screen::Gameplay, &MessageInfo::NoMessage) => {&[]},
-            (Screen::Gameplay, &MessageInfo::PasswordReveal { index, }) => {
-                match (self.password_lock.names[index], index) {
-                    ("north", 0) => NORTH_0_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("north", 1) => NORTH_1_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("north", 2) => NORTH_2_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("north", 3) => NORTH_3_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("west", 0) => WEST_0_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("west", 1) => WEST_1_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("west", 2) => WEST_2_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("west", 3) => WEST_3_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("south", 0) => SOUTH_0_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("south", 1) => SOUTH_1_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("south", 2) => SOUTH_2_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("south", 3) => SOUTH_3_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("east", 0) => EAST_0_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("east", 1) => EAST_1_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("east", 2) => EAST_2_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    ("east", 3) => EAST_3_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                    _ => MISSING_PASSWORD_REVEAL_MESSAGE.as_slice(),
-                }
-            },
-            (Screen::Gameplay, &MessageInfo::PasswordRevealRefused) => {
-                PASSWORD_REVEAL_REFUSAL_MESSAGE.as_slice()
-            },
-            (Screen::Gameplay, &MessageInfo::ForgotPassword) => {
-                FORGOT_PASSWORD_MESSAGE.as_slice()
-            },
-            (Screen::Gameplay, &MessageInfo::Ramble(index)) => {
-                if let Some(msg) = RAMBLE_MESSAGES.get(index as usize) {
-                    msg.as_slice()
-                } else {
-                    RAMBLE_FALLBACK_MESSAGE.as_slice()
-                }
-            },
-            (Screen::Gameplay, &MessageInfo::GhostOoo) => {
-                GHOST_OOO_MESSAGE.as_slice()
-            },
+        // TODO? Modify rect size based on the text
+        let message_box = {
+            let min_y = Y::ZERO + output_height;
+            Rect {
+                min_x: X::ZERO,
+                min_y,
+                max_x: X::ZERO + output_width,
+                max_y: min_y + xy::h(7),
+            }
         };
 
         let text_box = match self.screen {
             Screen::Gameplay => {
                 match self.message_info {
                     MessageInfo::NoMessage => None,
-                    _ => {
-                        // TODO? Modify rect size based on the text
-                        let min_y = Y::ZERO + output_height;
-                        Some(Rect {
-                            min_x: X::ZERO,
-                            min_y,
-                            max_x: X::ZERO + output_width,
-                            max_y: min_y + xy::h(7),
-                        })
-                    }
+                    _ => Some(message_box),
+                }
+            },
+            #[cfg(feature = "editor")]
+            Screen::Editor => {
+                if self.editor.as_ref().is_some_and(|editor| editor.picker.is_some()) {
+                    // Just the picker, so it is easy to see.
+                    camera.done = true;
                 }
+                Some(message_box)
             },
             Screen::Congraturation => {
                 // No tiles needed
                 camera.done = true;
                 None
             },
+            Screen::Journal { .. } => {
+                // Nothing but text
+                camera.done = true;
+                None
+            },
+            Screen::Overview { .. } => {
+                // The overview takes the place of the tiles.
+                camera.done = true;
+                None
+            },
         };
 
-        let player = Some(Tile {
-            kind: self.entities.player.kind,
-            x: self.entities.player.x - offset_x,
-            y: self.entities.player.y - offset_y,
-        });
-
-        let mut speech_bubbles = [const { None }; SPEECH_BUBBLE_COUNT as _];
-
-        // Tryign to make it easy to add more sources of speech bubles later.
-        const DOG_OFFSET: usize = 0;
+        let player = match self.screen {
+            Screen::Journal { .. }
+            | Screen::Overview { .. } => None,
+            // The editor draws the player with the rest of its entities.
+            #[cfg(feature = "editor")]
+            Screen::Editor => None,
+            Screen::Gameplay
+            | Screen::Congraturation => Some(Tile {
+                kind: self.entities.player.kind,
+                x: self.entities.player.x - offset_x,
+                y: self.entities.player.y - offset_y,
+            }),
+        };
 
-        for dog_i in 0..maps::DOG_COUNT {
-            let bubble_i = dog_i as usize - DOG_OFFSET;
+        let mut speech_bubbles = Vec::with_capacity(self.mob_states.len());
 
-            speech_bubbles[bubble_i] = match self.dog_states[dog_i as usize] {
-                DogState::Sniffing => {
-                    if let Some(dog) = self.entities.get_dog(dog_i) {
+        if let Screen::Gameplay = self.screen {
+            for (mob_state, mob) in self.mob_states.iter().zip(&self.entities.mobs) {
+                speech_bubbles.push(match mob_state.seek {
+                    SeekState::Sniffing => {
                         let xys = xy::eight_neighbors(
-                            dog.x,
-                            dog.y,
+                            mob.x,
+                            mob.y,
                         );
-        
+
                         let (x, y) = xys[(self.frame_count >> 3 & 0b111) as usize];
-        
+
                         Some(
                             Tile {
                                 kind: tile::EXCLAMATION_BUBBLE,
@@ -1627,12 +7247,25 @@ impl State {
                                 y,
                             }
                         )
-                    } else {
-                        None
-                    }
-                },
-                DogState::MovingTowards(..) | DogState::PreSniffing => None,
-            };
+                    },
+                    SeekState::MovingTowards(..) | SeekState::PreSniffing => None,
+                });
+            }
+        }
+
+        #[cfg(feature = "editor")]
+        if let (Screen::Editor, Some(editor)) = (self.screen, &self.editor) {
+            if editor.picker.is_some() {
+                // The picker is placed on the screen, but bubbles are placed
+                // in the world.
+                speech_bubbles.extend(editor.picker_tiles().into_iter().map(|t| Some(Tile {
+                    x: t.x + offset_x,
+                    y: t.y + offset_y,
+                    ..t
+                })));
+            } else {
+                speech_bubbles.push(editor.cursor_tile());
+            }
         }
 
         let bubbles = BubbleIter {
@@ -1652,7 +7285,8 @@ impl State {
                 bubbles,
             },
             text_boxes: text_box.into_iter(),
-            message_segments: message_segments.into_iter(),
+            message_segments: self.message_segments(),
+            overview: OverviewCells::new(self),
             hud: Hud {
                 prints: &self.hud_prints,
             }
@@ -1660,9 +7294,133 @@ impl State {
     }
 }
 
-type SpeechBubbleCount = u8;
+impl State {
+    /// Lays out whatever text has changed since the last time, so rendering
+    /// does not have to.
+    fn refresh_texts(&mut self) {
+        let mut message_info = self.message_info.clone();
+        if let MessageInfo::Dialogue { ref mut choice, .. } = message_info {
+            *choice = 0;
+        }
+        let message_key = (self.map_index, message_info);
+
+        if self.texts.message_key != message_key {
+            self.texts.message = self.message_text().map(FittedText::new).unwrap_or_default();
+            self.texts.message_key = message_key;
+        }
+
+        // Entries only ever get added to the journal.
+        let new_entries: Vec<_> = self.journal.entries()[self.texts.journal.len()..].iter()
+            .map(|&fact| FittedText::new(self.fact_text(fact)))
+            .collect();
+        self.texts.journal.extend(new_entries);
+
+        #[cfg(feature = "editor")]
+        if let Some(editor) = &self.editor {
+            if self.texts.editor_status.text != *editor.status {
+                self.texts.editor_status = FittedText::new(editor.status.to_vec());
+            }
+        }
+    }
+
+    fn message_text(&self) -> Option<Vec<u8>> {
+        Some(match self.message_info {
+            MessageInfo::NoMessage => return None,
+            MessageInfo::PasswordReveal { lock, index } => self.password_reveal_text(lock, index),
+            MessageInfo::Timeline(index) => {
+                match self.map.timeline.get(index as usize).map(|e| e.action) {
+                    Some(timeline::Action::Message(text)) => text.to_vec(),
+                    _ => MISSING_TIMELINE_MESSAGE.to_vec(),
+                }
+            },
+            MessageInfo::LockReward(id) => {
+                let reward = self.world.maps.get(id.map as usize)
+                    .and_then(|map| map.locks.get(id.lock as usize))
+                    .map(|lock| lock.reward);
+                match reward {
+                    Some(locks::Reward::Action(timeline::Action::Message(text))) => text.to_vec(),
+                    _ => MISSING_LOCK_REWARD_MESSAGE.to_vec(),
+                }
+            },
+            MessageInfo::Dialogue { node, .. } => self.node_text(self.map, node),
+        })
+    }
+
+    fn fact_text(&self, fact: Fact) -> Vec<u8> {
+        match fact {
+            Fact::PasswordReveal { lock, index } => self.password_reveal_text(lock, index),
+            Fact::SpecialGrave => SPECIAL_GRAVE_JOURNAL_ENTRY.to_vec(),
+            Fact::Heard { map, node } => match self.world.maps.get(map as usize) {
+                Some(&map) => self.node_text(map, node),
+                None => MISSING_DIALOGUE_MESSAGE.to_vec(),
+            },
+        }
+    }
+
+    fn password_reveal_text(&self, lock: LockId, index: ButtonIndex) -> Vec<u8> {
+        match self.password_button_name(lock, index) {
+            Some(name) => password_reveal_text(name, index),
+            None => MISSING_PASSWORD_REVEAL_MESSAGE.to_vec(),
+        }
+    }
+
+    fn node_text(&self, map: Map, node: NodeIndex) -> Vec<u8> {
+        match map.dialogue.nodes.get(node as usize).map(|n| n.text) {
+            Some(dialogue::Text::Plain(text)) => text.to_vec(),
+            Some(dialogue::Text::PasswordReveal { lock, index }) => {
+                self.password_reveal_text(lock, index)
+            },
+            Some(dialogue::Text::SpecialGrave(template)) => {
+                let (x, y) = self.special_grave;
+                let (rows, graves) = grave_counts(self.world.start_map(), x, y);
+
+                special_grave_text(template, rows, graves)
+            },
+            None => MISSING_DIALOGUE_MESSAGE.to_vec(),
+        }
+    }
+
+    fn message_segments(&self) -> MessageSegments<'_> {
+        let mut output = MessageSegments::new(self);
+
+        match (self.screen, &self.message_info) {
+            (Screen::Congraturation, _) => {
+                output.literals = CONGRATURATION_LINES.iter();
+            },
+            // The journal entries, starting from `scroll`, laid out down the
+            // whole screen, for as many entries as fit.
+            (Screen::Journal { scroll }, _) => {
+                let shown = if self.journal.entries().is_empty() { 2 } else { 1 };
+                output.literals = JOURNAL_LITERALS[..shown].iter();
+                output.texts = self.texts.journal.get(scroll..).unwrap_or_default().iter();
+                output.y = JOURNAL_FIRST_LINE;
+                // A blank line between entries
+                output.gap = H::ONE;
+                output.last_line = Some(JOURNAL_LAST_LINE);
+            },
+            (Screen::Overview { .. }, _) => {},
+            // The node's text, followed by one line for each choice on offer,
+            // with a marker next to the selected one.
+            (Screen::Gameplay, &MessageInfo::Dialogue { node, choice, .. }) => {
+                output.texts = core::slice::from_ref(&self.texts.message).iter();
+                output.choices = self.map.dialogue.nodes
+                    .get(node as usize)
+                    .map_or(&[][..], |node| node.choices)
+                    .iter();
+                output.selected = choice;
+            },
+            (Screen::Gameplay, _) => {
+                output.texts = core::slice::from_ref(&self.texts.message).iter();
+            },
+            #[cfg(feature = "editor")]
+            (Screen::Editor, _) => {
+                output.texts = core::slice::from_ref(&self.texts.editor_status).iter();
+            },
+        }
 
-const SPEECH_BUBBLE_COUNT: SpeechBubbleCount = maps::DOG_COUNT;
+        output
+    }
+}
 
 pub struct CurrentTiles<'camera> {
     camera: CameraIter<'camera>,
@@ -1692,6 +7450,10 @@ impl Iterator for CurrentTiles<'_> {
 
 struct CameraIter<'entities> {
     tile: Tile,
+    /// The rest of what is drawn at `pending_tile`, from the top down, so the
+    /// next one is at the end.
+    pending: Vec<TileKind>,
+    pending_tile: Tile,
     done: bool,
     map: Map,
     entities: &'entities Entities,
@@ -1705,15 +7467,22 @@ impl Iterator for CameraIter<'_> {
     type Item = Tile;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(kind) = self.pending.pop() {
+            return Some(Tile { kind, ..self.pending_tile.clone() })
+        }
+
         if self.done { return None }
 
         let x = self.tile.x + self.offset_x;
         let y = self.tile.y + self.offset_y;
 
-        if let Some(tile_kind) = get_effective_tile(self.map, self.entities, x, y) {
-            self.tile.kind = tile_kind;
+        if let Some(tiles) = tiles_at(self.map, self.entities, x, y) {
+            self.pending.extend(tiles);
+            self.pending.reverse();
 
-            let output = self.tile.clone();
+            self.pending_tile = self.tile.clone();
+            let output = self.pending.pop()
+                .map(|kind| Tile { kind, ..self.pending_tile.clone() });
 
             self.tile.x += W::ONE;
 
@@ -1731,7 +7500,7 @@ impl Iterator for CameraIter<'_> {
                 }
             }
 
-            return Some(output)
+            return output
         }
 
         None
@@ -1740,7 +7509,7 @@ impl Iterator for CameraIter<'_> {
 
 struct BubbleIter {
     tile: Tile,
-    speech_bubbles: [Option<Tile>; SPEECH_BUBBLE_COUNT as usize],
+    speech_bubbles: Vec<Option<Tile>>,
     index: usize,
     offset_x: xy::W,
     offset_y: xy::H,
@@ -1778,4 +7547,70 @@ impl Iterator for BubbleIter {
 
         None
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod lint_works {
+    use super::*;
+
+    #[test]
+    fn on_the_built_in_world() {
+        let problems: Vec<_> = lint::lint(maps::WORLD).into_iter()
+            .map(|(map, problem)| format!("map {map}: {problem}"))
+            .collect();
+
+        assert_eq!(problems, Vec::<String>::new());
+    }
+
+    #[test]
+    fn on_these_mistakes() {
+        use lint::{Object, Problem};
+        use maps::exits::{Exit, Trigger};
+
+        const MOBS: &[(X, Y, maps::mobs::Mob)] = &[
+            (xy::x(55), xy::y(93), maps::mobs::DOG),
+            (xy::x(55), xy::y(93), maps::mobs::DOG),
+        ];
+        const EXITS: &[Exit] = &[
+            Exit { trigger: Trigger::At(xy::x(100), xy::y(91)), map: 5, x: xy::x(0), y: xy::y(0) },
+        ];
+
+        const LOCKS: &[locks::Lock] = &[
+            locks::Lock {
+                buttons: &[
+                    locks::Button { x: xy::x(100), y: xy::y(88), name: "north" },
+                    locks::Button { x: xy::x(103), y: xy::y(100), name: "east" },
+                    locks::Button { x: xy::x(102), y: xy::y(101), name: "south" },
+                    locks::Button { x: xy::x(101), y: xy::y(100), name: "west" },
+                ],
+                length: 4,
+                reward: locks::Reward::Key { x: xy::x(102), y: xy::y(100), door_x: xy::x(98), door_y: xy::y(89) },
+            },
+        ];
+
+        let map: Map = Box::leak(Box::new(maps::Map {
+            mobs: MOBS,
+            locks: LOCKS,
+            exits: EXITS,
+            ..maps::MAP
+        }));
+
+        let problems: Vec<_> = lint::lint(maps::World { maps: Box::leak(Box::new([map])), start: 0 })
+            .into_iter()
+            .map(|(_, problem)| problem)
+            .collect();
+
+        let (x, y) = (xy::x(100), xy::y(88));
+        let what = Object::Button { lock: 0, button: 0 };
+        assert!(problems.contains(&Problem::Blocked { what, x, y }));
+        assert!(problems.contains(&Problem::Unreachable { what, x, y }));
+        assert!(problems.contains(&Problem::SameSpot {
+            what: Object::Mob(0),
+            other: Object::Mob(1),
+            x: xy::x(55),
+            y: xy::y(93),
+        }));
+        assert!(problems.contains(&Problem::UnknownMap { what: Object::Exit(0), map: 5 }));
+        assert_eq!(problems.len(), 4);
+    }
+}
@@ -108,6 +108,26 @@ impl Commands {
         );
     }
 
+    /// Fills the rectangle, which must be no bigger than a tile, with one
+    /// colour.
+    pub fn fill_rect(
+        &mut self,
+        rect: unscaled::Rect,
+        colour: PaletteIndex
+    ) {
+        // The middle of the text box has no transparent pixels, so all of
+        // it gets the colour.
+        const SOLID: TileId = TILES_PER_ROW * 11 + 1;
+
+        self.commands.push(
+            Command {
+                sprite_xy: id_to_xy(SOLID),
+                rect: Rect::from_unscaled(rect),
+                colour_override: PALETTE[colour as usize],
+            }
+        );
+    }
+
     pub fn draw_text_box(
         &mut self,
         min_x: unscaled::X,
@@ -0,0 +1,447 @@
+//! Runs `game::State` without a window, for tests and for poking at the game
+//! from the command line.
+
+use game::RenderInfo;
+use models::{tile, TileKind};
+use platform_types::{Button, Input, Speaker, SFX};
+use xs::Seed;
+
+pub struct Simulation {
+    pub state: game::State,
+    pub input: Input,
+    speaker: Speaker,
+}
+
+impl Simulation {
+    pub fn new(seed: Seed) -> Self {
+        Self::from_state(game::State::new(seed))
+    }
+
+    pub fn new_on_map(seed: Seed, map: &'static maps::Map) -> Self {
+        Self::from_state(game::State::new_on_map(seed, map))
+    }
+
+    fn from_state(state: game::State) -> Self {
+        Self {
+            state,
+            input: Input::default(),
+            speaker: Speaker::default(),
+        }
+    }
+
+    /// Runs a single frame, with exactly the buttons in `gamepad` held down.
+    pub fn step(&mut self, gamepad: Button) {
+        self.speaker.clear();
+
+        self.input.gamepad = gamepad;
+
+        self.state.frame(self.input, &mut self.speaker);
+
+        self.input.previous_gamepad = self.input.gamepad;
+    }
+
+    pub fn run(&mut self, script: &Script) {
+        for &(gamepad, count) in &script.steps {
+            for _ in 0..count {
+                self.step(gamepad);
+            }
+        }
+    }
+
+    /// The sound effects requested during the last frame.
+    pub fn sfx(&self) -> &[SFX] {
+        self.speaker.slice()
+    }
+
+    /// The tiles that would be drawn to the screen, with one character per tile.
+    pub fn tile_grid(&self) -> String {
+        let RenderInfo { tiles, .. } = self.state.render_info();
+
+        let mut rows: Vec<Vec<u8>> = Vec::new();
+
+        for t in tiles {
+            let (x, y) = (t.x.usize(), t.y.usize());
+
+            if rows.len() <= y {
+                rows.resize(y + 1, Vec::new());
+            }
+            let row = &mut rows[y];
+            if row.len() <= x {
+                row.resize(x + 1, b' ');
+            }
+
+            row[x] = tile_char(t.kind);
+        }
+
+        let mut output = String::with_capacity(rows.len() * 33);
+
+        for row in rows {
+            output.extend(row.into_iter().map(char::from));
+            output.push('\n');
+        }
+
+        output
+    }
+
+    /// The message currently being shown, if any, with one line per segment.
+    pub fn message(&self) -> String {
+        let RenderInfo { message_segments, .. } = self.state.render_info();
+
+        let mut output = String::new();
+
+        for segment in message_segments {
+            if !output.is_empty() {
+                output.push('\n');
+            }
+            output.push_str(&String::from_utf8_lossy(segment.as_slice()));
+        }
+
+        output
+    }
+
+    /// The message, but with the lines joined back together, so it can be
+    /// compared against the original text.
+    pub fn message_text(&self) -> String {
+        self.message().replace('\n', " ")
+    }
+
+    pub fn hud(&self) -> String {
+        let RenderInfo { hud, .. } = self.state.render_info();
+
+        let mut output = String::new();
+
+        for print in hud.prints {
+            let end = print.text.iter()
+                .position(|&b| b == 0)
+                .unwrap_or(print.text.len());
+
+            output.push_str(&String::from_utf8_lossy(&print.text[..end]));
+            output.push('\n');
+        }
+
+        output
+    }
+
+    /// Everything that would be visible on screen, as text.
+    pub fn dump(&self) -> String {
+        let mut output = self.tile_grid();
+
+        output.push_str(&self.hud());
+
+        let message = self.message();
+        if !message.is_empty() {
+            output.push_str(&message);
+            output.push('\n');
+        }
+
+        output
+    }
+}
+
+pub fn tile_char(kind: TileKind) -> u8 {
+    match kind {
+        tile::WALL_0
+        | tile::WALL_1
+        | tile::WALL_2
+        | tile::WALL_3
+        | tile::WALL_4
+        | tile::WALL_5
+        | tile::WALL_6
+        | tile::WALL_7
+        | tile::WALL_8
+        | tile::WALL_9
+        | tile::WALL_10
+        | tile::WALL_11
+        | tile::WALL_12
+        | tile::WALL_13
+        | tile::WALL_14
+        | tile::WALL_15
+        | tile::WALL_16
+        | tile::WALL_17
+        | tile::WALL_18
+        | tile::WALL_19
+        | tile::WALL_20
+        | tile::WALL_21
+        | tile::WALL_22
+        | tile::WALL_23
+        | tile::WALL_24
+        | tile::WALL_25
+        | tile::WALL_26 => b'#',
+        tile::DOOR_0
+        | tile::DOOR_1
+        | tile::DOOR_2
+        | tile::DOOR_3
+        | tile::DOOR_4 => b'/',
+        tile::CLOSED_DOOR => b'+',
+        tile::PERSON_0 => b'0',
+        tile::PERSON_1 => b'1',
+        tile::PERSON_2 => b'2',
+        tile::PERSON_3 => b'3',
+        tile::PERSON_4 => b'4',
+        tile::PERSON_5 => b'@',
+        tile::ZOMBIE => b'z',
+        tile::PANOPTIKHAN => b'e',
+        tile::FLOOR => b'.',
+        tile::CRAB => b'c',
+        tile::GHOST_1 => b'g',
+        tile::ECHO => b'&',
+        tile::TURTLE => b't',
+        tile::LARGE_POT => b'o',
+        tile::STAIRS_DOWN => b'>',
+        tile::GROUND => b' ',
+        tile::GRASS_GROUND => b',',
+        tile::KEY => b'k',
+        tile::BUTTON_LIT => b'b',
+        tile::BUTTON_DARK => b'B',
+        tile::BUTTON_PRESSED => b'_',
+        tile::EXCLAMATION_BUBBLE => b'!',
+        tile::GRAVE_1
+        | tile::GRAVE_2 => b'n',
+        tile::PORTAL => b'*',
+        _ if tile::DOGS.contains(&kind) => b'd',
+        _ => b'?',
+    }
+}
+
+/// A sequence of inputs, each held for some number of frames.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Script {
+    pub steps: Vec<(Button, u32)>,
+}
+
+impl Script {
+    pub fn frame_count(&self) -> u64 {
+        self.steps.iter().map(|&(_, count)| u64::from(count)).sum()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ScriptError {
+    UnknownButton { token: String, button: String },
+    BadCount { token: String },
+}
+
+impl core::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ScriptError::UnknownButton { token, button } => write!(
+                f,
+                "Unknown button \"{button}\" in \"{token}\". Expected one of up, down, left, right, a, b, start, select, or \"-\" for no buttons"
+            ),
+            ScriptError::BadCount { token } => write!(
+                f,
+                "Could not parse the frame count in \"{token}\". Expected something like \"left*10\""
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Parses a script made of whitespace separated tokens, each of which is held
+/// for one frame. A token is either `-`, meaning no buttons, or button names
+/// joined with `+`, like `up+a`. A token can be followed by `*` and a number
+/// to hold it for that many frames, like `-*60`. `#` starts a comment that
+/// runs to the end of the line.
+///
+/// Note that moving requires the direction be pressed that frame, so walking
+/// multiple tiles looks like `right - right - right`.
+pub fn parse_script(source: &str) -> Result<Script, ScriptError> {
+    let mut script = Script::default();
+
+    for line in source.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+
+        for token in line.split_whitespace() {
+            let (buttons, count) = match token.split_once('*') {
+                Some((buttons, count)) => (
+                    buttons,
+                    count.parse().map_err(|_| ScriptError::BadCount {
+                        token: token.to_owned(),
+                    })?,
+                ),
+                None => (token, 1),
+            };
+
+            let mut gamepad = Button::default();
+
+            if buttons != "-" {
+                for name in buttons.split('+') {
+                    gamepad.insert(match name.to_ascii_lowercase().as_str() {
+                        "up" | "u" => Button::UP,
+                        "down" | "d" => Button::DOWN,
+                        "left" | "l" => Button::LEFT,
+                        "right" | "r" => Button::RIGHT,
+                        "a" => Button::A,
+                        "b" => Button::B,
+                        "start" => Button::START,
+                        "select" => Button::SELECT,
+                        _ => return Err(ScriptError::UnknownButton {
+                            token: token.to_owned(),
+                            button: name.to_owned(),
+                        }),
+                    });
+                }
+            }
+
+            script.steps.push((gamepad, count));
+        }
+    }
+
+    Ok(script)
+}
+
+/// Parses a seed given either in decimal, or in hex with a leading `0x`.
+pub fn parse_seed(s: &str) -> Option<Seed> {
+    let n = match s.strip_prefix("0x") {
+        Some(hex) => u128::from_str_radix(hex, 16).ok()?,
+        None => s.parse::<u128>().ok()?,
+    };
+
+    Some(n.to_le_bytes())
+}
+
+#[cfg(test)]
+mod parse_script_works {
+    use super::*;
+
+    #[test]
+    fn on_this_example() {
+        let script = parse_script("right - up+a*3 # comment\n-*60").unwrap();
+
+        let mut up_a = Button::UP;
+        up_a.insert(Button::A);
+
+        assert_eq!(
+            script.steps,
+            vec![
+                (Button::RIGHT, 1),
+                (Button::default(), 1),
+                (up_a, 3),
+                (Button::default(), 60),
+            ]
+        );
+        assert_eq!(script.frame_count(), 65);
+    }
+
+    #[test]
+    fn on_an_unknown_button() {
+        assert_eq!(
+            parse_script("up+z"),
+            Err(ScriptError::UnknownButton {
+                token: "up+z".to_owned(),
+                button: "z".to_owned(),
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod simulation_works {
+    use super::*;
+
+    const SEED: Seed = [42; 16];
+
+    const WALK_TO_PERSON_0: &str = "
+        right - right - right - right - right -
+        up - up -
+        left - left -
+    ";
+
+    #[test]
+    fn when_walking_to_person_0_and_pressing_a() {
+        let mut sim = Simulation::new(SEED);
+
+        sim.run(&parse_script(WALK_TO_PERSON_0).unwrap());
+
+        assert_eq!(sim.message(), "");
+
+        sim.run(&parse_script("up up+a").unwrap());
+
+        let message = sim.message_text();
+
+        assert!(
+            message.starts_with("push the ")
+            && message.ends_with(" button first"),
+            "{message}\n{}",
+            sim.dump()
+        );
+    }
+
+    #[test]
+    fn when_walking_away_after_the_message() {
+        let mut sim = Simulation::new(SEED);
+
+        sim.run(&parse_script(WALK_TO_PERSON_0).unwrap());
+        sim.run(&parse_script("up up+a - right").unwrap());
+
+        assert_eq!(sim.message(), "");
+    }
+
+    #[test]
+    fn when_opening_the_journal_after_hearing_the_message() {
+        let mut sim = Simulation::new(SEED);
+
+        sim.run(&parse_script(WALK_TO_PERSON_0).unwrap());
+        sim.run(&parse_script("up up+a").unwrap());
+
+        let heard = sim.message_text();
+
+        sim.run(&parse_script("- select").unwrap());
+
+        let journal = sim.message_text();
+
+        assert!(journal.contains(&heard), "{journal}\n{heard}");
+        assert_eq!(sim.tile_grid(), "");
+
+        // The world is paused while the journal is open.
+        let frame_count = sim.state.frame_count;
+        sim.run(&parse_script("- down - up -*10").unwrap());
+        assert_eq!(sim.state.frame_count, frame_count);
+
+        // On to the overview map, then back to the game.
+        sim.run(&parse_script("select - select").unwrap());
+
+        assert!(sim.tile_grid().contains('@'), "{}", sim.dump());
+    }
+
+    #[test]
+    fn when_holding_b_after_walking() {
+        let mut sim = Simulation::new(SEED);
+
+        let start = (sim.state.entities.player.x, sim.state.entities.player.y);
+
+        sim.run(&parse_script("right - right - right -*20").unwrap());
+
+        assert_ne!((sim.state.entities.player.x, sim.state.entities.player.y), start);
+
+        // Rewinding starts after a moment, in case a direction is pressed.
+        sim.run(&parse_script("b*12").unwrap());
+
+        // Partway back
+        assert!(sim.state.frame_count > 0 && sim.state.frame_count < 25);
+
+        sim.run(&parse_script("b*60").unwrap());
+
+        assert_eq!((sim.state.entities.player.x, sim.state.entities.player.y), start);
+        assert_eq!(sim.state.frame_count, 0);
+
+        // And time goes forward again once B is let go.
+        sim.run(&parse_script("- right").unwrap());
+
+        assert_eq!(sim.state.frame_count, 2);
+        assert_ne!((sim.state.entities.player.x, sim.state.entities.player.y), start);
+    }
+
+    #[test]
+    fn the_grid_is_the_size_of_the_camera() {
+        let sim = Simulation::new(SEED);
+
+        let grid = sim.tile_grid();
+        let lines: Vec<_> = grid.lines().collect();
+
+        assert_eq!(lines.len(), 24);
+        assert!(lines.iter().all(|l| l.len() == 32), "{grid}");
+        assert!(grid.contains('@'), "{grid}");
+    }
+}
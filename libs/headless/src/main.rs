@@ -0,0 +1,110 @@
+const USAGE: &str = "\
+Usage: headless [--seed <seed>] [--frames <n>] [--script <script> | --script-file <path>] [--map-file <path>]
+       headless --lint [--map-file <path>]
+
+Creates a game from the seed, runs the script, then idles until <n> frames have
+passed, if that is more than the script's length. Then prints what would be on
+screen, as text.
+
+With --lint, checks the maps for mistakes instead, like things placed on walls,
+or stairs that cannot be reached. Prints any it finds, and exits with status 1
+if there were any.
+
+<seed> is either decimal, or hex with a leading 0x. Defaults to 0.
+See `headless::parse_script` for the script format.
+See the `map_format` crate for the map file format. Defaults to the built-in maps.";
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{err}\n\n{USAGE}");
+        std::process::exit(2);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut seed = xs::Seed::default();
+    let mut frames = 0;
+    let mut script_source = String::new();
+    let mut map_file = None;
+    let mut lint = false;
+
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("Missing value for {arg}"));
+
+        match arg.as_str() {
+            "--seed" => {
+                let s = value()?;
+                seed = headless::parse_seed(&s)
+                    .ok_or_else(|| format!("Could not parse seed \"{s}\""))?;
+            },
+            "--frames" => {
+                let s = value()?;
+                frames = s.parse()
+                    .map_err(|_| format!("Could not parse frame count \"{s}\""))?;
+            },
+            "--script" => {
+                script_source = value()?;
+            },
+            "--script-file" => {
+                let path = value()?;
+                script_source = std::fs::read_to_string(&path)
+                    .map_err(|err| format!("Could not read {path}: {err}"))?;
+            },
+            "--map-file" => {
+                let path = value()?;
+                let text = std::fs::read_to_string(&path)
+                    .map_err(|err| format!("Could not read {path}: {err}"))?;
+                let loaded = maps::load(&text)
+                    .map_err(|err| format!("{path}: {err}"))?;
+                let map: &'static maps::Map = Box::leak(Box::new(loaded));
+                map_file = Some(map);
+            },
+            "--lint" => {
+                lint = true;
+            },
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                return Ok(())
+            },
+            _ => return Err(format!("Unknown argument {arg}")),
+        }
+    }
+
+    if lint {
+        let world = match map_file {
+            Some(map) => maps::World { maps: Box::leak(Box::new([map])), start: 0 },
+            None => maps::WORLD,
+        };
+
+        let problems = game::lint::lint(world);
+        for (map, problem) in &problems {
+            println!("map {map}: {problem}");
+        }
+
+        if !problems.is_empty() {
+            std::process::exit(1);
+        }
+
+        return Ok(())
+    }
+
+    let script = headless::parse_script(&script_source)
+        .map_err(|err| err.to_string())?;
+
+    let mut sim = match map_file {
+        Some(map) => headless::Simulation::new_on_map(seed, map),
+        None => headless::Simulation::new(seed),
+    };
+
+    sim.run(&script);
+
+    for _ in script.frame_count()..frames {
+        sim.step(<_>::default());
+    }
+
+    print!("{}", sim.dump());
+
+    Ok(())
+}
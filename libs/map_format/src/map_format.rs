@@ -0,0 +1,1009 @@
+//! A text format for maps, so they can be edited and reviewed as text, instead
+//! of as a giant array literal. `maps` reads these with a build script for the
+//! built-in map, and at runtime for any others.
+//!
+//! A map file is a series of lines. Blank lines are ignored, as is everything
+//! after a `#`, except inside the `tiles` section. The lines are:
+//!
+//! * `size <width> <height>`
+//! * `loop_length <frames>`: How many frames pass before time resets. At most
+//!   65536, unless the game is built with a wider frame counter.
+//! * `player <x> <y>`: Where the player starts.
+//! * `special_grave <x> <y>`
+//! * `dialogue <name>`: What is said on this map. Dialogue cannot be described
+//!   in this format, so this names one of the built-in ones, which are looked
+//!   up by `maps`. Without this line, no one on the map says anything.
+//! * `lock <length>`: Starts a lock, made of buttons that need to be pressed
+//!   in a random order, `length` presses long. The `button` and reward lines
+//!   up to the next `lock` line are part of this lock.
+//! * `button <x> <y> <name>`: One per button in the current lock, in order.
+//!   There must be at least as many as the lock's length.
+//! * `key <x> <y> <door_x> <door_y>`: A reward. When the lock opens, a key
+//!   appears at `x`, `y`. Picking it up opens the door at `door_x`, `door_y`.
+//! * `open_door <x> <y>`: A reward. When the lock opens, so does this door.
+//! * `mob <x> <y> <name>`: One per mob, in the order they move in. The names
+//!   are looked up by `maps`.
+//! * `exit <x> <y> <map> <to_x> <to_y>`: Stepping onto `x`, `y` takes the
+//!   player to `to_x`, `to_y` on another map. `map` is that map's index in
+//!   the world it is part of.
+//! * `edge <side> <map> <to_x> <to_y>`: Walking off the `top`, `bottom`,
+//!   `left` or `right` side takes the player to another map. The player keeps
+//!   their position along the side, so one of `to_x` and `to_y` is ignored.
+//! * `tile <kind> <property>...`: Replaces the built-in properties of that
+//!   kind of tile, on this map only. Each property is the name of a flag,
+//!   `name=<name>`, with underscores for spaces, or `depth=<depth>`. They are
+//!   looked up by `maps`. Leaving out a flag turns it off, so `tile 15` makes
+//!   floors that nothing can walk on, but leaving out the depth keeps the
+//!   built-in one.
+//! * `legend`: Starts the legend, where each line is a character, then
+//!   whitespace, then the tile kind that character stands for. `#` and
+//!   whitespace cannot be used as characters.
+//! * `tiles`: Starts the tiles, which must come last. Each of the following
+//!   `height` lines has exactly `width` characters from the legend.
+//!
+//! Each lock needs exactly one reward. Each line other than `dialogue`,
+//! `lock`, `button`, reward, `mob`, `exit`, `edge` and `tile` lines must
+//! appear exactly once. The `dialogue` line can appear at most once, and there
+//! can be at most one `tile` line per kind.
+
+use std::collections::HashMap;
+
+pub type Coord = u32;
+pub type Kind = u32;
+
+/// Something named, at a position on the map.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Placement {
+    pub x: Coord,
+    pub y: Coord,
+    pub name: String,
+    /// The line it was on, for error messages.
+    pub line: usize,
+}
+
+/// Something the format refers to by name, instead of describing it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reference {
+    pub name: String,
+    /// The line it was on, for error messages.
+    pub line: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    At(Coord, Coord),
+    Edge(Side),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reward {
+    Key { x: Coord, y: Coord, door_x: Coord, door_y: Coord },
+    OpenDoor(Coord, Coord),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lock {
+    /// How many presses long the combination is.
+    pub length: u32,
+    pub buttons: Vec<Placement>,
+    pub reward: Reward,
+    /// The line of the `lock` line, for error messages.
+    pub line: usize,
+}
+
+/// A way to another map.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Exit {
+    pub trigger: Trigger,
+    /// The index of the map in the world.
+    pub map: u32,
+    pub x: Coord,
+    pub y: Coord,
+    /// The line it was on, for error messages.
+    pub line: usize,
+}
+
+/// The properties a map gives a kind of tile, in place of the built-in ones.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TileProperties {
+    pub kind: Kind,
+    pub properties: Vec<String>,
+    /// The line it was on, for error messages.
+    pub line: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Description {
+    pub width: Coord,
+    pub height: Coord,
+    /// Row by row, from the top left.
+    pub tiles: Vec<Kind>,
+    pub loop_length: u32,
+    pub player: (Coord, Coord),
+    pub special_grave: (Coord, Coord),
+    pub dialogue: Option<Reference>,
+    pub locks: Vec<Lock>,
+    pub mobs: Vec<Placement>,
+    pub exits: Vec<Exit>,
+    pub tile_properties: Vec<TileProperties>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    UnknownKeyword(String),
+    WrongArgumentCount { keyword: &'static str, expected: usize, found: usize },
+    BadNumber(String),
+    Duplicate(&'static str),
+    Missing(&'static str),
+    OutsideLock(&'static str),
+    NoReward,
+    TooFewButtons { length: u32, found: usize },
+    BadSide(String),
+    BadLegendEntry(String),
+    DuplicateLegendChar(char),
+    UnknownTileChar { c: char, column: usize },
+    WrongRowLength { expected: usize, found: usize },
+    WrongRowCount { expected: usize, found: usize },
+    OffTheMap { x: Coord, y: Coord },
+    TooManyKinds { found: usize },
+    DuplicateTile(Kind),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    /// The line the problem is on, counting from 1, if it is on a particular line.
+    pub line: Option<usize>,
+    pub kind: ErrorKind,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use ErrorKind::*;
+
+        if let Some(line) = self.line {
+            write!(f, "line {line}: ")?;
+        }
+
+        match &self.kind {
+            UnknownKeyword(keyword) => write!(f, "Unknown keyword \"{keyword}\""),
+            WrongArgumentCount { keyword, expected, found } => write!(f, "\"{keyword}\" takes {expected} arguments, but {found} were given"),
+            BadNumber(s) => write!(f, "Could not parse \"{s}\" as a number"),
+            Duplicate(keyword) => write!(f, "\"{keyword}\" was given more than once"),
+            Missing(keyword) => write!(f, "\"{keyword}\" was never given"),
+            OutsideLock(keyword) => write!(f, "\"{keyword}\" needs to come after a \"lock\" line"),
+            NoReward => write!(f, "The lock has no reward. Give it a \"key\" or \"open_door\" line"),
+            TooFewButtons { length, found } => write!(f, "The lock is {length} presses long, but only has {found} buttons"),
+            BadSide(s) => write!(f, "Expected top, bottom, left or right, but got \"{s}\""),
+            BadLegendEntry(s) => write!(f, "Expected a character, then a tile kind, but got \"{s}\""),
+            DuplicateLegendChar(c) => write!(f, "'{c}' is in the legend more than once"),
+            UnknownTileChar { c, column } => write!(f, "'{c}', in column {column}, is not in the legend"),
+            WrongRowLength { expected, found } => write!(f, "Expected a row of {expected} tiles, but it had {found}"),
+            TooManyKinds { found } => write!(f, "There are {found} different tile kinds, but only {} characters to write them with", LEGEND_CHARS.chars().count()),
+            WrongRowCount { expected, found } => write!(f, "Expected {expected} rows of tiles, but there were {found}"),
+            OffTheMap { x, y } => write!(f, "({x}, {y}) is off the map"),
+            DuplicateTile(kind) => write!(f, "Tile kind {kind} has a \"tile\" line already"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A lock that is still being parsed.
+struct PartialLock {
+    length: u32,
+    buttons: Vec<Placement>,
+    reward: Option<Reward>,
+    line: usize,
+}
+
+impl PartialLock {
+    fn finish(self) -> Result<Lock, Error> {
+        let err = |kind| Error { line: Some(self.line), kind };
+
+        let Some(reward) = self.reward else {
+            return Err(err(ErrorKind::NoReward))
+        };
+
+        if self.buttons.len() < self.length as usize {
+            return Err(err(ErrorKind::TooFewButtons { length: self.length, found: self.buttons.len() }))
+        }
+
+        Ok(Lock { length: self.length, buttons: self.buttons, reward, line: self.line })
+    }
+}
+
+enum Section {
+    Header,
+    Legend,
+    Tiles,
+}
+
+pub fn parse(text: &str) -> Result<Description, Error> {
+    let mut size = None;
+    let mut loop_length = None;
+    let mut player = None;
+    let mut special_grave = None;
+    let mut dialogue = None;
+    let mut locks = Vec::new();
+    let mut lock: Option<PartialLock> = None;
+    let mut mobs = Vec::new();
+    let mut exits = Vec::new();
+    let mut tile_properties: Vec<TileProperties> = Vec::new();
+    let mut legend = HashMap::new();
+    let mut tiles = Vec::new();
+    let mut row_count = 0;
+
+    let mut section = Section::Header;
+
+    for (i, raw_line) in text.lines().enumerate() {
+        let line = i + 1;
+        let err = |kind| Error { line: Some(line), kind };
+
+        if let Section::Tiles = section {
+            if raw_line.is_empty() {
+                continue
+            }
+
+            let Some((width, _)) = size else {
+                return Err(err(ErrorKind::Missing("size")))
+            };
+
+            let mut row_length = 0;
+            for (column, c) in raw_line.chars().enumerate() {
+                let Some(&kind) = legend.get(&c) else {
+                    return Err(err(ErrorKind::UnknownTileChar { c, column: column + 1 }))
+                };
+                tiles.push(kind);
+                row_length += 1;
+            }
+
+            if row_length != width as usize {
+                return Err(err(ErrorKind::WrongRowLength { expected: width as usize, found: row_length }))
+            }
+
+            row_count += 1;
+            continue
+        }
+
+        let content = raw_line.split('#').next().unwrap_or_default().trim();
+        if content.is_empty() {
+            continue
+        }
+
+        if let Section::Legend = section {
+            if content != "tiles" {
+                let mut chars = content.chars();
+                let c = chars.next().unwrap_or_default();
+                let rest = chars.as_str();
+
+                if !rest.starts_with(char::is_whitespace) {
+                    return Err(err(ErrorKind::BadLegendEntry(content.to_owned())))
+                }
+
+                let kind = number(rest.trim()).map_err(err)?;
+
+                if legend.insert(c, kind).is_some() {
+                    return Err(err(ErrorKind::DuplicateLegendChar(c)))
+                }
+                continue
+            }
+        }
+
+        let mut words = content.split_whitespace();
+        let keyword = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+
+        macro_rules! args {
+            ($keyword: literal, $count: literal) => ({
+                if args.len() != $count {
+                    return Err(err(ErrorKind::WrongArgumentCount {
+                        keyword: $keyword,
+                        expected: $count,
+                        found: args.len(),
+                    }))
+                }
+            })
+        }
+
+        macro_rules! once {
+            ($slot: ident, $keyword: literal, $value: expr) => ({
+                if $slot.is_some() {
+                    return Err(err(ErrorKind::Duplicate($keyword)))
+                }
+                $slot = Some($value);
+            })
+        }
+
+        macro_rules! position {
+            ($slot: ident, $keyword: literal) => ({
+                args!($keyword, 2);
+                once!($slot, $keyword, (number(args[0]).map_err(err)?, number(args[1]).map_err(err)?, line));
+            })
+        }
+
+        macro_rules! lock {
+            ($keyword: literal) => ({
+                match &mut lock {
+                    Some(lock) => lock,
+                    None => return Err(err(ErrorKind::OutsideLock($keyword))),
+                }
+            })
+        }
+
+        macro_rules! reward {
+            ($keyword: literal, $reward: expr) => ({
+                let reward = $reward;
+                let lock = lock!($keyword);
+                if lock.reward.is_some() {
+                    return Err(err(ErrorKind::Duplicate("reward")))
+                }
+                lock.reward = Some(reward);
+            })
+        }
+
+        macro_rules! placement {
+            ($list: ident, $keyword: literal) => ({
+                args!($keyword, 3);
+                $list.push(Placement {
+                    x: number(args[0]).map_err(err)?,
+                    y: number(args[1]).map_err(err)?,
+                    name: args[2].to_owned(),
+                    line,
+                });
+            })
+        }
+
+        match keyword {
+            "size" => {
+                args!("size", 2);
+                once!(size, "size", (number(args[0]).map_err(err)?, number(args[1]).map_err(err)?));
+            },
+            "loop_length" => {
+                args!("loop_length", 1);
+                once!(loop_length, "loop_length", number(args[0]).map_err(err)?);
+            },
+            "player" => position!(player, "player"),
+            "special_grave" => position!(special_grave, "special_grave"),
+            "dialogue" => {
+                args!("dialogue", 1);
+                once!(dialogue, "dialogue", Reference { name: args[0].to_owned(), line });
+            },
+            "lock" => {
+                args!("lock", 1);
+                if let Some(finished) = lock.take() {
+                    locks.push(finished.finish()?);
+                }
+                lock = Some(PartialLock {
+                    length: number(args[0]).map_err(err)?,
+                    buttons: Vec::new(),
+                    reward: None,
+                    line,
+                });
+            },
+            "button" => {
+                let buttons = &mut lock!("button").buttons;
+                placement!(buttons, "button");
+            },
+            "key" => {
+                args!("key", 4);
+                reward!("key", Reward::Key {
+                    x: number(args[0]).map_err(err)?,
+                    y: number(args[1]).map_err(err)?,
+                    door_x: number(args[2]).map_err(err)?,
+                    door_y: number(args[3]).map_err(err)?,
+                });
+            },
+            "open_door" => {
+                args!("open_door", 2);
+                reward!("open_door", Reward::OpenDoor(number(args[0]).map_err(err)?, number(args[1]).map_err(err)?));
+            },
+            "mob" => placement!(mobs, "mob"),
+            "exit" => {
+                args!("exit", 5);
+                exits.push(Exit {
+                    trigger: Trigger::At(number(args[0]).map_err(err)?, number(args[1]).map_err(err)?),
+                    map: number(args[2]).map_err(err)?,
+                    x: number(args[3]).map_err(err)?,
+                    y: number(args[4]).map_err(err)?,
+                    line,
+                });
+            },
+            "edge" => {
+                args!("edge", 4);
+                let side = match args[0] {
+                    "top" => Side::Top,
+                    "bottom" => Side::Bottom,
+                    "left" => Side::Left,
+                    "right" => Side::Right,
+                    s => return Err(err(ErrorKind::BadSide(s.to_owned()))),
+                };
+                exits.push(Exit {
+                    trigger: Trigger::Edge(side),
+                    map: number(args[1]).map_err(err)?,
+                    x: number(args[2]).map_err(err)?,
+                    y: number(args[3]).map_err(err)?,
+                    line,
+                });
+            },
+            "tile" => {
+                let Some((kind, properties)) = args.split_first() else {
+                    return Err(err(ErrorKind::WrongArgumentCount { keyword: "tile", expected: 1, found: 0 }))
+                };
+                let kind = number(kind).map_err(err)?;
+
+                if tile_properties.iter().any(|t| t.kind == kind) {
+                    return Err(err(ErrorKind::DuplicateTile(kind)))
+                }
+
+                tile_properties.push(TileProperties {
+                    kind,
+                    properties: properties.iter().map(|&p| p.to_owned()).collect(),
+                    line,
+                });
+            },
+            "legend" => {
+                args!("legend", 0);
+                section = Section::Legend;
+            },
+            "tiles" => {
+                args!("tiles", 0);
+                section = Section::Tiles;
+            },
+            _ => return Err(err(ErrorKind::UnknownKeyword(keyword.to_owned()))),
+        }
+    }
+
+    let missing = |keyword| Error { line: None, kind: ErrorKind::Missing(keyword) };
+
+    let (width, height) = size.ok_or_else(|| missing("size"))?;
+    let loop_length = loop_length.ok_or_else(|| missing("loop_length"))?;
+    let player = player.ok_or_else(|| missing("player"))?;
+    if let Some(finished) = lock {
+        locks.push(finished.finish()?);
+    }
+    let special_grave = special_grave.ok_or_else(|| missing("special_grave"))?;
+
+    if !matches!(section, Section::Tiles) {
+        return Err(missing("tiles"))
+    }
+
+    if row_count != height as usize {
+        return Err(Error {
+            line: None,
+            kind: ErrorKind::WrongRowCount { expected: height as usize, found: row_count },
+        })
+    }
+
+    let rewards = locks.iter().flat_map(|lock| match lock.reward {
+        Reward::Key { x, y, door_x, door_y } => vec![(x, y, lock.line), (door_x, door_y, lock.line)],
+        Reward::OpenDoor(x, y) => vec![(x, y, lock.line)],
+    });
+
+    let positions = [player, special_grave].into_iter()
+        .chain(locks.iter().flat_map(|lock| &lock.buttons).chain(&mobs).map(|p| (p.x, p.y, p.line)))
+        .chain(rewards)
+        .chain(exits.iter().filter_map(|exit| match exit.trigger {
+            Trigger::At(x, y) => Some((x, y, exit.line)),
+            Trigger::Edge(_) => None,
+        }));
+
+    for (x, y, line) in positions {
+        if x >= width || y >= height {
+            return Err(Error { line: Some(line), kind: ErrorKind::OffTheMap { x, y } })
+        }
+    }
+
+    let xy = |(x, y, _)| (x, y);
+
+    Ok(Description {
+        width,
+        height,
+        tiles,
+        loop_length,
+        player: xy(player),
+        special_grave: xy(special_grave),
+        dialogue,
+        locks,
+        mobs,
+        exits,
+        tile_properties,
+    })
+}
+
+fn number(s: &str) -> Result<u32, ErrorKind> {
+    s.parse().map_err(|_| ErrorKind::BadNumber(s.to_owned()))
+}
+
+/// The characters `write` uses in the legend, in the order they get used.
+const LEGEND_CHARS: &str = ".,:;'\"`~-_=+*^<>|/\\!?@$%&()[]{}0123456789\
+abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+fn side_name(side: Side) -> &'static str {
+    match side {
+        Side::Top => "top",
+        Side::Bottom => "bottom",
+        Side::Left => "left",
+        Side::Right => "right",
+    }
+}
+
+fn placement_line(keyword: &str, placement: &Placement) -> String {
+    format!("{keyword} {} {} {}", placement.x, placement.y, placement.name)
+}
+
+fn reward_line(reward: Reward) -> String {
+    match reward {
+        Reward::Key { x, y, door_x, door_y } => format!("key {x} {y} {door_x} {door_y}"),
+        Reward::OpenDoor(x, y) => format!("open_door {x} {y}"),
+    }
+}
+
+fn exit_line(exit: &Exit) -> String {
+    match exit.trigger {
+        Trigger::At(x, y) => format!("exit {x} {y} {} {} {}", exit.map, exit.x, exit.y),
+        Trigger::Edge(side) => format!("edge {} {} {} {}", side_name(side), exit.map, exit.x, exit.y),
+    }
+}
+
+fn tile_line(tile: &TileProperties) -> String {
+    let mut line = format!("tile {}", tile.kind);
+    for property in &tile.properties {
+        line.push(' ');
+        line.push_str(property);
+    }
+    line
+}
+
+/// Writes the description in the format that `parse` reads. The legend uses
+/// a character for each tile kind that appears, in order of kind, so the same
+/// kinds always get the same characters. The line numbers in the description
+/// are ignored.
+pub fn write(d: &Description) -> Result<String, Error> {
+    use std::fmt::Write;
+
+    let mut kinds: Vec<Kind> = d.tiles.clone();
+    kinds.sort_unstable();
+    kinds.dedup();
+
+    if kinds.len() > LEGEND_CHARS.chars().count() {
+        return Err(Error { line: None, kind: ErrorKind::TooManyKinds { found: kinds.len() } })
+    }
+
+    let legend: HashMap<Kind, char> = kinds.iter().copied().zip(LEGEND_CHARS.chars()).collect();
+
+    let mut text = String::with_capacity(d.tiles.len() + d.height as usize + 1024);
+
+    // Writing to a `String` cannot fail.
+    let _ = writeln!(text, "size {} {}", d.width, d.height);
+    let _ = writeln!(text, "loop_length {}", d.loop_length);
+    let _ = writeln!(text, "player {} {}", d.player.0, d.player.1);
+    let _ = writeln!(text, "special_grave {} {}", d.special_grave.0, d.special_grave.1);
+    if let Some(dialogue) = &d.dialogue {
+        let _ = writeln!(text, "dialogue {}", dialogue.name);
+    }
+
+    for lock in &d.locks {
+        let _ = writeln!(text, "\nlock {}", lock.length);
+        for button in &lock.buttons {
+            let _ = writeln!(text, "{}", placement_line("button", button));
+        }
+        let _ = writeln!(text, "{}", reward_line(lock.reward));
+    }
+
+    if !d.mobs.is_empty() {
+        text.push('\n');
+    }
+    for mob in &d.mobs {
+        let _ = writeln!(text, "{}", placement_line("mob", mob));
+    }
+
+    if !d.exits.is_empty() {
+        text.push('\n');
+    }
+    for exit in &d.exits {
+        let _ = writeln!(text, "{}", exit_line(exit));
+    }
+
+    if !d.tile_properties.is_empty() {
+        text.push('\n');
+    }
+    for tile in &d.tile_properties {
+        let _ = writeln!(text, "{}", tile_line(tile));
+    }
+
+    let _ = writeln!(text, "\nlegend");
+    for kind in &kinds {
+        let _ = writeln!(text, "{} {kind}", legend[kind]);
+    }
+
+    let _ = writeln!(text, "tiles");
+    for row in d.tiles.chunks(d.width.max(1) as usize) {
+        text.extend(row.iter().map(|kind| legend[kind]));
+        text.push('\n');
+    }
+
+    Ok(text)
+}
+
+/// Like `write`, but keeps the comments, blank lines, legend and order of
+/// `original`, which should be the text the description was parsed from before
+/// it changed. The buttons of each lock and the mobs are written onto the lines
+/// that held them before, in order. Lines left over are dropped, and any extras
+/// go after the last of those lines. The legend gets a new entry for each new
+/// tile kind. If `original` does not parse, or has a different number of locks,
+/// exits or `tile` lines than the description, this is the same as `write`.
+pub fn rewrite(original: &str, d: &Description) -> Result<String, Error> {
+    let Ok(old) = parse(original) else {
+        return write(d)
+    };
+
+    if old.locks.len() != d.locks.len()
+    || old.exits.len() != d.exits.len()
+    || old.tile_properties.len() != d.tile_properties.len() {
+        return write(d)
+    }
+
+    fn push_line(text: &mut String, line: &str) {
+        text.push_str(line);
+        text.push('\n');
+    }
+
+    let mut text = String::with_capacity(original.len());
+    let mut legend: Vec<(char, Kind)> = Vec::new();
+    let mut locks_seen = 0;
+    let mut buttons_seen = 0;
+    let mut mobs_seen = 0;
+    let mut exits_seen = 0;
+    let mut tiles_seen = 0;
+
+    let mut section = Section::Header;
+
+    for (i, raw_line) in original.lines().enumerate() {
+        let line = i + 1;
+
+        let (content, comment) = match raw_line.split_once('#') {
+            Some((content, comment)) => (content.trim(), Some(comment)),
+            None => (raw_line.trim(), None),
+        };
+
+        match section {
+            // The rows are written from the description, after the `tiles` line.
+            Section::Tiles => continue,
+            Section::Legend if content != "tiles" => {
+                let mut chars = content.chars();
+                if let (Some(c), Ok(kind)) = (chars.next(), number(chars.as_str().trim())) {
+                    legend.push((c, kind));
+                }
+                push_line(&mut text, raw_line);
+                continue
+            },
+            _ => {},
+        }
+
+        if content.is_empty() {
+            push_line(&mut text, raw_line);
+            continue
+        }
+
+        // `parse` checked that these come after a `lock` line.
+        let lock = locks_seen.max(1) - 1;
+
+        let written = match content.split_whitespace().next().unwrap_or_default() {
+            "size" => Some(format!("size {} {}", d.width, d.height)),
+            "loop_length" => Some(format!("loop_length {}", d.loop_length)),
+            "player" => Some(format!("player {} {}", d.player.0, d.player.1)),
+            "special_grave" => Some(format!("special_grave {} {}", d.special_grave.0, d.special_grave.1)),
+            "dialogue" => d.dialogue.as_ref().map(|dialogue| format!("dialogue {}", dialogue.name)),
+            "lock" => {
+                locks_seen += 1;
+                buttons_seen = 0;
+                Some(format!("lock {}", d.locks[locks_seen - 1].length))
+            },
+            "button" => {
+                buttons_seen += 1;
+                d.locks[lock].buttons.get(buttons_seen - 1).map(|button| placement_line("button", button))
+            },
+            "key" | "open_door" => Some(reward_line(d.locks[lock].reward)),
+            "mob" => {
+                mobs_seen += 1;
+                d.mobs.get(mobs_seen - 1).map(|mob| placement_line("mob", mob))
+            },
+            "exit" | "edge" => {
+                exits_seen += 1;
+                Some(exit_line(&d.exits[exits_seen - 1]))
+            },
+            "tile" => {
+                tiles_seen += 1;
+                Some(tile_line(&d.tile_properties[tiles_seen - 1]))
+            },
+            "legend" => {
+                if old.mobs.is_empty() && !d.mobs.is_empty() {
+                    for mob in &d.mobs {
+                        push_line(&mut text, &placement_line("mob", mob));
+                    }
+                    text.push('\n');
+                }
+                section = Section::Legend;
+                Some(content.to_owned())
+            },
+            "tiles" => {
+                let mut kinds: Vec<Kind> = d.tiles.clone();
+                kinds.sort_unstable();
+                kinds.dedup();
+
+                let mut unused = LEGEND_CHARS.chars().filter(|&c| legend.iter().all(|&(used, _)| used != c));
+                let mut chars = HashMap::new();
+                for &kind in &kinds {
+                    if let Some(&(c, _)) = legend.iter().find(|&&(_, known)| known == kind) {
+                        chars.insert(kind, c);
+                        continue
+                    }
+
+                    let Some(c) = unused.next() else {
+                        return Err(Error { line: None, kind: ErrorKind::TooManyKinds { found: kinds.len() } })
+                    };
+                    push_line(&mut text, &format!("{c} {kind}"));
+                    chars.insert(kind, c);
+                }
+
+                push_line(&mut text, raw_line);
+                for row in d.tiles.chunks(d.width.max(1) as usize) {
+                    text.extend(row.iter().map(|kind| chars[kind]));
+                    text.push('\n');
+                }
+
+                section = Section::Tiles;
+                continue
+            },
+            _ => Some(content.to_owned()),
+        };
+
+        if let Some(mut written) = written {
+            if let Some(comment) = comment {
+                written.push_str(" #");
+                written.push_str(comment);
+            }
+            push_line(&mut text, &written);
+        }
+
+        if old.dialogue.is_none() && content.starts_with("special_grave") {
+            if let Some(dialogue) = &d.dialogue {
+                push_line(&mut text, &format!("dialogue {}", dialogue.name));
+            }
+        }
+
+        if locks_seen > 0 {
+            let old_lock = &old.locks[locks_seen - 1];
+            if line == old_lock.buttons.last().map_or(old_lock.line, |button| button.line) {
+                for button in d.locks[locks_seen - 1].buttons.iter().skip(old_lock.buttons.len()) {
+                    push_line(&mut text, &placement_line("button", button));
+                }
+            }
+        }
+
+        if old.mobs.last().is_some_and(|mob| mob.line == line) {
+            for mob in d.mobs.iter().skip(old.mobs.len()) {
+                push_line(&mut text, &placement_line("mob", mob));
+            }
+        }
+    }
+
+    Ok(text)
+}
+
+#[cfg(test)]
+mod rewrite_works {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+# A tiny map
+size 3 2
+loop_length 600
+player 1 0 # By the wall
+special_grave 2 1
+
+# The only lock.
+lock 1
+button 0 1 north
+key 2 0 0 0
+
+mob 1 1 turtle # Slow and steady
+mob 2 1 dog
+
+legend
+. 15
+# Walls
+| 14
+tiles
+|.|
+...
+";
+
+    #[test]
+    fn on_an_unchanged_map() {
+        let text = include_str!("../../maps/src/map.txt");
+
+        assert_eq!(rewrite(text, &parse(text).unwrap()).unwrap(), text);
+    }
+
+    #[test]
+    fn on_a_changed_map() {
+        let mut description = parse(EXAMPLE).unwrap();
+        description.player = (0, 0);
+        description.mobs.pop();
+        description.locks[0].buttons.push(Placement { x: 1, y: 1, name: "south".to_owned(), line: 0 });
+        description.tiles[4] = 84;
+
+        let text = rewrite(EXAMPLE, &description).unwrap();
+
+        assert_eq!(text, "\
+# A tiny map
+size 3 2
+loop_length 600
+player 0 0 # By the wall
+special_grave 2 1
+
+# The only lock.
+lock 1
+button 0 1 north
+button 1 1 south
+key 2 0 0 0
+
+mob 1 1 turtle # Slow and steady
+
+legend
+. 15
+# Walls
+| 14
+, 84
+tiles
+|.|
+.,.
+");
+        assert_eq!(write(&parse(&text).unwrap()), write(&description));
+    }
+}
+
+#[cfg(test)]
+mod write_works {
+    use super::*;
+
+    #[test]
+    fn on_a_parsed_map() {
+        let description = parse(include_str!("../../maps/src/map.txt")).unwrap();
+
+        let text = write(&description).unwrap();
+        let reparsed = parse(&text).unwrap();
+
+        assert_eq!(reparsed.tiles, description.tiles);
+        assert_eq!(reparsed.locks.len(), description.locks.len());
+        assert_eq!(reparsed.mobs.len(), description.mobs.len());
+        // Line numbers aside, writing it again gives the same text.
+        assert_eq!(write(&reparsed).unwrap(), text);
+    }
+
+    #[test]
+    fn on_every_legend_char() {
+        let mut description = parse(include_str!("../../maps/src/map.txt")).unwrap();
+        let available = LEGEND_CHARS.chars().count();
+
+        for (i, tile) in description.tiles.iter_mut().enumerate() {
+            *tile = (i % available) as Kind;
+        }
+
+        let reparsed = parse(&write(&description).unwrap()).unwrap();
+        assert_eq!(reparsed.tiles, description.tiles);
+
+        description.tiles[0] = available as Kind;
+        assert_eq!(
+            write(&description).unwrap_err().kind,
+            ErrorKind::TooManyKinds { found: available + 1 },
+        );
+    }
+}
+
+#[cfg(test)]
+mod parse_works {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+# A tiny map
+size 3 2
+loop_length 600
+player 1 0
+special_grave 2 1
+lock 1
+button 0 1 north
+key 2 0 0 0
+mob 1 1 turtle # Slow and steady
+exit 0 1 1 4 4
+edge left 2 9 0
+tile 14 opaque name=hedge
+
+legend
+. 15
+# Walls
+| 14
+tiles
+|.|
+...
+";
+
+    #[test]
+    fn on_this_example() {
+        let description = parse(EXAMPLE).unwrap();
+
+        assert_eq!(description.tiles, [14, 15, 14, 15, 15, 15]);
+        assert_eq!(description.player, (1, 0));
+        assert_eq!(description.special_grave, (2, 1));
+        assert_eq!(description.dialogue, None);
+        assert_eq!(
+            description.mobs,
+            [Placement { x: 1, y: 1, name: "turtle".to_owned(), line: 9 }]
+        );
+        assert_eq!(description.locks[0].buttons[0].name, "north");
+        assert_eq!(description.locks[0].reward, Reward::Key { x: 2, y: 0, door_x: 0, door_y: 0 });
+        assert_eq!(description.exits[1].trigger, Trigger::Edge(Side::Left));
+        assert_eq!((description.exits[1].map, description.exits[1].x), (2, 9));
+        assert_eq!(description.tile_properties[0].kind, 14);
+        assert_eq!(description.tile_properties[0].properties, ["opaque", "name=hedge"]);
+    }
+
+    #[test]
+    fn on_these_mistakes() {
+        let err = |line, kind| Err(Error { line, kind });
+
+        assert_eq!(
+            parse(&EXAMPLE.replace("|.|", "|x|")),
+            err(Some(19), ErrorKind::UnknownTileChar { c: 'x', column: 2 })
+        );
+        assert_eq!(
+            parse(&EXAMPLE.replace("mob 1 1", "mob 1 5")),
+            err(Some(9), ErrorKind::OffTheMap { x: 1, y: 5 })
+        );
+        assert_eq!(
+            parse(&EXAMPLE.replace("edge left", "edge west")),
+            err(Some(11), ErrorKind::BadSide("west".to_owned()))
+        );
+        assert_eq!(
+            parse(&EXAMPLE.replace("player 1 0\n", "")),
+            err(None, ErrorKind::Missing("player"))
+        );
+        assert_eq!(
+            parse(&EXAMPLE.replace("...\n", "")),
+            err(None, ErrorKind::WrongRowCount { expected: 2, found: 1 })
+        );
+        assert_eq!(
+            parse(&EXAMPLE.replace("key 2 0 0 0", "key 2 0 0")),
+            err(Some(8), ErrorKind::WrongArgumentCount { keyword: "key", expected: 4, found: 3 })
+        );
+        assert_eq!(
+            parse(&EXAMPLE.replace("lock 1", "# No lock")),
+            err(Some(7), ErrorKind::OutsideLock("button"))
+        );
+        assert_eq!(
+            parse(&EXAMPLE.replace("lock 1", "lock 2")),
+            err(Some(6), ErrorKind::TooFewButtons { length: 2, found: 1 })
+        );
+        assert_eq!(
+            parse(&EXAMPLE.replace("key 2 0 0 0", "# No reward")),
+            err(Some(6), ErrorKind::NoReward)
+        );
+        assert_eq!(
+            parse(&EXAMPLE.replace("name=hedge", "name=hedge\ntile 14")),
+            err(Some(13), ErrorKind::DuplicateTile(14))
+        );
+        assert_eq!(
+            parse(&EXAMPLE.replace("special_grave 2 1", "special_grave 2 1\ndialogue a\ndialogue b")),
+            err(Some(7), ErrorKind::Duplicate("dialogue"))
+        );
+    }
+}
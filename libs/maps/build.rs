@@ -0,0 +1,177 @@
+//! Turns the built-in maps' text files into Rust code, so they can be `static`s.
+
+use std::fmt::Write;
+
+use models::properties;
+
+mod mobs {
+    include!("src/mob_names.rs");
+}
+
+mod dialogue {
+    include!("src/dialogue_names.rs");
+}
+
+/// Each map's text file, and the name of the file generated from it.
+const MAPS: [(&str, &str); 2] = [
+    ("src/map.txt", "map.in"),
+    ("src/crypt.txt", "crypt.in"),
+];
+
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").expect("Cargo sets OUT_DIR for build scripts");
+
+    println!("cargo:rerun-if-changed=src/mob_names.rs");
+    println!("cargo:rerun-if-changed=src/dialogue_names.rs");
+
+    for (path, generated) in MAPS {
+        println!("cargo:rerun-if-changed={path}");
+
+        let text = std::fs::read_to_string(path)
+            .unwrap_or_else(|err| panic!("Could not read {path}: {err}"));
+
+        let description = map_format::parse(&text)
+            .unwrap_or_else(|err| panic!("{path}: {err}"));
+
+        std::fs::write(format!("{out_dir}/{generated}"), map_code(path, &description))
+            .unwrap_or_else(|err| panic!("Could not write the generated map: {err}"));
+    }
+}
+
+/// A `Map` expression, without any timeline, since that cannot be described in
+/// the text format.
+fn map_code(path: &str, d: &map_format::Description) -> String {
+    let mut code = String::with_capacity(d.tiles.len() * 5);
+
+    let _ = writeln!(code, "// Generated from {path} by build.rs. Edit that instead.");
+    let _ = writeln!(code, "Map {{");
+    let _ = writeln!(code, "    width: xy::w({}),", d.width);
+    let _ = writeln!(code, "    height: xy::h({}),", d.height);
+    let _ = writeln!(code, "    player_x: xy::x({}),", d.player.0);
+    let _ = writeln!(code, "    player_y: xy::y({}),", d.player.1);
+
+    let _ = writeln!(code, "    mobs: &[");
+    for mob in &d.mobs {
+        if !mobs::NAMES.contains(&mob.name.as_str()) {
+            panic!("{path}: line {}: \"{}\" is not a mob name", mob.line, mob.name);
+        }
+
+        let _ = writeln!(
+            code,
+            "        (xy::x({}), xy::y({}), mobs::{}),",
+            mob.x,
+            mob.y,
+            mob.name.to_ascii_uppercase(),
+        );
+    }
+    let _ = writeln!(code, "    ],");
+
+    let _ = writeln!(code, "    locks: &[");
+    for lock in &d.locks {
+        // `locks::ButtonIndex` is a `u8`, as checked by `maps::load` too.
+        if lock.buttons.len() > usize::from(u8::MAX) + 1 {
+            panic!("{path}: line {}: A lock can have at most 256 buttons, not {}", lock.line, lock.buttons.len());
+        }
+
+        let _ = writeln!(code, "        locks::Lock {{");
+        let _ = writeln!(code, "            buttons: &[");
+        for button in &lock.buttons {
+            let _ = writeln!(
+                code,
+                "                locks::Button {{ x: xy::x({}), y: xy::y({}), name: {:?} }},",
+                button.x,
+                button.y,
+                button.name,
+            );
+        }
+        let _ = writeln!(code, "            ],");
+        let _ = writeln!(code, "            length: {},", lock.length);
+
+        let reward = match lock.reward {
+            map_format::Reward::Key { x, y, door_x, door_y } => format!(
+                "locks::Reward::Key {{ x: xy::x({x}), y: xy::y({y}), door_x: xy::x({door_x}), door_y: xy::y({door_y}) }}"
+            ),
+            map_format::Reward::OpenDoor(x, y) => format!(
+                "locks::Reward::Action(timeline::Action::OpenDoor {{ x: xy::x({x}), y: xy::y({y}) }})"
+            ),
+        };
+        let _ = writeln!(code, "            reward: {reward},");
+        let _ = writeln!(code, "        }},");
+    }
+    let _ = writeln!(code, "    ],");
+
+    let _ = writeln!(code, "    special_grave_x: xy::x({}),", d.special_grave.0);
+    let _ = writeln!(code, "    special_grave_y: xy::y({}),", d.special_grave.1);
+    let _ = writeln!(code, "    loop_length: {},", d.loop_length);
+    let _ = writeln!(code, "    timeline: &[],");
+    match &d.dialogue {
+        Some(reference) => {
+            if !dialogue::NAMES.contains(&reference.name.as_str()) {
+                panic!("{path}: line {}: \"{}\" is not a dialogue name", reference.line, reference.name);
+            }
+
+            let _ = writeln!(code, "    dialogue: {}_DIALOGUE,", reference.name.to_ascii_uppercase());
+        },
+        None => {
+            let _ = writeln!(code, "    dialogue: dialogue::Dialogue::EMPTY,");
+        },
+    }
+
+    let _ = writeln!(code, "    exits: &[");
+    for exit in &d.exits {
+        let trigger = match exit.trigger {
+            map_format::Trigger::At(x, y) => format!("exits::Trigger::At(xy::x({x}), xy::y({y}))"),
+            map_format::Trigger::Edge(side) => format!("exits::Trigger::Edge(exits::Side::{side:?})"),
+        };
+
+        let _ = writeln!(
+            code,
+            "        exits::Exit {{ trigger: {trigger}, map: {}, x: xy::x({}), y: xy::y({}) }},",
+            exit.map,
+            exit.x,
+            exit.y,
+        );
+    }
+    let _ = writeln!(code, "    ],");
+
+    let _ = writeln!(code, "    tile_properties: &[");
+    for tile in &d.tile_properties {
+        let mut flags = Vec::new();
+        let mut name = None;
+        let mut depth = format!("properties::of({}).depth", tile.kind);
+        for property in &tile.properties {
+            if let Some(property_name) = property.strip_prefix("name=") {
+                name = Some(property_name.replace('_', " "));
+            } else if let Some(found) = property.strip_prefix("depth=")
+                .and_then(properties::depth_by_name) {
+                depth = format!("properties::Depth::{found:?}");
+            } else if properties::flag_by_name(property).is_some() {
+                flags.push(format!("properties::{}", property.to_ascii_uppercase()));
+            } else {
+                panic!("{path}: line {}: \"{property}\" is not a tile property", tile.line);
+            }
+        }
+
+        let flags = if flags.is_empty() { "0".to_owned() } else { flags.join(" | ") };
+
+        let _ = writeln!(
+            code,
+            "        ({}, properties::Properties {{ flags: {flags}, name: {name:?}, depth: {depth} }}),",
+            tile.kind,
+        );
+    }
+    let _ = writeln!(code, "    ],");
+
+    let _ = write!(code, "    tiles: &[");
+    for (i, kind) in d.tiles.iter().enumerate() {
+        if i % d.width as usize == 0 {
+            let _ = write!(code, "\n        ");
+        }
+        let _ = write!(code, "{kind},");
+    }
+    let _ = writeln!(code, "\n    ],");
+
+    let _ = writeln!(code, "}}");
+
+    code
+}
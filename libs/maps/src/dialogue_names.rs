@@ -0,0 +1,7 @@
+// Shared with the build script, so it can check the built-in maps' dialogue.
+
+/// The names used for dialogue in map files. These are the names of the
+/// `<NAME>_DIALOGUE` constants in `maps`, in lower case.
+pub const NAMES: [&str; 1] = [
+    "graveyard",
+];
@@ -0,0 +1,13 @@
+// Shared with the build script, so it can check the built-in maps' mob names.
+
+/// The names used for mobs in map files. These are the names of the constants
+/// in `mobs`, in lower case.
+pub const NAMES: [&str; 7] = [
+    "turtle",
+    "crab",
+    "ghost",
+    "large_pot",
+    "panoptikhan",
+    "zombie",
+    "dog",
+];
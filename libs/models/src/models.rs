@@ -2,6 +2,14 @@ use platform_types::unscaled;
 
 pub type TileKind = u16;
 
+/// 65536 distinct frames ought to be enough for anybody! Unless a map wants
+/// a loop longer than about 18 minutes, in which case the `wide-frame-count`
+/// feature can be turned on.
+#[cfg(not(feature = "wide-frame-count"))]
+pub type FrameCount = u16;
+#[cfg(feature = "wide-frame-count")]
+pub type FrameCount = u32;
+
 pub mod tile {
     use super::*;
 
@@ -51,6 +59,7 @@ pub mod tile {
     pub const CRAB: TileKind = 21;
     pub const GHOST_1: TileKind = 22;
     //pub const GHOST_2: TileKind = 23;
+    pub const ECHO: TileKind = 23; // The player from the previous loop. Uses the second ghost graphic.
     pub const TURTLE: TileKind = 24;
     pub const LARGE_POT: TileKind = 36;
     //pub const SMALL_POT: TileKind = 37;
@@ -86,28 +95,226 @@ pub mod tile {
     ];
 }
 
+/// What each kind of tile does, so a new kind of tile only needs a row in
+/// `TABLE`, instead of a new arm in every `match` on tile kinds. Maps can
+/// override these, so the game should ask the map rather than use `of`
+/// directly.
+pub mod properties {
+    use super::*;
+
+    pub type Flags = u8;
+
+    /// Entities can move onto it.
+    pub const WALKABLE: Flags = 0x01;
+    /// Moving into it pushes it along, if there is room past it.
+    pub const PUSHABLE: Flags = 0x02;
+    /// Worth a dog going over to sniff at.
+    pub const LANDMARK: Flags = 0x04;
+    /// Does something when the player interacts with it.
+    pub const INTERACTABLE: Flags = 0x08;
+    /// Cannot be seen through.
+    pub const OPAQUE: Flags = 0x10;
+    /// Stops even the things that pass through everything else.
+    pub const BLOCKS_GHOSTS: Flags = 0x20;
+
+    /// The names used for the flags in map files.
+    pub const FLAG_NAMES: [(&str, Flags); 6] = [
+        ("walkable", WALKABLE),
+        ("pushable", PUSHABLE),
+        ("landmark", LANDMARK),
+        ("interactable", INTERACTABLE),
+        ("opaque", OPAQUE),
+        ("blocks_ghosts", BLOCKS_GHOSTS),
+    ];
+
+    /// Where an entity of a kind sits among the others on its tile. Each tile
+    /// holds at most one entity at each depth, and they are drawn, and bumped
+    /// into, from `Ground` up.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub enum Depth {
+        /// Covers the map's own tile, instead of sitting on it.
+        Ground,
+        /// Small things lying around.
+        Item,
+        /// Things that take up the tile.
+        #[default]
+        Object,
+        /// Shown above everything else.
+        Effect,
+    }
+
+    pub const DEPTH_COUNT: usize = 4;
+
+    impl Depth {
+        pub const ALL: [Depth; DEPTH_COUNT] = [
+            Depth::Ground,
+            Depth::Item,
+            Depth::Object,
+            Depth::Effect,
+        ];
+
+        pub const fn index(self) -> usize {
+            self as usize
+        }
+    }
+
+    /// The names used for the depths in map files.
+    pub const DEPTH_NAMES: [(&str, Depth); DEPTH_COUNT] = [
+        ("ground", Depth::Ground),
+        ("item", Depth::Item),
+        ("object", Depth::Object),
+        ("effect", Depth::Effect),
+    ];
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct Properties {
+        pub flags: Flags,
+        pub name: Option<&'static str>,
+        pub depth: Depth,
+    }
+
+    impl Properties {
+        /// Whether all of the given flags are set.
+        pub const fn has(self, flags: Flags) -> bool {
+            self.flags & flags == flags
+        }
+
+        pub const fn at(self, depth: Depth) -> Properties {
+            Properties { depth, ..self }
+        }
+    }
+
+    /// The properties of any kind without a row in `TABLE`. That includes the
+    /// creatures, which are only ever entities.
+    pub const UNLISTED: Properties = Properties {
+        flags: LANDMARK,
+        name: None,
+        depth: Depth::Object,
+    };
+
+    const fn row(flags: Flags, name: &'static str) -> Properties {
+        Properties { flags, name: Some(name), depth: Depth::Object }
+    }
+
+    const WALL: Properties = row(OPAQUE, "wall");
+    const OPEN_DOOR: Properties = row(WALKABLE, "open door").at(Depth::Ground);
+
+    const ROWS: &[(TileKind, Properties)] = &[
+        (tile::WALL_0, WALL),
+        (tile::WALL_1, WALL),
+        (tile::WALL_2, WALL),
+        (tile::WALL_3, WALL),
+        (tile::WALL_4, WALL),
+        (tile::WALL_5, WALL),
+        (tile::WALL_6, WALL),
+        (tile::WALL_7, WALL),
+        (tile::WALL_8, WALL),
+        (tile::WALL_9, WALL),
+        (tile::WALL_10, WALL),
+        (tile::WALL_11, WALL),
+        (tile::WALL_12, WALL),
+        (tile::WALL_13, WALL),
+        (tile::WALL_14, WALL),
+        (tile::WALL_15, WALL),
+        (tile::WALL_16, WALL),
+        (tile::WALL_17, WALL),
+        (tile::WALL_18, WALL),
+        (tile::WALL_19, WALL),
+        (tile::WALL_20, WALL),
+        (tile::WALL_21, WALL),
+        (tile::WALL_22, WALL),
+        (tile::WALL_23, WALL),
+        (tile::WALL_24, WALL),
+        (tile::WALL_25, WALL),
+        (tile::WALL_26, WALL),
+        (tile::DOOR_0, OPEN_DOOR),
+        (tile::DOOR_1, OPEN_DOOR),
+        // The one that closes again.
+        (tile::OPEN_DOOR, row(WALKABLE | INTERACTABLE, "open door").at(Depth::Ground)),
+        (tile::DOOR_3, OPEN_DOOR),
+        (tile::DOOR_4, OPEN_DOOR),
+        (tile::CLOSED_DOOR, row(INTERACTABLE | OPAQUE, "closed door").at(Depth::Ground)),
+        (tile::FLOOR, row(WALKABLE, "floor").at(Depth::Ground)),
+        (tile::GROUND, row(WALKABLE, "ground").at(Depth::Ground)),
+        (tile::GRASS_GROUND, row(WALKABLE, "grass").at(Depth::Ground)),
+        (tile::LARGE_POT, row(PUSHABLE | LANDMARK, "large pot")),
+        (tile::STAIRS_DOWN, row(WALKABLE | LANDMARK, "stairs").at(Depth::Ground)),
+        (tile::KEY, row(WALKABLE | LANDMARK, "key").at(Depth::Item)),
+        (tile::BUTTON_LIT, row(WALKABLE | LANDMARK, "button").at(Depth::Ground)),
+        (tile::BUTTON_DARK, row(WALKABLE | LANDMARK, "dark button").at(Depth::Ground)),
+        (tile::BUTTON_PRESSED, row(WALKABLE | LANDMARK, "pressed button").at(Depth::Ground)),
+        (tile::EXCLAMATION_BUBBLE, row(INTERACTABLE, "bubble").at(Depth::Effect)),
+        (tile::GRAVE_1, row(LANDMARK, "grave")),
+        (tile::GRAVE_2, row(LANDMARK, "grave")),
+        (tile::PORTAL, row(WALKABLE | LANDMARK, "portal").at(Depth::Ground)),
+    ];
+
+    /// One for each tile on the graphics sheet. Any kind past that is `UNLISTED`.
+    pub const TABLE_LEN: usize = 256;
+
+    /// The properties of each kind, indexed by kind.
+    pub static TABLE: [Properties; TABLE_LEN] = {
+        let mut table = [UNLISTED; TABLE_LEN];
+
+        let mut i = 0;
+        while i < ROWS.len() {
+            let (kind, properties) = ROWS[i];
+            table[kind as usize] = properties;
+            i += 1;
+        }
+
+        table
+    };
+
+    /// The properties of the kind, before any map overrides them.
+    pub const fn of(kind: TileKind) -> Properties {
+        if (kind as usize) < TABLE_LEN {
+            TABLE[kind as usize]
+        } else {
+            UNLISTED
+        }
+    }
+
+    /// The flag with the given name, from `FLAG_NAMES`.
+    pub fn flag_by_name(name: &str) -> Option<Flags> {
+        FLAG_NAMES.into_iter()
+            .find(|&(flag_name, _)| flag_name == name)
+            .map(|(_, flag)| flag)
+    }
+
+    /// The depth with the given name, from `DEPTH_NAMES`.
+    pub fn depth_by_name(name: &str) -> Option<Depth> {
+        DEPTH_NAMES.into_iter()
+            .find(|&(depth_name, _)| depth_name == name)
+            .map(|(_, depth)| depth)
+    }
+}
+
 // TODO I think this is being used as both world xy and screen xy,
 //      and we should make them distinct types if that becomes an issue
+/// Positions and sizes, in tiles. Every `Inner` is a valid value.
+///
+/// The operators saturate, and are staying that way, since that suits laying
+/// things out on the screen. For moving around the world, use the `checked_`
+/// functions instead, so that going past the edge can be noticed, rather than
+/// turning into staying in place.
 pub mod xy {
     use super::*;
 
-    pub type Inner = u8;
+    pub type Inner = u16;
 
     #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct X(Inner);
 
-    /// Clamps to the valid range
     pub const fn x(x: Inner) -> X {
-        X(if x > MAX_W_INNER { MAX_W_INNER } else { x })
+        X(x)
     }
 
-    pub const MAX_W_INNER: Inner = 0xF0;
-
     #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct W(Inner);
 
     pub const fn w(w: Inner) -> W {
-        W(if w > MAX_W_INNER { MAX_W_INNER } else { w })
+        W(w)
     }
 
     impl core::ops::SubAssign<W> for W {
@@ -127,9 +334,6 @@ pub mod xy {
 
     pub const fn const_add_assign_w(x: &mut X, w: W) {
         x.0 = x.0.saturating_add(w.0);
-        if x.0 > MAX_W_INNER {
-            x.0 = MAX_W_INNER;
-        }
     }
 
     impl core::ops::AddAssign<W> for X {
@@ -176,21 +380,30 @@ pub mod xy {
     }
 
 
+    impl X {
+        /// `None` if the result would not fit.
+        pub fn checked_add(self, w: W) -> Option<X> {
+            self.0.checked_add(w.0).map(X)
+        }
+
+        /// `None` if the result would be negative.
+        pub fn checked_sub(self, w: W) -> Option<X> {
+            self.0.checked_sub(w.0).map(X)
+        }
+    }
+
     #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct Y(Inner);
 
-    pub const MAX_H_INNER: Inner = 0xF0;
-
-    /// Clamps to the valid range
     pub const fn y(y: Inner) -> Y {
-        Y(if y > MAX_H_INNER { MAX_H_INNER } else { y })
+        Y(y)
     }
 
     #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
     pub struct H(Inner);
 
     pub const fn h(h: Inner) -> H {
-        H(if h > MAX_H_INNER { MAX_H_INNER } else { h })
+        H(h)
     }
 
     impl core::ops::SubAssign<H> for H {
@@ -210,9 +423,6 @@ pub mod xy {
 
     pub const fn const_add_assign_h(y: &mut Y, h: H) {
         y.0 = y.0.saturating_add(h.0);
-        if y.0 > MAX_H_INNER {
-            y.0 = MAX_H_INNER;
-        }
     }
 
     impl core::ops::AddAssign<H> for Y {
@@ -258,6 +468,18 @@ pub mod xy {
         }
     }
 
+    impl Y {
+        /// `None` if the result would not fit.
+        pub fn checked_add(self, h: H) -> Option<Y> {
+            self.0.checked_add(h.0).map(Y)
+        }
+
+        /// `None` if the result would be negative.
+        pub fn checked_sub(self, h: H) -> Option<Y> {
+            self.0.checked_sub(h.0).map(Y)
+        }
+    }
+
     macro_rules! shared_impl {
         ($($name: ident)+) => {
             $(
@@ -266,7 +488,7 @@ pub mod xy {
                     pub const ONE: Self = Self(1);
 
                     pub fn get(self) -> unscaled::$name {
-                        unscaled::$name(self.0.into())
+                        unscaled::$name(self.0)
                     }
 
                     pub fn usize(self) -> usize {
@@ -285,6 +507,7 @@ pub mod xy {
         X Y W H
     }
 
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
     pub struct Rect {
         pub min_x: X,
         pub min_y: Y,
@@ -292,6 +515,17 @@ pub mod xy {
         pub max_y: Y,
     }
 
+    impl Rect {
+        /// Counting the max edges as inside, as with the corners of areas in
+        /// map files.
+        pub fn contains(self, x: X, y: Y) -> bool {
+            self.min_x <= x && x <= self.max_x
+            && self.min_y <= y && y <= self.max_y
+        }
+    }
+
+    /// Saturating at the edges, like the operators, so this is for drawing
+    /// things around a position. See `checked_eight_neighbors`.
     pub fn eight_neighbors(x: X, y: Y) -> [(X, Y); 8] {
         let mut output: [(X, Y); 8] = <_>::default();
 
@@ -311,5 +545,17 @@ pub mod xy {
         output
     }
 
+    /// Like `eight_neighbors`, but leaving out those past the edge, instead of
+    /// saturating them onto `x`, `y` itself. In no particular order.
+    pub fn checked_eight_neighbors(x: X, y: Y) -> impl Iterator<Item = (X, Y)> {
+        let xs = [x.checked_sub(W::ONE), Some(x), x.checked_add(W::ONE)];
+        let ys = [y.checked_sub(H::ONE), Some(y), y.checked_add(H::ONE)];
+
+        ys.into_iter()
+            .flat_map(move |ny| xs.into_iter().map(move |nx| (nx, ny)))
+            .filter_map(|(nx, ny)| Some((nx?, ny?)))
+            .filter(move |&neighbor| neighbor != (x, y))
+    }
+
 }
 pub use xy::{X, Y, W, H, Rect};
\ No newline at end of file
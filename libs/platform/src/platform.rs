@@ -53,7 +53,10 @@ pub fn run<S: State + 'static>(mut state: S) {
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 window_id,
-            } if window_id == window.id() => *control_flow = ControlFlow::Exit,
+            } if window_id == window.id() => {
+                state.quit();
+                *control_flow = ControlFlow::Exit
+            },
             Event::WindowEvent {
                 event: WindowEvent::KeyboardInput{
                     input: winit::event::KeyboardInput {
@@ -203,6 +206,10 @@ mod wasm {
                 SFX::CardPlace => "cardPlace",
                 SFX::CardSlide => "cardSlide",
                 SFX::ButtonPress => "buttonPress",
+                // TODO? A dedicated sound for this?
+                SFX::TimeWarning => "cardSlide",
+                // TODO? A dedicated sound for this?
+                SFX::Bump => "cardPlace",
             };
 
             handler.call1(&JsValue::undefined(), &request_string.into()).ok()?;
@@ -334,6 +341,18 @@ mod not_wasm {
                         i_b!("buttonPress2"),
                         i_b!("buttonPress3"),
                     ],
+                    // TODO? A dedicated sound for this?
+                    SFX::TimeWarning => &[
+                        i_b!("cardSlide1"),
+                        i_b!("cardSlide2"),
+                        i_b!("cardSlide3"),
+                    ],
+                    // TODO? A dedicated sound for this?
+                    SFX::Bump => &[
+                        i_b!("cardPlace1"),
+                        i_b!("cardPlace2"),
+                        i_b!("cardPlace3"),
+                    ],
                 };
 
                 let data: &[u8] = sounds[
@@ -987,7 +987,7 @@ pub mod command {
 }
 pub use command::Command;
 
-#[derive(Clone, Copy, Default, Debug)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
 pub struct Input {
     pub gamepad: Button,
     pub previous_gamepad: Button,
@@ -1010,6 +1010,8 @@ pub enum SFX {
     CardPlace,
     CardSlide,
     ButtonPress,
+    TimeWarning,
+    Bump,
 }
 
 pub struct Speaker {
@@ -1064,6 +1066,14 @@ pub mod button {
         pub fn remove(&mut self, other: Self) {
             self.0 &= !other.0;
         }
+
+        pub const fn bits(self) -> u8 {
+            self.0
+        }
+
+        pub const fn from_bits(bits: u8) -> Self {
+            Self(bits)
+        }
     }
 }
 pub use button::Button;
@@ -1078,6 +1088,9 @@ pub trait State {
     fn press(&mut self, button: Button);
 
     fn release(&mut self, button: Button);
+
+    /// Called when the window is about to close.
+    fn quit(&mut self) {}
 }
 
 // reportedly colourblind friendly colours
@@ -12,8 +12,74 @@ mod wasm {
     }
 }
 
+#[cfg(target_arch = "wasm32")]
 fn run() {
     let params = platform::get_state_params();
     let state = app::State::new(params);
     platform::run(state);
-}
\ No newline at end of file
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn run() {
+    #[cfg(not(feature = "editor"))]
+    const USAGE: &str = "Usage: time-loops-and-checking-knowledge [--record <file> | --replay <file> | --save-file <file>]";
+    #[cfg(feature = "editor")]
+    const USAGE: &str = "Usage: time-loops-and-checking-knowledge [--record <file> | --replay <file> | --save-file <file> | --edit <file>]";
+
+    let params = platform::get_state_params();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let state = match args[..] {
+        [] => app::State::new(params),
+        ["--record", path] => {
+            let file = std::fs::File::create(path).unwrap_or_else(|err| {
+                eprintln!("Could not create {path}: {err}");
+                std::process::exit(1)
+            });
+
+            app::State::recording(params, Box::new(std::io::BufWriter::new(file)))
+                .unwrap_or_else(|err| {
+                    eprintln!("Could not write to {path}: {err}");
+                    std::process::exit(1)
+                })
+        },
+        ["--replay", path] => {
+            let replay = std::fs::read(path)
+                .map_err(|err| err.to_string())
+                .and_then(|bytes| {
+                    app::replay::Replay::parse(&bytes).map_err(|err| err.to_string())
+                })
+                .unwrap_or_else(|err| {
+                    eprintln!("Could not load replay {path}: {err}");
+                    std::process::exit(1)
+                });
+
+            app::State::replaying(params, replay)
+        },
+        ["--save-file", path] => {
+            app::State::new(params)
+                .with_save_file(path.into())
+                .unwrap_or_else(|err| {
+                    eprintln!("Could not load save {path}: {err}");
+                    std::process::exit(1)
+                })
+        },
+        #[cfg(feature = "editor")]
+        ["--edit", path] => {
+            app::State::new(params)
+                .with_editor_file(path.into())
+                .unwrap_or_else(|err| {
+                    eprintln!("Could not load map {path}: {err}");
+                    std::process::exit(1)
+                })
+        },
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(2)
+        },
+    };
+
+    platform::run(state);
+}